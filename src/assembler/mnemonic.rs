@@ -0,0 +1,106 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use hassel_lib6502::{OpAddressMode, OpClass, OpCode};
+
+const MNEMONICS: &'static [(OpClass, &'static str)] = &[
+    (OpClass::Adc, "ADC"),
+    (OpClass::And, "AND"),
+    (OpClass::Asl, "ASL"),
+    (OpClass::Bcc, "BCC"),
+    (OpClass::Bcs, "BCS"),
+    (OpClass::Beq, "BEQ"),
+    (OpClass::Bit, "BIT"),
+    (OpClass::Bmi, "BMI"),
+    (OpClass::Bne, "BNE"),
+    (OpClass::Bpl, "BPL"),
+    (OpClass::Brk, "BRK"),
+    (OpClass::Bvc, "BVC"),
+    (OpClass::Bvs, "BVS"),
+    (OpClass::Clc, "CLC"),
+    (OpClass::Cld, "CLD"),
+    (OpClass::Cli, "CLI"),
+    (OpClass::Clv, "CLV"),
+    (OpClass::Cmp, "CMP"),
+    (OpClass::Cpx, "CPX"),
+    (OpClass::Cpy, "CPY"),
+    (OpClass::Dec, "DEC"),
+    (OpClass::Dex, "DEX"),
+    (OpClass::Dey, "DEY"),
+    (OpClass::Eor, "EOR"),
+    (OpClass::Inc, "INC"),
+    (OpClass::Inx, "INX"),
+    (OpClass::Iny, "INY"),
+    (OpClass::Jmp, "JMP"),
+    (OpClass::Jsr, "JSR"),
+    (OpClass::Lda, "LDA"),
+    (OpClass::Ldx, "LDX"),
+    (OpClass::Ldy, "LDY"),
+    (OpClass::Lsr, "LSR"),
+    (OpClass::Nop, "NOP"),
+    (OpClass::Ora, "ORA"),
+    (OpClass::Pha, "PHA"),
+    (OpClass::Php, "PHP"),
+    (OpClass::Pla, "PLA"),
+    (OpClass::Plp, "PLP"),
+    (OpClass::Rol, "ROL"),
+    (OpClass::Ror, "ROR"),
+    (OpClass::Rti, "RTI"),
+    (OpClass::Rts, "RTS"),
+    (OpClass::Sbc, "SBC"),
+    (OpClass::Sec, "SEC"),
+    (OpClass::Sed, "SED"),
+    (OpClass::Sei, "SEI"),
+    (OpClass::Sta, "STA"),
+    (OpClass::Stx, "STX"),
+    (OpClass::Sty, "STY"),
+    (OpClass::Tax, "TAX"),
+    (OpClass::Tay, "TAY"),
+    (OpClass::Tsx, "TSX"),
+    (OpClass::Txa, "TXA"),
+    (OpClass::Txs, "TXS"),
+    (OpClass::Tya, "TYA"),
+    (OpClass::Top, "TOP"),
+];
+
+/// Returns the assembly mnemonic for an instruction class. The undocumented
+/// triple-byte NOP is reported as `TOP`, which most assemblers don't accept.
+pub fn mnemonic(class: OpClass) -> &'static str {
+    MNEMONICS
+        .iter()
+        .find(|entry| entry.0 == class)
+        .map(|entry| entry.1)
+        .unwrap()
+}
+
+/// Looks up an instruction class from its mnemonic, ignoring case.
+/// Only documented instructions are recognized.
+pub fn class_for_mnemonic(mnemonic: &str) -> Option<OpClass> {
+    MNEMONICS
+        .iter()
+        .filter(|entry| entry.0 != OpClass::Top)
+        .find(|entry| entry.1.eq_ignore_ascii_case(mnemonic))
+        .map(|entry| entry.0)
+}
+
+/// Finds the op-code for an instruction class in the given address mode
+pub fn find_op_code(class: OpClass, mode: OpAddressMode) -> Option<(u8, OpCode)> {
+    (0..0x100usize)
+        .filter_map(|value| OpCode::from_value(value as u8).map(|code| (value as u8, code)))
+        .find(|&(_, code)| code.class == class && code.address_mode == mode)
+}
+
+/// Returns true for the conditional branch instructions, which take a PC-relative offset
+pub fn is_branch(class: OpClass) -> bool {
+    match class {
+        OpClass::Bcc | OpClass::Bcs | OpClass::Beq | OpClass::Bmi | OpClass::Bne | OpClass::Bpl | OpClass::Bvc
+        | OpClass::Bvs => true,
+        _ => false,
+    }
+}
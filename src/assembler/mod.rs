@@ -0,0 +1,696 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! A small two-pass 6502 assembler for building test programs and ROMs without
+//! an external toolchain. Statements can be given as source text, or through the
+//! builder methods:
+//!
+//! ```rust
+//! # extern crate hassel_emu;
+//! # use hassel_emu::*;
+//! # use hassel_emu::assembler::Assembler;
+//! # fn main() {
+//! let program = Assembler::new(0xFFF2)
+//!     .source("start: LDA #3
+//!                     STA $00
+//!              loop:  JMP loop")
+//!     .reset_vector("start".into())
+//!     .assemble()
+//!     .unwrap();
+//!
+//! let memory_map = MemoryMap::builder()
+//!     .ram(0x0000, 0xFFF1)
+//!     .rom(0xFFF2, 0xFFFF, program.to_rom(0xFFF2, 0xFFFF).unwrap())
+//!     .build();
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use hassel_lib6502::{OpAddressMode, OpClass};
+
+use debug::SymbolTable;
+use emulator::{Image, ImageError, ImageSegment};
+
+mod mnemonic;
+mod parser;
+
+pub use self::mnemonic::{class_for_mnemonic, find_op_code, is_branch, mnemonic};
+pub use self::parser::{parse_operand, parse_value};
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// Errors reported by the assembler. Line numbers count every statement
+/// given to the assembler (each source line or builder call), starting at 1.
+#[derive(Debug)]
+pub enum AsmError {
+    /// A line of source couldn't be parsed
+    Syntax { line: usize, reason: String },
+    /// The instruction doesn't support the given operand
+    InvalidAddressMode { line: usize, mnemonic: &'static str },
+    /// A label was referenced but never defined
+    UndefinedLabel { line: usize, label: String },
+    /// A label was defined more than once
+    DuplicateLabel { line: usize, label: String },
+    /// A value didn't fit in its operand
+    ValueOutOfRange { line: usize, value: i32 },
+    /// A branch target is further than a signed byte away
+    BranchOutOfRange { line: usize, offset: i32 },
+    /// The assembled output couldn't be turned into an image, usually due to overlapping code
+    Image(ImageError),
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmError::Syntax { line, ref reason } => write!(f, "line {}: {}", line, reason),
+            AsmError::InvalidAddressMode { line, mnemonic } => {
+                write!(f, "line {}: invalid address mode for {}", line, mnemonic)
+            }
+            AsmError::UndefinedLabel { line, ref label } => write!(f, "line {}: undefined label `{}`", line, label),
+            AsmError::DuplicateLabel { line, ref label } => {
+                write!(f, "line {}: label `{}` is already defined", line, label)
+            }
+            AsmError::ValueOutOfRange { line, value } => write!(f, "line {}: value {} is out of range", line, value),
+            AsmError::BranchOutOfRange { line, offset } => {
+                write!(f, "line {}: branch offset {} is out of range", line, offset)
+            }
+            AsmError::Image(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for AsmError {
+    fn description(&self) -> &str {
+        match *self {
+            AsmError::Syntax { .. } => "syntax error",
+            AsmError::InvalidAddressMode { .. } => "invalid address mode",
+            AsmError::UndefinedLabel { .. } => "undefined label",
+            AsmError::DuplicateLabel { .. } => "duplicate label",
+            AsmError::ValueOutOfRange { .. } => "value out of range",
+            AsmError::BranchOutOfRange { .. } => "branch out of range",
+            AsmError::Image(_) => "invalid image",
+        }
+    }
+}
+
+impl From<ImageError> for AsmError {
+    fn from(err: ImageError) -> AsmError {
+        AsmError::Image(err)
+    }
+}
+
+/// A value that is either given directly or resolved at assembly time
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    /// A literal number
+    Number(u16),
+    /// The address of a label or the value of a constant
+    Label(String),
+    /// The address of the current statement (`*`)
+    Here,
+    /// Another value plus a signed offset
+    Offset(Box<Value>, i32),
+    /// The low byte of another value (`<value`)
+    Low(Box<Value>),
+    /// The high byte of another value (`>value`)
+    High(Box<Value>),
+}
+
+impl From<u16> for Value {
+    fn from(val: u16) -> Value {
+        Value::Number(val)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(label: &'a str) -> Value {
+        Value::Label(label.into())
+    }
+}
+
+/// Instruction operands, named by their syntax rather than the resulting address mode.
+/// The assembler picks zero page modes automatically when the address is known to fit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Operand {
+    /// No operand, or the accumulator (`ASL A`)
+    Implied,
+    /// `#value`
+    Immediate(Value),
+    /// `value`; zero page, absolute, or a branch target
+    Address(Value),
+    /// `value,X`
+    AddressX(Value),
+    /// `value,Y`
+    AddressY(Value),
+    /// `a:value`; always absolute, even when the value fits in the zero page
+    Absolute(Value),
+    /// `a:value,X`
+    AbsoluteX(Value),
+    /// `a:value,Y`
+    AbsoluteY(Value),
+    /// `(value)`
+    Indirect(Value),
+    /// `(value,X)`
+    IndirectX(Value),
+    /// `(value),Y`
+    IndirectY(Value),
+}
+
+/// The interrupt vectors that can be set with directives
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Vector {
+    Nmi,
+    Reset,
+    Irq,
+}
+
+#[derive(Clone, Debug)]
+enum Item {
+    Org(u16),
+    Label(String),
+    Constant(String, Value),
+    Op(OpClass, Operand),
+    Bytes(Vec<Value>),
+    Words(Vec<Value>),
+    Vector(Vector, Value),
+}
+
+/// The result of assembling a program
+pub struct Program {
+    image: Image,
+    symbols: SymbolTable,
+}
+
+impl Program {
+    /// Returns the assembled bytes
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Returns every label defined by the program
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Returns a buffer covering exactly the given region, suitable for passing to
+    /// `MemoryMapBuilder::rom`. Bytes that weren't assembled are filled with `$FF`.
+    pub fn to_rom(&self, start: u16, end_inclusive: u16) -> Result<Vec<u8>, ImageError> {
+        self.image.to_rom_filled(start, end_inclusive, 0xFF)
+    }
+}
+
+struct Context<'a> {
+    labels: &'a HashMap<String, i32>,
+    here: u16,
+    line: usize,
+}
+
+impl<'a> Context<'a> {
+    fn resolve(&self, value: &Value) -> Result<i32, AsmError> {
+        Ok(match *value {
+            Value::Number(val) => val as i32,
+            Value::Label(ref label) => match self.labels.get(label) {
+                Some(val) => *val,
+                None => {
+                    return Err(AsmError::UndefinedLabel {
+                        line: self.line,
+                        label: label.clone(),
+                    })
+                }
+            },
+            Value::Here => self.here as i32,
+            Value::Offset(ref value, offset) => self.resolve(value)? + offset,
+            Value::Low(ref value) => self.resolve(value)? & 0xFF,
+            Value::High(ref value) => (self.resolve(value)? >> 8) & 0xFF,
+        })
+    }
+
+    fn byte(&self, value: &Value) -> Result<u8, AsmError> {
+        match self.resolve(value)? {
+            val if val >= -128 && val <= 0xFF => Ok(val as u8),
+            val => Err(AsmError::ValueOutOfRange {
+                line: self.line,
+                value: val,
+            }),
+        }
+    }
+
+    fn word(&self, value: &Value) -> Result<u16, AsmError> {
+        match self.resolve(value)? {
+            val if val >= 0 && val <= 0xFFFF => Ok(val as u16),
+            val => Err(AsmError::ValueOutOfRange {
+                line: self.line,
+                value: val,
+            }),
+        }
+    }
+}
+
+/// Builder interface for assembling a program
+pub struct Assembler {
+    origin: u16,
+    items: Vec<(usize, Item)>,
+    line: usize,
+    error: Option<AsmError>,
+}
+
+impl Assembler {
+    /// Creates a new assembler that starts placing code at the given address
+    pub fn new(origin: u16) -> Assembler {
+        Assembler {
+            origin: origin,
+            items: Vec::new(),
+            line: 0,
+            error: None,
+        }
+    }
+
+    fn push(mut self, item: Item) -> Self {
+        self.line += 1;
+        self.items.push((self.line, item));
+        self
+    }
+
+    /// Assembles lines of source text. Supports `label:` definitions, `name = value`
+    /// constants, all documented instructions, and the `.org`, `.byte`, `.word`,
+    /// `.reset`, `.irq` and `.nmi` directives. Comments start with `;`.
+    pub fn source(mut self, text: &str) -> Self {
+        for line in text.lines() {
+            self.line += 1;
+            match parser::parse_line(line) {
+                Ok(items) => for item in items {
+                    self.items.push((self.line, item));
+                },
+                Err(reason) => if self.error.is_none() {
+                    self.error = Some(AsmError::Syntax {
+                        line: self.line,
+                        reason: reason,
+                    });
+                },
+            }
+        }
+        self
+    }
+
+    /// Continues assembling at a new address
+    pub fn org(self, address: u16) -> Self {
+        self.push(Item::Org(address))
+    }
+
+    /// Defines a label at the current address
+    pub fn label<S: Into<String>>(self, name: S) -> Self {
+        self.push(Item::Label(name.into()))
+    }
+
+    /// Defines a named constant
+    pub fn constant<S: Into<String>>(self, name: S, value: Value) -> Self {
+        self.push(Item::Constant(name.into(), value))
+    }
+
+    /// Adds an instruction
+    pub fn op(self, class: OpClass, operand: Operand) -> Self {
+        self.push(Item::Op(class, operand))
+    }
+
+    /// Adds raw bytes
+    pub fn bytes(self, data: &[u8]) -> Self {
+        self.push(Item::Bytes(data.iter().map(|val| Value::Number(*val as u16)).collect()))
+    }
+
+    /// Adds a little-endian 16-bit word
+    pub fn word(self, value: Value) -> Self {
+        self.push(Item::Words(vec![value]))
+    }
+
+    /// Sets the reset vector at $FFFC
+    pub fn reset_vector(self, value: Value) -> Self {
+        self.push(Item::Vector(Vector::Reset, value))
+    }
+
+    /// Sets the maskable interrupt vector at $FFFE
+    pub fn irq_vector(self, value: Value) -> Self {
+        self.push(Item::Vector(Vector::Irq, value))
+    }
+
+    /// Sets the non-maskable interrupt vector at $FFFA
+    pub fn nmi_vector(self, value: Value) -> Self {
+        self.push(Item::Vector(Vector::Nmi, value))
+    }
+
+    /// Chooses the address mode for an instruction. Zero page modes are only used
+    /// when the value is already known in the first pass, so forward references to
+    /// zero page labels produce absolute addressing, same as most assemblers.
+    fn choose_mode(
+        class: OpClass,
+        operand: &Operand,
+        context: &Context,
+    ) -> Result<(u8, OpAddressMode, usize), AsmError> {
+        let fits_zero_page = |value: &Value| match context.resolve(value) {
+            Ok(val) => val >= 0 && val <= 0xFF,
+            Err(_) => false,
+        };
+
+        let candidates: Vec<OpAddressMode> = match *operand {
+            Operand::Implied => vec![OpAddressMode::Implied],
+            Operand::Immediate(_) => vec![OpAddressMode::Immediate],
+            Operand::Address(_) if is_branch(class) => vec![OpAddressMode::PCOffset],
+            Operand::Address(ref value) if fits_zero_page(value) => {
+                vec![OpAddressMode::ZeroPage, OpAddressMode::Absolute]
+            }
+            Operand::Address(_) => vec![OpAddressMode::Absolute],
+            Operand::AddressX(ref value) if fits_zero_page(value) => {
+                vec![OpAddressMode::ZeroPageOffsetX, OpAddressMode::AbsoluteOffsetX]
+            }
+            Operand::AddressX(_) => vec![OpAddressMode::AbsoluteOffsetX],
+            Operand::AddressY(ref value) if fits_zero_page(value) => {
+                vec![OpAddressMode::ZeroPageOffsetY, OpAddressMode::AbsoluteOffsetY]
+            }
+            Operand::AddressY(_) => vec![OpAddressMode::AbsoluteOffsetY],
+            Operand::Absolute(_) => vec![OpAddressMode::Absolute],
+            Operand::AbsoluteX(_) => vec![OpAddressMode::AbsoluteOffsetX],
+            Operand::AbsoluteY(_) => vec![OpAddressMode::AbsoluteOffsetY],
+            Operand::Indirect(_) => vec![OpAddressMode::Indirect],
+            Operand::IndirectX(_) => vec![OpAddressMode::PreIndirectX],
+            Operand::IndirectY(_) => vec![OpAddressMode::PostIndirectY],
+        };
+
+        for mode in candidates {
+            if let Some((value, code)) = find_op_code(class, mode) {
+                return Ok((value, mode, code.len as usize));
+            }
+        }
+        Err(AsmError::InvalidAddressMode {
+            line: context.line,
+            mnemonic: mnemonic(class),
+        })
+    }
+
+    fn define(labels: &mut HashMap<String, i32>, line: usize, name: &str, value: i32) -> Result<(), AsmError> {
+        if labels.insert(name.into(), value).is_some() {
+            return Err(AsmError::DuplicateLabel {
+                line: line,
+                label: name.into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs both passes and returns the assembled program
+    pub fn assemble(self) -> Result<Program, AsmError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        // First pass: assign addresses to labels and pick address modes
+        let mut labels: HashMap<String, i32> = HashMap::new();
+        let mut modes: Vec<Option<(u8, OpAddressMode)>> = Vec::with_capacity(self.items.len());
+        let mut pc = self.origin as usize;
+        for &(line, ref item) in &self.items {
+            let mut mode = None;
+            match *item {
+                Item::Org(address) => pc = address as usize,
+                Item::Label(ref name) => Assembler::define(&mut labels, line, name, pc as i32)?,
+                Item::Constant(ref name, ref value) => {
+                    let value = Context {
+                        labels: &labels,
+                        here: pc as u16,
+                        line: line,
+                    }.resolve(value)?;
+                    Assembler::define(&mut labels, line, name, value)?;
+                }
+                Item::Op(class, ref operand) => {
+                    let context = Context {
+                        labels: &labels,
+                        here: pc as u16,
+                        line: line,
+                    };
+                    let (value, address_mode, len) = Assembler::choose_mode(class, operand, &context)?;
+                    mode = Some((value, address_mode));
+                    pc += len;
+                }
+                Item::Bytes(ref values) => pc += values.len(),
+                Item::Words(ref values) => pc += values.len() * 2,
+                Item::Vector(..) => {}
+            }
+            modes.push(mode);
+        }
+
+        // Second pass: emit bytes now that every label is known
+        let mut segments: Vec<ImageSegment> = Vec::new();
+        let mut current = ImageSegment {
+            address: self.origin,
+            data: Vec::new(),
+        };
+        let mut vectors: Vec<ImageSegment> = Vec::new();
+        for (&(line, ref item), mode) in self.items.iter().zip(modes.into_iter()) {
+            let context = Context {
+                labels: &labels,
+                here: current.address.wrapping_add(current.data.len() as u16),
+                line: line,
+            };
+            match *item {
+                Item::Org(address) => {
+                    let next = ImageSegment {
+                        address: address,
+                        data: Vec::new(),
+                    };
+                    let finished = ::std::mem::replace(&mut current, next);
+                    if !finished.data.is_empty() {
+                        segments.push(finished);
+                    }
+                }
+                Item::Label(_) | Item::Constant(..) => {}
+                Item::Op(_, ref operand) => {
+                    let (value, address_mode) = mode.unwrap();
+                    current.data.push(value);
+                    Assembler::emit_operand(&mut current.data, address_mode, operand, &context)?;
+                }
+                Item::Bytes(ref values) => for value in values {
+                    current.data.push(context.byte(value)?);
+                },
+                Item::Words(ref values) => for value in values {
+                    let word = context.word(value)?;
+                    current.data.push((word & 0xFF) as u8);
+                    current.data.push((word >> 8) as u8);
+                },
+                Item::Vector(vector, ref value) => {
+                    let word = context.word(value)?;
+                    vectors.push(ImageSegment {
+                        address: match vector {
+                            Vector::Nmi => NMI_VECTOR,
+                            Vector::Reset => RESET_VECTOR,
+                            Vector::Irq => IRQ_VECTOR,
+                        },
+                        data: vec![(word & 0xFF) as u8, (word >> 8) as u8],
+                    });
+                }
+            }
+            if current.address as usize + current.data.len() > 0x10000 {
+                return Err(AsmError::ValueOutOfRange {
+                    line: line,
+                    value: (current.address as usize + current.data.len()) as i32,
+                });
+            }
+        }
+        if !current.data.is_empty() {
+            segments.push(current);
+        }
+        segments.extend(vectors);
+
+        let mut symbols = SymbolTable::new();
+        for (name, value) in labels {
+            if value >= 0 && value <= 0xFFFF {
+                symbols.add_symbol(name, value as u16);
+            }
+        }
+        Ok(Program {
+            image: Image::from_segments(segments, None)?,
+            symbols: symbols,
+        })
+    }
+
+    fn emit_operand(
+        output: &mut Vec<u8>,
+        mode: OpAddressMode,
+        operand: &Operand,
+        context: &Context,
+    ) -> Result<(), AsmError> {
+        let value = match *operand {
+            Operand::Implied => return Ok(()),
+            Operand::Immediate(ref value)
+            | Operand::Address(ref value)
+            | Operand::AddressX(ref value)
+            | Operand::AddressY(ref value)
+            | Operand::Absolute(ref value)
+            | Operand::AbsoluteX(ref value)
+            | Operand::AbsoluteY(ref value)
+            | Operand::Indirect(ref value)
+            | Operand::IndirectX(ref value)
+            | Operand::IndirectY(ref value) => value,
+        };
+        match mode {
+            OpAddressMode::Implied => {}
+            OpAddressMode::PCOffset => {
+                let offset = context.resolve(value)? - (context.here as i32 + 2);
+                if offset < -128 || offset > 127 {
+                    return Err(AsmError::BranchOutOfRange {
+                        line: context.line,
+                        offset: offset,
+                    });
+                }
+                output.push(offset as u8);
+            }
+            OpAddressMode::Immediate
+            | OpAddressMode::ZeroPage
+            | OpAddressMode::ZeroPageOffsetX
+            | OpAddressMode::ZeroPageOffsetY
+            | OpAddressMode::PreIndirectX
+            | OpAddressMode::PostIndirectY => output.push(context.byte(value)?),
+            OpAddressMode::Absolute
+            | OpAddressMode::AbsoluteOffsetX
+            | OpAddressMode::AbsoluteOffsetY
+            | OpAddressMode::Indirect => {
+                let word = context.word(value)?;
+                output.push((word & 0xFF) as u8);
+                output.push((word >> 8) as u8);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::{Cpu, MemoryMap};
+
+    #[test]
+    fn test_readme_program() {
+        let program = Assembler::new(0xFFF2)
+            .source(
+                "start: LDA #3
+                        STA $00
+                 loop:  JMP loop
+                 .nmi loop
+                 .reset start
+                 .irq loop",
+            )
+            .assemble()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                0xA9, 0x03, 0x85, 0x00, 0x4C, 0xF6, 0xFF, 0xFF, 0xF6, 0xFF, 0xF2, 0xFF, 0xF6, 0xFF,
+            ],
+            program.to_rom(0xFFF2, 0xFFFF).unwrap()
+        );
+        assert_eq!(Some(0xFFF6), program.symbols().address_of("loop"));
+    }
+
+    #[test]
+    fn test_builder_and_branches() {
+        let program = Assembler::new(0xE000)
+            .constant("counter", Value::Number(0x10))
+            .label("start")
+            .op(OpClass::Ldx, Operand::Immediate(Value::Number(5)))
+            .label("loop")
+            .op(OpClass::Inc, Operand::Address("counter".into()))
+            .op(OpClass::Dex, Operand::Implied)
+            .op(OpClass::Bne, Operand::Address("loop".into()))
+            .op(OpClass::Jmp, Operand::Address(Value::Here))
+            .reset_vector("start".into())
+            .assemble()
+            .unwrap();
+
+        let memory_map = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .rom(0xE000, 0xFFFF, program.to_rom(0xE000, 0xFFFF).unwrap())
+            .build();
+        let mut cpu = Cpu::new(memory_map);
+        for _ in 0..20 {
+            cpu.step();
+        }
+        assert_eq!(5, cpu.memory().debug_read().byte(0x0010));
+    }
+
+    #[test]
+    fn test_address_modes() {
+        let program = Assembler::new(0x0200)
+            .source(
+                "LDA ($20),Y
+                 STA (ptr,X)
+                 JMP (vector)
+                 LDA table,X
+                 ASL A
+                 LDA #<table
+                 LDX #>table
+                 ptr = $30
+                 vector: .word table+1
+                 table: .byte 1, 2, 'a'",
+            )
+            .assemble()
+            .unwrap();
+        assert_eq!(
+            vec![
+                0xB1, 0x20, 0x81, 0x30, 0x6C, 0x0F, 0x02, 0xBD, 0x11, 0x02, 0x0A, 0xA9, 0x11, 0xA2, 0x02, 0x12, 0x02,
+                0x01, 0x02, 0x61,
+            ],
+            program.to_rom(0x0200, 0x0213).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_quotes_and_absolute_prefix() {
+        let program = Assembler::new(0x0200)
+            .source(
+                "LDA #':' ; load a colon
+                 LDA a:$10
+                 STA a:$20,X
+                 eq = '='
+                 .byte ';', '=', ',', eq
+                 LDA #'+'
+                 CMP #'+'+1
+                 .byte '-', '-'-1",
+            )
+            .assemble()
+            .unwrap();
+        assert_eq!(
+            vec![
+                0xA9, 0x3A, 0xAD, 0x10, 0x00, 0x9D, 0x20, 0x00, 0x3B, 0x3D, 0x2C, 0x3D, 0xA9, 0x2B, 0xC9, 0x2C, 0x2D,
+                0x2C,
+            ],
+            program.to_rom(0x0200, 0x0211).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        match Assembler::new(0).source("LDX ($10),Y").assemble() {
+            Err(AsmError::InvalidAddressMode { line: 1, .. }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match Assembler::new(0).source("BNE far\n.org $1000\nfar: RTS").assemble() {
+            Err(AsmError::BranchOutOfRange { line: 1, .. }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match Assembler::new(0).source("NOP\nJMP nowhere").assemble() {
+            Err(AsmError::UndefinedLabel { line: 2, .. }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match Assembler::new(0).source("FOO #1").assemble() {
+            Err(AsmError::Syntax { line: 1, .. }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use assembler::mnemonic::class_for_mnemonic;
+use assembler::{Item, Operand, Value, Vector};
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(chr) if chr.is_alphabetic() || chr == '_' || chr == '@' => {}
+        _ => return false,
+    }
+    chars.all(|chr| chr.is_alphanumeric() || chr == '_')
+}
+
+/// Finds the first of the `delimiters` that isn't part of a character literal such as `';'`
+fn find_unquoted(text: &str, delimiters: &[char]) -> Option<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut index = 0;
+    while index < chars.len() {
+        let (offset, chr) = chars[index];
+        if chr == '\'' && chars.get(index + 2).map(|&(_, end)| end) == Some('\'') {
+            index += 3;
+            continue;
+        }
+        if delimiters.contains(&chr) {
+            return Some(offset);
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Splits `text` on every `delimiter` that isn't part of a character literal
+fn split_unquoted(text: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(split) = find_unquoted(rest, &[delimiter]) {
+        parts.push(&rest[..split]);
+        rest = &rest[split + delimiter.len_utf8()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let parsed = if text.starts_with('$') {
+        u32::from_str_radix(&text[1..], 16).ok()
+    } else if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16).ok()
+    } else if text.starts_with('%') {
+        u32::from_str_radix(&text[1..], 2).ok()
+    } else if text.len() == 3 && text.starts_with('\'') && text.ends_with('\'') {
+        text.chars().nth(1).map(|chr| chr as u32)
+    } else {
+        text.parse::<u32>().ok()
+    };
+    match parsed {
+        Some(val) if val <= 0xFFFF => Ok(val as u16),
+        _ => Err(format!("invalid number `{}`", text)),
+    }
+}
+
+/// Parses an expression of the form `[<|>]term[(+|-)offset]`, where a term
+/// is a number, a label, or `*` for the address of the current statement
+pub fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if text.starts_with('<') {
+        return Ok(Value::Low(Box::new(parse_value(&text[1..])?)));
+    } else if text.starts_with('>') {
+        return Ok(Value::High(Box::new(parse_value(&text[1..])?)));
+    }
+
+    // Skip the first character so that a leading sign isn't treated as an offset
+    let split = match find_unquoted(text, &['+', '-']) {
+        Some(0) => find_unquoted(&text[1..], &['+', '-']).map(|index| index + 1),
+        split => split,
+    };
+    let (term, offset) = match split {
+        Some(split) => {
+            let magnitude = parse_number(text[split + 1..].trim())? as i32;
+            let offset = if &text[split..split + 1] == "-" {
+                -magnitude
+            } else {
+                magnitude
+            };
+            (text[..split].trim(), Some(offset))
+        }
+        None => (text, None),
+    };
+
+    let value = if term == "*" {
+        Value::Here
+    } else if is_identifier(term) {
+        Value::Label(term.into())
+    } else if term.is_empty() {
+        return Err("missing value".into());
+    } else {
+        Value::Number(parse_number(term)?)
+    };
+    Ok(match offset {
+        Some(offset) => Value::Offset(Box::new(value), offset),
+        None => value,
+    })
+}
+
+/// Parses the operand part of an instruction, such as `#$10`, `($20),Y` or `table,X`.
+/// An `a:` prefix forces absolute addressing, as in ca65.
+pub fn parse_operand(text: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    let upper = text.to_uppercase();
+    if upper.starts_with("A:") {
+        return match parse_operand(&text[2..])? {
+            Operand::Address(value) => Ok(Operand::Absolute(value)),
+            Operand::AddressX(value) => Ok(Operand::AbsoluteX(value)),
+            Operand::AddressY(value) => Ok(Operand::AbsoluteY(value)),
+            _ => Err(format!("invalid absolute operand `{}`", text)),
+        };
+    }
+    if text.is_empty() || upper == "A" {
+        Ok(Operand::Implied)
+    } else if text.starts_with('#') {
+        Ok(Operand::Immediate(parse_value(&text[1..])?))
+    } else if text.starts_with('(') {
+        if upper.ends_with(",X)") {
+            Ok(Operand::IndirectX(parse_value(&text[1..text.len() - 3])?))
+        } else if upper.ends_with("),Y") {
+            Ok(Operand::IndirectY(parse_value(&text[1..text.len() - 3])?))
+        } else if text.ends_with(')') {
+            Ok(Operand::Indirect(parse_value(&text[1..text.len() - 1])?))
+        } else {
+            Err(format!("invalid indirect operand `{}`", text))
+        }
+    } else if upper.ends_with(",X") {
+        Ok(Operand::AddressX(parse_value(&text[..text.len() - 2])?))
+    } else if upper.ends_with(",Y") {
+        Ok(Operand::AddressY(parse_value(&text[..text.len() - 2])?))
+    } else {
+        Ok(Operand::Address(parse_value(text)?))
+    }
+}
+
+fn parse_value_list(text: &str) -> Result<Vec<Value>, String> {
+    split_unquoted(text, ',').into_iter().map(parse_value).collect()
+}
+
+/// Parses a single line of assembly into the statements it contains
+pub fn parse_line(line: &str) -> Result<Vec<Item>, String> {
+    let mut items = Vec::new();
+    let mut rest = match find_unquoted(line, &[';']) {
+        Some(comment) => &line[..comment],
+        None => line,
+    }.trim();
+
+    if let Some(equals) = find_unquoted(rest, &['=']) {
+        let name = rest[..equals].trim_matches(':').trim();
+        if !is_identifier(name) {
+            return Err(format!("invalid constant name `{}`", name));
+        }
+        items.push(Item::Constant(name.into(), parse_value(&rest[equals + 1..])?));
+        return Ok(items);
+    }
+
+    // A colon after the first word ends a label; later ones belong to the operand (`a:`)
+    if let Some(colon) = find_unquoted(rest, &[':']) {
+        let name = rest[..colon].trim();
+        if !name.contains(char::is_whitespace) {
+            if !is_identifier(name) {
+                return Err(format!("invalid label `{}`", name));
+            }
+            items.push(Item::Label(name.into()));
+            rest = rest[colon + 1..].trim();
+        }
+    }
+    if rest.is_empty() {
+        return Ok(items);
+    }
+
+    let (keyword, operand) = match rest.find(char::is_whitespace) {
+        Some(split) => (&rest[..split], rest[split..].trim()),
+        None => (rest, ""),
+    };
+    let item = match keyword.to_lowercase().as_str() {
+        ".org" => match parse_value(operand)? {
+            Value::Number(address) => Item::Org(address),
+            _ => return Err(".org requires a numeric address".into()),
+        },
+        ".byte" | ".db" => Item::Bytes(parse_value_list(operand)?),
+        ".word" | ".dw" | ".addr" => Item::Words(parse_value_list(operand)?),
+        ".reset" => Item::Vector(Vector::Reset, parse_value(operand)?),
+        ".irq" => Item::Vector(Vector::Irq, parse_value(operand)?),
+        ".nmi" => Item::Vector(Vector::Nmi, parse_value(operand)?),
+        _ if keyword.starts_with('.') => return Err(format!("unknown directive `{}`", keyword)),
+        _ => match class_for_mnemonic(keyword) {
+            Some(class) => Item::Op(class, parse_operand(operand)?),
+            None => return Err(format!("unknown mnemonic `{}`", keyword)),
+        },
+    };
+    items.push(item);
+    Ok(items)
+}
@@ -0,0 +1,175 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use hassel_emu::debug::{SemihostingDevice, SymbolTable, SEMIHOSTING_SIZE};
+use hassel_emu::emulator::{Image, ImageFormat};
+#[cfg(feature = "hassel_arch")]
+use hassel_emu::hassel::{GraphicsDevice, HasselSystemBuilder};
+use hassel_emu::{Cpu, MemoryMap};
+
+use options::{Layout, Options};
+
+/// Everything the runner needs to hold on to after building the system
+pub struct Machine {
+    pub cpu: Cpu,
+    pub symbols: SymbolTable,
+    pub semihosting: Option<Rc<RefCell<SemihostingDevice>>>,
+    #[cfg(feature = "hassel_arch")]
+    pub graphics: Option<Rc<RefCell<GraphicsDevice>>>,
+    /// The loaded program
+    pub image: Image,
+    /// Address ranges that are mapped to RAM
+    pub ram: Vec<(u16, u16)>,
+    /// Address ranges that are mapped to ROM
+    pub rom: Vec<(u16, u16)>,
+}
+
+pub fn load_image(options: &Options) -> Result<Image, String> {
+    let mut data = Vec::new();
+    File::open(&options.rom)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|err| format!("failed to read `{}`: {}", options.rom, err))?;
+
+    // Raw images default to ending at the top of memory, where the vectors live
+    let raw_offset = match options.load_address {
+        Some(address) => address,
+        None if data.len() <= 0x10000 => (0x10000 - data.len()) as u16,
+        None => return Err(format!("`{}` is larger than 64K", options.rom)),
+    };
+    let format = match options.format.as_ref().map(|format| format.as_str()) {
+        None => ImageFormat::from_path(&options.rom, raw_offset),
+        Some("raw") | Some("bin") => ImageFormat::Raw(raw_offset),
+        Some("hex") | Some("ihex") => ImageFormat::IntelHex,
+        Some("srec") | Some("s19") => ImageFormat::SRecord,
+        Some("prg") => ImageFormat::Prg,
+        Some(other) => return Err(format!("unknown image format `{}`", other)),
+    };
+    Image::parse(format, &data).map_err(|err| format!("failed to load `{}`: {}", options.rom, err))
+}
+
+pub fn load_symbols(options: &Options) -> Result<SymbolTable, String> {
+    match options.symbols {
+        Some(ref path) => SymbolTable::load(path).map_err(|err| format!("failed to load `{}`: {}", path, err)),
+        None => Ok(SymbolTable::new()),
+    }
+}
+
+fn build_flat(
+    image: &Image,
+    options: &Options,
+    console: Box<Write>,
+) -> Result<(MemoryMap, Option<Rc<RefCell<SemihostingDevice>>>, Vec<(u16, u16)>), String> {
+    let exit_address = match options.exit_address {
+        Some(address) => address,
+        None => {
+            let mut memory = MemoryMap::builder()
+                .ram_fill(options.ram_fill)
+                .ram(0x0000, 0xFFFF)
+                .build();
+            image.write_to(&mut memory);
+            return Ok((memory, None, vec![(0x0000, 0xFFFF)]));
+        }
+    };
+
+    let device_end = exit_address as usize + SEMIHOSTING_SIZE as usize - 1;
+    if device_end > 0xFFFF {
+        return Err(format!("exit address ${:04X} leaves no room for its registers", exit_address));
+    }
+    let overlaps = image.segments().iter().any(|segment| {
+        segment.address as usize <= device_end && segment.end_inclusive() >= exit_address
+    });
+    if overlaps {
+        return Err(format!("the image overlaps the semihosting device at ${:04X}", exit_address));
+    }
+
+    let device = Rc::new(RefCell::new(SemihostingDevice::new(exit_address, console)));
+    let mut builder = MemoryMap::builder().ram_fill(options.ram_fill);
+    let mut ram = Vec::new();
+    if exit_address > 0 {
+        builder = builder.ram(0x0000, exit_address - 1);
+        ram.push((0x0000, exit_address - 1));
+    }
+    builder = builder.device("semihosting", exit_address, device_end as u16, device.clone());
+    if device_end < 0xFFFF {
+        builder = builder.ram(device_end as u16 + 1, 0xFFFF);
+        ram.push((device_end as u16 + 1, 0xFFFF));
+    }
+    let mut memory = builder.try_build().map_err(|err| err.to_string())?;
+    image.write_to(&mut memory);
+    Ok((memory, Some(device), ram))
+}
+
+impl Machine {
+    pub fn new(options: &Options) -> Result<Machine, String> {
+        Machine::with_console(options, Box::new(io::stdout()))
+    }
+
+    /// Builds the machine with semihosting output going to `console`
+    pub fn with_console(options: &Options, console: Box<Write>) -> Result<Machine, String> {
+        let image = load_image(options)?;
+        let symbols = load_symbols(options)?;
+
+        match options.layout {
+            Layout::Flat => {
+                let (memory, semihosting, ram) = build_flat(&image, options, console)?;
+                Ok(Machine {
+                    cpu: Cpu::new(memory),
+                    symbols: symbols,
+                    semihosting: semihosting,
+                    #[cfg(feature = "hassel_arch")]
+                    graphics: None,
+                    image: image,
+                    ram: ram,
+                    rom: Vec::new(),
+                })
+            }
+            Layout::Hassel => Machine::build_hassel(image, symbols, options),
+        }
+    }
+
+    #[cfg(feature = "hassel_arch")]
+    fn build_hassel(image: Image, symbols: SymbolTable, options: &Options) -> Result<Machine, String> {
+        if options.exit_address.is_some() {
+            return Err("--exit-address is only supported by the flat layout".into());
+        }
+        let rom = image
+            .to_rom_filled(0xE000, 0xFFFF, 0xFF)
+            .map_err(|err| format!("image doesn't fit the Hasseldorf ROM at $E000-$FFFF: {}", err))?;
+        let (memory, graphics, _io) = HasselSystemBuilder::new()
+            .rom(rom)
+            .ram_fill(options.ram_fill)
+            .build();
+        Ok(Machine {
+            cpu: Cpu::new(memory),
+            symbols: symbols,
+            semihosting: None,
+            graphics: Some(graphics),
+            image: image,
+            ram: vec![(0x0000, 0xDFFD)],
+            rom: vec![(0xE000, 0xFFFF)],
+        })
+    }
+
+    #[cfg(not(feature = "hassel_arch"))]
+    fn build_hassel(_image: Image, _symbols: SymbolTable, _options: &Options) -> Result<Machine, String> {
+        Err("this build doesn't include the hassel_arch feature".into())
+    }
+
+    /// Returns the exit status requested through semihosting, if any
+    pub fn exit_code(&self) -> Option<u8> {
+        self.semihosting
+            .as_ref()
+            .and_then(|device| device.borrow().exit_code())
+    }
+}
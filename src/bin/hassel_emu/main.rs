@@ -0,0 +1,449 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! Command-line runner for hassel_emu. Loads a ROM image, runs it headless
+//! or under the interactive monitor, and optionally writes a trace, memory
+//! dump or screen capture.
+
+extern crate hassel_emu;
+#[cfg(feature = "dap")]
+extern crate serde_json;
+
+mod machine;
+mod options;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "dap")]
+use std::net::TcpListener;
+use std::process;
+use std::rc::Rc;
+
+#[cfg(feature = "dap")]
+use hassel_emu::debug::{DapServer, DapTarget};
+use hassel_emu::debug::{CodeDataLogger, Diagnostics, Monitor, Profiler, Reassembler, Sanitizer, Tracer, ViceServer};
+use hassel_emu::emulator::RamFill;
+
+use machine::{load_image, load_symbols, Machine};
+#[cfg(feature = "dap")]
+use options::parse_layout;
+use options::{parse_address, Options, USAGE};
+
+/// Exit status used when the cycle limit runs out before another stop condition is met
+const EXIT_TIMEOUT: i32 = 124;
+/// Exit status used when one of the `--diagnose` checks stops the program
+const EXIT_DIAGNOSTIC: i32 = 125;
+/// Cycle limit used when there's no other stop condition, so that a ROM that never
+/// exits can't hang a script
+const DEFAULT_MAX_CYCLES: usize = 100_000_000;
+
+fn open_output(path: &str) -> Result<Box<Write>, String> {
+    if path == "-" {
+        Ok(Box::new(io::stdout()))
+    } else {
+        File::create(path)
+            .map(|file| Box::new(BufWriter::new(file)) as Box<Write>)
+            .map_err(|err| format!("failed to create `{}`: {}", path, err))
+    }
+}
+
+fn write_dump(machine: &Machine, options: &Options, path: &str) -> Result<(), String> {
+    let (start, end) = options.dump_range;
+    let memory = machine.cpu.memory().debug_read();
+    let data: Vec<u8> = (start as usize..end as usize + 1)
+        .map(|addr| memory.byte(addr as u16))
+        .collect();
+    open_output(path)?
+        .write_all(&data)
+        .map_err(|err| format!("failed to write `{}`: {}", path, err))
+}
+
+fn write_profile(profiler: &Profiler, machine: &Machine, options: &Options) -> Result<(), String> {
+    if let Some(ref path) = options.profile {
+        profiler
+            .write_report(&mut open_output(path)?, &machine.symbols)
+            .map_err(|err| format!("failed to write `{}`: {}", path, err))?;
+    }
+    if let Some(ref path) = options.profile_folded {
+        profiler
+            .write_folded(&mut open_output(path)?, &machine.symbols)
+            .map_err(|err| format!("failed to write `{}`: {}", path, err))?;
+    }
+    Ok(())
+}
+
+fn write_coverage(logger: &CodeDataLogger, machine: &Machine, options: &Options) -> Result<(), String> {
+    if let Some(ref path) = options.cdl {
+        logger
+            .write_cdl(&mut open_output(path)?)
+            .map_err(|err| format!("failed to write `{}`: {}", path, err))?;
+    }
+    if let Some(ref path) = options.lcov {
+        if machine.symbols.lines().next().is_none() {
+            return Err("--lcov needs a --symbols file with source lines".into());
+        }
+        logger
+            .write_lcov(&mut open_output(path)?, &machine.symbols)
+            .map_err(|err| format!("failed to write `{}`: {}", path, err))?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "hassel_arch")]
+fn write_screen(machine: &Machine, path: &str) -> Result<(), String> {
+    use hassel_emu::hassel::{SCREEN_HEIGHT_PIXELS, SCREEN_WIDTH_PIXELS};
+
+    let graphics = match machine.graphics {
+        Some(ref graphics) => graphics.borrow(),
+        None => return Err("--screen requires the hassel layout".into()),
+    };
+    let mut output = open_output(path)?;
+    let result = if path.to_lowercase().ends_with(".ppm") {
+        let mut data = format!("P6\n{} {}\n255\n", SCREEN_WIDTH_PIXELS, SCREEN_HEIGHT_PIXELS).into_bytes();
+        for pixel in graphics.frame_buffer() {
+            data.extend(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+        }
+        output.write_all(&data)
+    } else {
+        output.write_all(graphics.screen_text().as_bytes())
+    };
+    result.map_err(|err| format!("failed to write `{}`: {}", path, err))
+}
+
+#[cfg(not(feature = "hassel_arch"))]
+fn write_screen(_machine: &Machine, _path: &str) -> Result<(), String> {
+    Err("--screen requires the hassel_arch feature".into())
+}
+
+#[cfg(feature = "hassel_arch")]
+fn screen_text(machine: &Machine) -> Option<Box<Fn() -> String>> {
+    machine.graphics.clone().map(|graphics| {
+        Box::new(move || graphics.borrow().screen_text()) as Box<Fn() -> String>
+    })
+}
+
+#[cfg(not(feature = "hassel_arch"))]
+fn screen_text(_machine: &Machine) -> Option<Box<Fn() -> String>> {
+    None
+}
+
+fn run_monitor(machine: &mut Machine) -> Result<(String, i32), String> {
+    let screen = screen_text(machine);
+    machine.cpu.enable_provenance();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    {
+        let mut monitor = Monitor::new(&mut machine.cpu, stdin.lock(), stdout.lock())
+            .with_symbols(machine.symbols.clone());
+        if let Some(screen) = screen {
+            monitor = monitor.with_screen(screen);
+        }
+        monitor
+            .run()
+            .map_err(|err| format!("monitor failed: {}", err))?;
+    }
+    Ok(match machine.exit_code() {
+        Some(code) => (format!("program exited with status {}", code), code as i32),
+        None => ("left the monitor".to_string(), 0),
+    })
+}
+
+fn run_headless(
+    machine: &mut Machine,
+    options: &Options,
+    diagnostics: Option<&Rc<RefCell<Diagnostics>>>,
+) -> Result<(String, i32), String> {
+    let until = match options.until {
+        Some(ref until) => Some(match machine.symbols.address_of(until) {
+            Some(address) => address,
+            None => parse_address(until)?,
+        }),
+        None => None,
+    };
+    let mut tracer = match options.trace {
+        Some(ref path) => Some(Tracer::new(open_output(path)?).with_symbols(machine.symbols.clone())),
+        None => None,
+    };
+    let max_cycles = match options.max_cycles {
+        None if until.is_none() && options.exit_address.is_none() => Some(DEFAULT_MAX_CYCLES),
+        max_cycles => max_cycles,
+    };
+
+    let (reason, status) = loop {
+        if let Some(code) = machine.exit_code() {
+            break (format!("program exited with status {}", code), code as i32);
+        }
+        if until == Some(machine.cpu.registers().pc) {
+            break ("reached the --until address".to_string(), 0);
+        }
+        if diagnostics.map(|diagnostics| !diagnostics.borrow().events().is_empty()) == Some(true) {
+            break ("stopped by --diagnose".to_string(), EXIT_DIAGNOSTIC);
+        }
+        if let Some(max_cycles) = max_cycles {
+            if machine.cpu.cycles() >= max_cycles {
+                let status = if until.is_some() || options.exit_address.is_some() || options.max_cycles.is_none() {
+                    EXIT_TIMEOUT
+                } else {
+                    0
+                };
+                break ("reached the cycle limit".to_string(), status);
+            }
+        }
+        if let Some(ref mut tracer) = tracer {
+            tracer
+                .trace(&machine.cpu)
+                .map_err(|err| format!("failed to write trace: {}", err))?;
+        }
+        machine.cpu.step();
+    };
+
+    if let Some(tracer) = tracer {
+        tracer
+            .into_inner()
+            .map_err(|err| format!("failed to write trace: {}", err))?;
+    }
+    Ok((reason, status))
+}
+
+fn run_vice_monitor(machine: &mut Machine, address: &str) -> Result<(String, i32), String> {
+    let mut server = ViceServer::bind(address).map_err(|err| format!("failed to listen on `{}`: {}", address, err))?;
+    if let Ok(address) = server.local_addr() {
+        eprintln!("waiting for a VICE monitor client on {}", address);
+    }
+    server
+        .serve(&mut machine.cpu)
+        .map_err(|err| format!("VICE monitor connection failed: {}", err))?;
+    Ok(match machine.exit_code() {
+        Some(code) => (format!("program exited with status {}", code), code as i32),
+        None => ("the VICE monitor client disconnected".to_string(), 0),
+    })
+}
+
+#[cfg(feature = "dap")]
+fn address_argument(arguments: &serde_json::Value, name: &str) -> Result<Option<u16>, String> {
+    match arguments[name] {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(ref text) => parse_address(text).map(Some),
+        serde_json::Value::Number(ref number) => match number.as_u64() {
+            Some(val) if val <= 0xFFFF => Ok(Some(val as u16)),
+            _ => Err(format!("invalid `{}`", name)),
+        },
+        _ => Err(format!("invalid `{}`", name)),
+    }
+}
+
+/// Builds the machine for a DAP launch request. The arguments mirror the command-line options.
+#[cfg(feature = "dap")]
+fn launch(arguments: &serde_json::Value, console: Box<Write>) -> Result<DapTarget, String> {
+    let string_argument = |name: &str| arguments[name].as_str().map(|value| value.to_string());
+    let mut options = Options::new();
+    options.rom = string_argument("program").ok_or("the launch request requires a `program`")?;
+    options.symbols = string_argument("symbols");
+    options.format = string_argument("format");
+    options.load_address = address_argument(arguments, "loadAddress")?;
+    options.exit_address = address_argument(arguments, "exitAddress")?;
+    if let Some(layout) = string_argument("layout") {
+        options.layout = parse_layout(&layout)?;
+    }
+
+    let machine = Machine::with_console(&options, console)?;
+    let exit_code = machine.semihosting.map(|device| {
+        Box::new(move || device.borrow().exit_code()) as Box<Fn() -> Option<u8>>
+    });
+    Ok(DapTarget {
+        cpu: machine.cpu,
+        symbols: machine.symbols,
+        exit_code: exit_code,
+    })
+}
+
+#[cfg(feature = "dap")]
+fn run_dap(address: &str) -> Result<(), String> {
+    let mut server = DapServer::new(Box::new(launch));
+    let result = if address == "-" {
+        server.serve(io::stdin(), io::stdout())
+    } else {
+        let listener = TcpListener::bind(address).map_err(|err| format!("failed to listen on `{}`: {}", address, err))?;
+        if let Ok(address) = listener.local_addr() {
+            eprintln!("waiting for a debug adapter client on {}", address);
+        }
+        listener
+            .accept()
+            .and_then(|(stream, _)| stream.try_clone().map(|input| (input, stream)))
+            .and_then(|(input, output)| server.serve(input, output))
+    };
+    result.map_err(|err| format!("debug adapter connection failed: {}", err))
+}
+
+#[cfg(not(feature = "dap"))]
+fn run_dap(_address: &str) -> Result<(), String> {
+    Err("--dap requires the dap feature".into())
+}
+
+fn write_disassembly(options: &Options, path: &str) -> Result<(), String> {
+    let image = load_image(options)?;
+    let start = image.segments().iter().map(|segment| segment.address).min().unwrap_or(0);
+    let end = image.segments().iter().map(|segment| segment.end_inclusive()).max().unwrap_or(0);
+    let rom = image
+        .to_rom_filled(start, end, 0xFF)
+        .map_err(|err| format!("failed to load `{}`: {}", options.rom, err))?;
+
+    let mut reassembler = Reassembler::new(start, rom)
+        .map_err(|err| format!("failed to load `{}`: {}", options.rom, err))?
+        .with_symbols(load_symbols(options)?);
+    if let Some(entry_point) = image.entry_point() {
+        reassembler = reassembler.entry_point(entry_point);
+    }
+    if let Some(ref cdl_path) = options.disassemble_cdl {
+        let logger = File::open(cdl_path)
+            .and_then(|mut file| CodeDataLogger::read_cdl(&mut file))
+            .map_err(|err| format!("failed to read `{}`: {}", cdl_path, err))?;
+        reassembler = reassembler.with_code_data_log(&logger);
+    }
+    reassembler
+        .write_ca65(&mut open_output(path)?)
+        .map_err(|err| format!("failed to write `{}`: {}", path, err))
+}
+
+fn run(options: Options) -> Result<i32, String> {
+    if let Some(ref path) = options.disassemble {
+        write_disassembly(&options, path)?;
+        return Ok(0);
+    }
+    if let RamFill::Random(seed) = options.ram_fill {
+        eprintln!("filling RAM with random seed {}", seed);
+    }
+
+    let mut machine = Machine::new(&options)?;
+    let profiler = if options.profile.is_some() || options.profile_folded.is_some() {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        machine.cpu.add_instruction_observer(profiler.clone());
+        Some(profiler)
+    } else {
+        None
+    };
+    let sanitizer = if options.sanitize {
+        let sanitizer = machine
+            .ram
+            .iter()
+            .fold(Sanitizer::new(), |sanitizer, &(start, end)| sanitizer.ram(start, end));
+        let sanitizer = sanitizer.attach(&mut machine.cpu);
+        for segment in machine.image.segments() {
+            sanitizer
+                .borrow_mut()
+                .initialize(segment.address, segment.end_inclusive());
+        }
+        Some(sanitizer)
+    } else {
+        None
+    };
+    let diagnostics = if options.diagnose {
+        let mut diagnostics = Diagnostics::new();
+        for &(start, end) in &machine.rom {
+            diagnostics = diagnostics.read_only(start, end);
+        }
+        for &(start, end) in &options.no_execute {
+            diagnostics = diagnostics.non_executable(start, end);
+        }
+        if let Some(bytes) = options.max_stack {
+            diagnostics = diagnostics.max_stack(bytes);
+        }
+        if let Some(depth) = options.max_call_depth {
+            diagnostics = diagnostics.max_call_depth(depth);
+        }
+        Some(diagnostics.attach(&mut machine.cpu))
+    } else {
+        None
+    };
+    let logger = if options.cdl.is_some() || options.lcov.is_some() {
+        let logger = Rc::new(RefCell::new(CodeDataLogger::new()));
+        machine.cpu.add_instruction_observer(logger.clone());
+        Some(logger)
+    } else {
+        None
+    };
+
+    let (reason, status) = if options.monitor {
+        run_monitor(&mut machine)?
+    } else if let Some(ref address) = options.vice_monitor {
+        run_vice_monitor(&mut machine, address)?
+    } else {
+        run_headless(&mut machine, &options, diagnostics.as_ref())?
+    };
+
+    if let Some(ref path) = options.dump {
+        write_dump(&machine, &options, path)?;
+    }
+    if let Some(ref path) = options.screen {
+        write_screen(&machine, path)?;
+    }
+    if let Some(profiler) = profiler {
+        write_profile(&profiler.borrow(), &machine, &options)?;
+    }
+    if let Some(logger) = logger {
+        write_coverage(&logger.borrow(), &machine, &options)?;
+    }
+    if let Some(diagnostics) = diagnostics {
+        diagnostics
+            .borrow()
+            .write_report(&mut io::stderr(), &machine.symbols)
+            .map_err(|err| format!("failed to write the diagnostics: {}", err))?;
+    }
+    if let Some(sanitizer) = sanitizer {
+        sanitizer
+            .borrow()
+            .write_report(&mut io::stderr(), &machine.symbols)
+            .map_err(|err| format!("failed to write the sanitizer report: {}", err))?;
+    }
+
+    let registers = machine.cpu.registers();
+    eprintln!(
+        "{} at {} after {} cycles\nA:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X}",
+        reason,
+        machine.symbols.describe(registers.pc),
+        machine.cpu.cycles(),
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.sp,
+        registers.status.value()
+    );
+    Ok(status)
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        print!("{}", USAGE);
+        return;
+    }
+    if let Some(ref address) = options.dap {
+        if let Err(err) = run_dap(address) {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    match run(options) {
+        Ok(status) => process::exit(status),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hassel_emu::debug::parse_number;
+use hassel_emu::emulator::RamFill;
+
+pub const USAGE: &'static str = "\
+Usage: hassel_emu [OPTIONS] ROM
+       hassel_emu --dap ADDR
+
+Loads a ROM image and runs it headless until a stop condition is reached.
+
+Image options:
+    --format FORMAT        Image format: raw, hex, srec or prg (default: from extension)
+    --load-address ADDR    Load address for raw images (default: end at $FFFF)
+    --layout LAYOUT        Memory layout: flat (64K RAM) or hassel (default: flat)
+    --symbols FILE         Load labels and source lines from a ca65 .dbg, VICE
+                           label file, or `label = $addr` list
+
+Stop conditions:
+    --cycles N             Stop after executing N cycles. Without any of these
+                           stop conditions, the run stops after 100000000 cycles
+                           with exit status 124.
+    --until ADDR           Stop when the program counter reaches ADDR (or a label)
+    --exit-address ADDR    Map a semihosting device at ADDR (flat layout only).
+                           Writing to ADDR exits with the written status, and
+                           writing to ADDR+1 prints a character to stdout.
+
+Output options:
+    --trace FILE           Write an instruction trace to FILE (`-` for stdout)
+    --dump FILE            Write memory to FILE after stopping
+    --dump-range START-END Range of memory to dump (default: $0000-$FFFF)
+    --screen FILE          Write the Hassel screen to FILE after stopping, as a
+                           PPM image if FILE ends in .ppm, and as text otherwise
+    --profile FILE         Write a profile of cycles per routine, address and op-code
+    --profile-folded FILE  Write cycles per call stack in the folded format used by
+                           flamegraph tools
+    --cdl FILE             Write a code/data log with one byte of access flags per
+                           address (see the README for the bits)
+    --lcov FILE            Write an lcov coverage report of the source lines in
+                           the --symbols file
+    --disassemble FILE     Write ca65 source for the ROM to FILE instead of running,
+                           tracing code from the vectors and the image's entry point
+    --disassemble-cdl FILE Also trace from the code executed in a --cdl log
+    --sanitize             Report reads of RAM that hasn't been written yet
+    --ram-fill FILL        Fill RAM at power on with `zero` (the default), a byte
+                           value, `random`, or `random:SEED` to repeat a run
+    --diagnose             Stop on stack overflow or underflow, infinite loops and
+                           writes to ROM, and report what happened
+    --max-stack N          Also stop when more than N bytes are on the stack
+    --max-call-depth N     Also stop when calls are nested more than N deep
+    --no-execute START-END Also stop when executing from START-END (repeatable)
+    --monitor              Start the interactive monitor instead of running
+    --vice-monitor ADDR    Wait for a VICE binary monitor client on ADDR (such
+                           as 127.0.0.1:6502) instead of running
+    --dap ADDR             Serve the Debug Adapter Protocol on ADDR, or on stdin
+                           and stdout if ADDR is `-`. The ROM and the options
+                           above come from the launch request instead.
+    -h, --help             Show this message
+
+Addresses can be written as $E000, 0xE000 or decimal.
+";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layout {
+    Flat,
+    Hassel,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub rom: String,
+    pub format: Option<String>,
+    pub load_address: Option<u16>,
+    pub layout: Layout,
+    pub symbols: Option<String>,
+    pub max_cycles: Option<usize>,
+    pub until: Option<String>,
+    pub exit_address: Option<u16>,
+    pub trace: Option<String>,
+    pub dump: Option<String>,
+    pub dump_range: (u16, u16),
+    pub screen: Option<String>,
+    pub profile: Option<String>,
+    pub profile_folded: Option<String>,
+    pub cdl: Option<String>,
+    pub lcov: Option<String>,
+    pub disassemble: Option<String>,
+    pub disassemble_cdl: Option<String>,
+    pub sanitize: bool,
+    pub ram_fill: RamFill,
+    pub diagnose: bool,
+    pub max_stack: Option<usize>,
+    pub max_call_depth: Option<usize>,
+    pub no_execute: Vec<(u16, u16)>,
+    pub monitor: bool,
+    pub vice_monitor: Option<String>,
+    pub dap: Option<String>,
+    pub help: bool,
+}
+
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    match parse_number(text) {
+        Some(val) if val <= 0xFFFF => Ok(val as u16),
+        _ => Err(format!("invalid address `{}`", text)),
+    }
+}
+
+pub fn parse_layout(text: &str) -> Result<Layout, String> {
+    match text {
+        "flat" => Ok(Layout::Flat),
+        "hassel" => Ok(Layout::Hassel),
+        _ => Err(format!("unknown layout `{}`", text)),
+    }
+}
+
+fn parse_ram_fill(text: &str) -> Result<RamFill, String> {
+    if text == "zero" {
+        return Ok(RamFill::Zero);
+    }
+    if text == "random" {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| err.to_string())?;
+        return Ok(RamFill::Random(now.as_secs() ^ now.subsec_nanos() as u64));
+    }
+    if text.starts_with("random:") {
+        return text[7..]
+            .parse()
+            .map(RamFill::Random)
+            .map_err(|_| format!("invalid seed in `{}`", text));
+    }
+    match parse_number(text) {
+        Some(val) if val <= 0xFF => Ok(RamFill::Value(val as u8)),
+        _ => Err(format!("invalid RAM fill `{}`", text)),
+    }
+}
+
+fn parse_range(text: &str) -> Result<(u16, u16), String> {
+    let mut parts = text.splitn(2, '-');
+    match (parts.next(), parts.next()) {
+        (Some(start), Some(end)) => {
+            let (start, end) = (parse_address(start)?, parse_address(end)?);
+            if end < start {
+                return Err(format!("range `{}` ends before it starts", text));
+            }
+            Ok((start, end))
+        }
+        _ => Err(format!("invalid range `{}`, expected START-END", text)),
+    }
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            rom: String::new(),
+            format: None,
+            load_address: None,
+            layout: Layout::Flat,
+            symbols: None,
+            max_cycles: None,
+            until: None,
+            exit_address: None,
+            trace: None,
+            dump: None,
+            dump_range: (0x0000, 0xFFFF),
+            screen: None,
+            profile: None,
+            profile_folded: None,
+            cdl: None,
+            lcov: None,
+            disassemble: None,
+            disassemble_cdl: None,
+            sanitize: false,
+            ram_fill: RamFill::Zero,
+            diagnose: false,
+            max_stack: None,
+            max_call_depth: None,
+            no_execute: Vec::new(),
+            monitor: false,
+            vice_monitor: None,
+            dap: None,
+            help: false,
+        }
+    }
+
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::new();
+
+        let mut rom = None;
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                options.help = true;
+                continue;
+            }
+            if arg == "--monitor" {
+                options.monitor = true;
+                continue;
+            }
+            if arg == "--sanitize" {
+                options.sanitize = true;
+                continue;
+            }
+            if arg == "--diagnose" {
+                options.diagnose = true;
+                continue;
+            }
+            if !arg.starts_with("--") {
+                if rom.is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                rom = Some(arg);
+                continue;
+            }
+
+            let value = args.next()
+                .ok_or_else(|| format!("missing value for `{}`", arg))?;
+            match arg.as_str() {
+                "--format" => options.format = Some(value),
+                "--load-address" => options.load_address = Some(parse_address(&value)?),
+                "--layout" => options.layout = parse_layout(&value)?,
+                "--symbols" => options.symbols = Some(value),
+                "--cycles" => {
+                    options.max_cycles = Some(value
+                        .parse()
+                        .map_err(|_| format!("invalid cycle count `{}`", value))?)
+                }
+                "--until" => options.until = Some(value),
+                "--exit-address" => options.exit_address = Some(parse_address(&value)?),
+                "--trace" => options.trace = Some(value),
+                "--dump" => options.dump = Some(value),
+                "--dump-range" => options.dump_range = parse_range(&value)?,
+                "--screen" => options.screen = Some(value),
+                "--profile" => options.profile = Some(value),
+                "--profile-folded" => options.profile_folded = Some(value),
+                "--cdl" => options.cdl = Some(value),
+                "--lcov" => options.lcov = Some(value),
+                "--disassemble" => options.disassemble = Some(value),
+                "--disassemble-cdl" => options.disassemble_cdl = Some(value),
+                "--ram-fill" => options.ram_fill = parse_ram_fill(&value)?,
+                "--max-stack" => {
+                    options.diagnose = true;
+                    options.max_stack = Some(value
+                        .parse()
+                        .map_err(|_| format!("invalid stack size `{}`", value))?)
+                }
+                "--max-call-depth" => {
+                    options.diagnose = true;
+                    options.max_call_depth = Some(value
+                        .parse()
+                        .map_err(|_| format!("invalid call depth `{}`", value))?)
+                }
+                "--no-execute" => {
+                    options.diagnose = true;
+                    options.no_execute.push(parse_range(&value)?);
+                }
+                "--vice-monitor" => options.vice_monitor = Some(value),
+                "--dap" => options.dap = Some(value),
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        if !options.help && options.dap.is_none() {
+            options.rom = rom.ok_or("no ROM given")?;
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = parse(&["--layout", "hassel", "--until", "$E000", "--dump-range", "$10-$1F", "--monitor", "rom.hex"]).unwrap();
+        assert_eq!("rom.hex", options.rom);
+        assert_eq!(Layout::Hassel, options.layout);
+        assert_eq!(Some("$E000".to_string()), options.until);
+        assert_eq!((0x10, 0x1F), options.dump_range);
+        assert!(options.monitor);
+
+        assert!(parse(&["--cycles", "lots", "rom.bin"]).is_err());
+        assert!(parse(&["--dump-range", "$20-$10", "rom.bin"]).is_err());
+        assert!(parse(&["--layout", "flat"]).is_err());
+        assert!(parse(&["--dap", "-"]).is_ok());
+
+        assert_eq!(RamFill::Random(42), parse(&["--ram-fill", "random:42", "rom.bin"]).unwrap().ram_fill);
+        assert_eq!(RamFill::Value(0xEA), parse(&["--ram-fill", "$EA", "rom.bin"]).unwrap().ram_fill);
+        assert!(parse(&["--ram-fill", "garbage", "rom.bin"]).is_err());
+
+        let options = parse(&["--no-execute", "$0200-$02FF", "--no-execute", "$10-$1F", "rom.bin"]).unwrap();
+        assert!(options.diagnose);
+        assert_eq!(vec![(0x0200, 0x02FF), (0x10, 0x1F)], options.no_execute);
+    }
+}
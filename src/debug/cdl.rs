@@ -0,0 +1,294 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use hassel_lib6502::{OpAddressMode, OpClass, OpCode};
+
+use debug::symbols::SymbolTable;
+use emulator::{CallFrame, InstructionObserver, ReadMemory, Registers};
+
+/// The address was executed as the first byte of an instruction
+pub const CDL_CODE: u8 = 0x01;
+/// The address was read as an instruction operand
+pub const CDL_OPERAND: u8 = 0x02;
+/// The address was read as data by a load, compare, arithmetic or stack instruction
+pub const CDL_DATA: u8 = 0x04;
+/// The address was written to
+pub const CDL_WRITTEN: u8 = 0x08;
+/// The address was read as part of a pointer by an indirect JMP, an indirect
+/// addressing mode, or BRK
+pub const CDL_INDIRECT: u8 = 0x10;
+
+const STACK_ADDR: u16 = 0x0100;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+fn stack_address(sp: u8, offset: i16) -> u16 {
+    STACK_ADDR + (sp as i16 + offset) as u8 as u16
+}
+
+/// Records how every address was accessed by executed instructions, and how often
+/// each instruction ran. The result can be saved as a CDL-style byte map with one
+/// byte of `CDL_*` flags per address, or as an lcov coverage report.
+/// Register it on a CPU with `Cpu::add_instruction_observer`.
+///
+/// Accesses are worked out from the instructions themselves, so interrupts taken
+/// by the hardware (as opposed to BRK) don't mark their stack writes or vectors.
+pub struct CodeDataLogger {
+    flags: Vec<u8>,
+    counts: Vec<u64>,
+}
+
+impl CodeDataLogger {
+    pub fn new() -> CodeDataLogger {
+        CodeDataLogger {
+            flags: vec![0; 0x10000],
+            counts: vec![0; 0x10000],
+        }
+    }
+
+    /// Loads a byte map written by `write_cdl` so that another run can add to it.
+    /// Execution counts aren't saved in the map, so they start from zero.
+    pub fn read_cdl<R: Read>(input: &mut R) -> io::Result<CodeDataLogger> {
+        let mut logger = CodeDataLogger::new();
+        input.read_exact(&mut logger.flags)?;
+        Ok(logger)
+    }
+
+    /// Returns the `CDL_*` flags recorded for the given address
+    pub fn flags(&self, address: u16) -> u8 {
+        self.flags[address as usize]
+    }
+
+    /// Returns the number of times the instruction at the given address was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// Marks an address with the given flags
+    pub fn mark(&mut self, address: u16, flags: u8) {
+        self.flags[address as usize] |= flags;
+    }
+
+    /// Writes the byte map, one byte of flags for each address from $0000 to $FFFF
+    pub fn write_cdl<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.flags)
+    }
+
+    /// Writes an lcov tracefile with the hit count of every source line in the
+    /// symbol table. A line's count is the highest execution count of the
+    /// instructions it generated. Lines that were only ever accessed as data are
+    /// left out, but lines that were never touched at all are reported as not hit,
+    /// since there's no telling unexecuted code from unused data.
+    pub fn write_lcov<W: Write>(&self, output: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        let mut files: BTreeMap<&str, BTreeMap<usize, u64>> = BTreeMap::new();
+        for (start, end_inclusive, source) in symbols.lines() {
+            let range = start as usize..end_inclusive as usize + 1;
+            let any_flags = self.flags[range.clone()].iter().fold(0, |acc, flags| acc | flags);
+            if any_flags != 0 && any_flags & CDL_CODE == 0 {
+                continue;
+            }
+            let hits = range
+                .filter(|&address| self.flags[address] & CDL_CODE != 0)
+                .map(|address| self.counts[address])
+                .max()
+                .unwrap_or(0);
+            let line = files
+                .entry(source.file.as_str())
+                .or_insert_with(BTreeMap::new)
+                .entry(source.line)
+                .or_insert(0);
+            *line = ::std::cmp::max(*line, hits);
+        }
+
+        for (file, lines) in files {
+            writeln!(output, "TN:")?;
+            writeln!(output, "SF:{}", file)?;
+            for (line, hits) in &lines {
+                writeln!(output, "DA:{},{}", line, hits)?;
+            }
+            writeln!(output, "LF:{}", lines.len())?;
+            writeln!(output, "LH:{}", lines.values().filter(|&&hits| hits > 0).count())?;
+            writeln!(output, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Works out the effective address of the instruction's memory operand, and
+    /// marks the pointer bytes used to find it
+    fn effective_address(
+        &mut self,
+        code: &OpCode,
+        operand: u16,
+        registers: &Registers,
+        memory: &ReadMemory,
+    ) -> Option<u16> {
+        let zero_page = |offset: u8| offset as u16;
+        match code.address_mode {
+            OpAddressMode::Implied | OpAddressMode::Immediate | OpAddressMode::PCOffset => None,
+            OpAddressMode::Absolute => Some(operand),
+            OpAddressMode::AbsoluteOffsetX => Some(operand.wrapping_add(registers.x as u16)),
+            OpAddressMode::AbsoluteOffsetY => Some(operand.wrapping_add(registers.y as u16)),
+            OpAddressMode::ZeroPage => Some(zero_page(operand as u8)),
+            OpAddressMode::ZeroPageOffsetX => Some(zero_page((operand as u8).wrapping_add(registers.x))),
+            OpAddressMode::ZeroPageOffsetY => Some(zero_page((operand as u8).wrapping_add(registers.y))),
+            OpAddressMode::Indirect => {
+                self.mark(operand, CDL_INDIRECT);
+                self.mark(operand.wrapping_add(1), CDL_INDIRECT);
+                Some(memory.word(operand))
+            }
+            OpAddressMode::PreIndirectX => {
+                let pointer = (operand as u8).wrapping_add(registers.x);
+                self.mark(zero_page(pointer), CDL_INDIRECT);
+                self.mark(zero_page(pointer.wrapping_add(1)), CDL_INDIRECT);
+                Some(memory.word_zero_page(pointer))
+            }
+            OpAddressMode::PostIndirectY => {
+                let pointer = operand as u8;
+                self.mark(zero_page(pointer), CDL_INDIRECT);
+                self.mark(zero_page(pointer.wrapping_add(1)), CDL_INDIRECT);
+                Some(memory.word_zero_page(pointer).wrapping_add(registers.y as u16))
+            }
+        }
+    }
+}
+
+impl InstructionObserver for CodeDataLogger {
+    fn executing(&mut self, registers: &Registers, memory: &ReadMemory) {
+        let pc = registers.pc;
+        self.mark(pc, CDL_CODE);
+        let code = match OpCode::from_value(memory.byte(pc)) {
+            Some(code) => code,
+            None => return,
+        };
+        for offset in 1..code.len as u16 {
+            self.mark(pc.wrapping_add(offset), CDL_OPERAND);
+        }
+
+        let operand = match code.len {
+            2 => memory.byte(pc.wrapping_add(1)) as u16,
+            3 => memory.word(pc.wrapping_add(1)),
+            _ => 0,
+        };
+        let address = self.effective_address(&code, operand, registers, memory);
+        let sp = registers.sp;
+        match code.class {
+            OpClass::Adc | OpClass::And | OpClass::Bit | OpClass::Cmp | OpClass::Cpx | OpClass::Cpy | OpClass::Eor
+            | OpClass::Lda | OpClass::Ldx | OpClass::Ldy | OpClass::Ora | OpClass::Sbc => {
+                if let Some(address) = address {
+                    self.mark(address, CDL_DATA);
+                }
+            }
+            OpClass::Sta | OpClass::Stx | OpClass::Sty => {
+                if let Some(address) = address {
+                    self.mark(address, CDL_WRITTEN);
+                }
+            }
+            OpClass::Asl | OpClass::Lsr | OpClass::Rol | OpClass::Ror | OpClass::Inc | OpClass::Dec => {
+                if let Some(address) = address {
+                    self.mark(address, CDL_DATA | CDL_WRITTEN);
+                }
+            }
+            OpClass::Pha | OpClass::Php => self.mark(stack_address(sp, 0), CDL_WRITTEN),
+            OpClass::Pla | OpClass::Plp => self.mark(stack_address(sp, 1), CDL_DATA),
+            OpClass::Jsr => {
+                self.mark(stack_address(sp, 0), CDL_WRITTEN);
+                self.mark(stack_address(sp, -1), CDL_WRITTEN);
+            }
+            OpClass::Rts => {
+                self.mark(stack_address(sp, 1), CDL_DATA);
+                self.mark(stack_address(sp, 2), CDL_DATA);
+            }
+            OpClass::Rti => {
+                for offset in 1..4 {
+                    self.mark(stack_address(sp, offset), CDL_DATA);
+                }
+            }
+            OpClass::Brk => {
+                for offset in 0..3 {
+                    self.mark(stack_address(sp, -offset), CDL_WRITTEN);
+                }
+                self.mark(IRQ_VECTOR, CDL_INDIRECT);
+                self.mark(IRQ_VECTOR + 1, CDL_INDIRECT);
+            }
+            _ => {}
+        }
+    }
+
+    fn executed(&mut self, pc: u16, _op_code: u8, _cycles: usize, _frames: &[CallFrame]) {
+        self.counts[pc as usize] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use debug::symbols::SourceLine;
+    use test_support::{load_program, run_to_label};
+
+    #[test]
+    fn test_code_data_log() {
+        let (mut cpu, program) = load_program(
+            "start: LDA #<table
+                    STA $10
+                    LDA #>table
+                    STA $11
+                    LDY #1
+                    LDA ($10),Y
+                    JSR store
+             halt:  JMP halt
+             store: INC $0300
+                    RTS
+             table: .byte $01, $02
+             .reset start",
+        );
+
+        let logger = Rc::new(RefCell::new(CodeDataLogger::new()));
+        cpu.add_instruction_observer(logger.clone());
+        run_to_label(&mut cpu, &program, "halt");
+        let symbols = program.symbols();
+
+        let logger = logger.borrow();
+        let start = symbols.address_of("start").unwrap();
+        let table = symbols.address_of("table").unwrap();
+        assert_eq!(CDL_CODE, logger.flags(start));
+        assert_eq!(CDL_OPERAND, logger.flags(start + 1));
+        assert_eq!(CDL_WRITTEN | CDL_INDIRECT, logger.flags(0x0010));
+        assert_eq!(0, logger.flags(table));
+        assert_eq!(CDL_DATA, logger.flags(table + 1));
+        assert_eq!(CDL_DATA | CDL_WRITTEN, logger.flags(0x0300));
+        assert_eq!(CDL_WRITTEN | CDL_DATA, logger.flags(0x01FF));
+        assert_eq!(0, logger.flags(symbols.address_of("halt").unwrap()));
+        assert_eq!(1, logger.count(symbols.address_of("store").unwrap()));
+
+        let mut cdl = Vec::new();
+        logger.write_cdl(&mut cdl).unwrap();
+        assert_eq!(0x10000, cdl.len());
+        let reloaded = CodeDataLogger::read_cdl(&mut &cdl[..]).unwrap();
+        assert_eq!(logger.flags(0x0010), reloaded.flags(0x0010));
+
+        let mut lines = SymbolTable::new();
+        let source = |line| SourceLine {
+            file: "test.s".into(),
+            line: line,
+        };
+        lines.add_line(start, start + 1, source(1));
+        lines.add_line(symbols.address_of("halt").unwrap(), table - 1, source(8));
+        lines.add_line(table, table + 1, source(11));
+        let mut lcov = Vec::new();
+        logger.write_lcov(&mut lcov, &lines).unwrap();
+        assert_eq!(
+            "TN:\nSF:test.s\nDA:1,1\nDA:8,1\nLF:2\nLH:2\nend_of_record\n",
+            String::from_utf8(lcov).unwrap()
+        );
+    }
+}
@@ -0,0 +1,779 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! A Debug Adapter Protocol server, so that editors such as VS Code can debug
+//! programs running in the emulator. The server exposes a single thread, maps
+//! source breakpoints through the symbol table's line information, and shows
+//! the registers, flags, zero page and stack as variables.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{self, Value};
+
+use debug::symbols::{parse_number, SourceLine, SymbolTable};
+use emulator::{Cpu, StepTarget};
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const FLAGS_REFERENCE: u64 = 2;
+const ZERO_PAGE_REFERENCE: u64 = 3;
+const STACK_REFERENCE: u64 = 4;
+
+/// Number of instructions to execute between checks for incoming requests while running
+const POLL_INTERVAL: usize = 1000;
+
+const BASE64_CHARS: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bits = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_CHARS[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Reads one `Content-Length` framed message. Returns None at the end of the input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if line.to_lowercase().starts_with("content-length:") {
+            length = line["content-length:".len()..].trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0u8; length.unwrap()];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// The program being debugged, as created by the launcher
+pub struct DapTarget {
+    pub cpu: Cpu,
+    pub symbols: SymbolTable,
+    /// Returns the program's exit status once it has finished, for
+    /// systems that have a way to exit such as semihosting
+    pub exit_code: Option<Box<Fn() -> Option<u8>>>,
+}
+
+/// Creates the target from the `launch` request's arguments. Output written
+/// to the console is shown in the editor's debug console.
+pub type Launcher = Box<FnMut(&Value, Box<Write>) -> Result<DapTarget, String>>;
+
+/// Writer that collects console output until it can be sent as an output event
+struct Console(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum RunMode {
+    Continue,
+    Step {
+        line: Option<SourceLine>,
+        over: bool,
+        target: Option<StepTarget>,
+    },
+    /// Runs until the innermost call returns, or like `Continue` outside of any call
+    StepOut { target: Option<StepTarget> },
+}
+
+impl RunMode {
+    fn stop_reason(&self) -> &'static str {
+        match *self {
+            RunMode::Continue => "pause",
+            _ => "step",
+        }
+    }
+}
+
+/// Executes one instruction and returns true if that completes the run mode
+fn step(cpu: &mut Cpu, symbols: &SymbolTable, mode: &mut RunMode) -> bool {
+    match *mode {
+        RunMode::Continue | RunMode::StepOut { target: None } => {
+            cpu.step();
+            false
+        }
+        RunMode::Step {
+            ref line,
+            over,
+            ref mut target,
+        } => {
+            if over {
+                let goal = target.take().unwrap_or_else(|| cpu.step_over_target());
+                if !cpu.step_toward(goal) {
+                    *target = Some(goal);
+                    return false;
+                }
+            } else {
+                cpu.step();
+            }
+            // Without line information, step by instruction. Otherwise, keep going
+            // until reaching a different line that has source information.
+            match *line {
+                None => true,
+                Some(ref line) => match symbols.source_line(cpu.registers().pc) {
+                    Some(current) => current != line,
+                    None => false,
+                },
+            }
+        }
+        RunMode::StepOut { target: Some(target) } => cpu.step_toward(target),
+    }
+}
+
+enum Flow {
+    Continue,
+    Disconnect,
+}
+
+struct Session<'a, W: Write> {
+    launcher: &'a mut Launcher,
+    output: W,
+    seq: u64,
+    target: Option<DapTarget>,
+    console: Rc<RefCell<Vec<u8>>>,
+    source_breakpoints: BTreeMap<String, Vec<u16>>,
+    function_breakpoints: Vec<u16>,
+    breakpoints: BTreeSet<u16>,
+    stop_on_entry: bool,
+    lines_start_at_1: bool,
+    source_root: Option<PathBuf>,
+    running: Option<RunMode>,
+}
+
+impl<'a, W: Write> Session<'a, W> {
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = serde_json::to_vec(&message).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        write!(self.output, "Content-Length: {}\r\n\r\n", body.len())?;
+        self.output.write_all(&body)?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+
+    fn flush_console(&mut self) -> io::Result<()> {
+        let text = String::from_utf8_lossy(&self.console.borrow()).into_owned();
+        self.console.borrow_mut().clear();
+        if text.is_empty() {
+            Ok(())
+        } else {
+            self.event("output", json!({ "category": "stdout", "output": text }))
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.running = None;
+        self.flush_console()?;
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn target(&mut self) -> Result<&mut DapTarget, String> {
+        self.target
+            .as_mut()
+            .ok_or_else(|| "no program has been launched".to_string())
+    }
+
+    fn client_line(&self, line: usize) -> usize {
+        if self.lines_start_at_1 {
+            line
+        } else {
+            line.saturating_sub(1)
+        }
+    }
+
+    fn source(&self, source: &SourceLine) -> Value {
+        let path = match self.source_root {
+            Some(ref root) if Path::new(&source.file).is_relative() => root.join(&source.file),
+            _ => PathBuf::from(&source.file),
+        };
+        json!({
+            "name": path.file_name().map(|name| name.to_string_lossy().into_owned()),
+            "path": path.to_string_lossy(),
+        })
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.breakpoints = self.source_breakpoints
+            .values()
+            .flat_map(|addresses| addresses.iter().cloned())
+            .chain(self.function_breakpoints.iter().cloned())
+            .collect();
+    }
+
+    /// Runs the CPU for a while and reports whatever stopped it
+    fn run(&mut self) -> io::Result<()> {
+        let mut mode = match self.running.take() {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+
+        let mut reason = None;
+        let mut exit_code = None;
+        {
+            let target = self.target.as_mut().unwrap();
+            for _ in 0..POLL_INTERVAL {
+                let finished = step(&mut target.cpu, &target.symbols, &mut mode);
+                if let Some(ref exit_code_fn) = target.exit_code {
+                    exit_code = exit_code_fn();
+                    if exit_code.is_some() {
+                        break;
+                    }
+                }
+                if self.breakpoints.contains(&target.cpu.registers().pc) {
+                    reason = Some("breakpoint");
+                    break;
+                }
+                if finished {
+                    reason = Some(mode.stop_reason());
+                    break;
+                }
+            }
+        }
+
+        if let Some(code) = exit_code {
+            self.flush_console()?;
+            self.event("exited", json!({ "exitCode": code }))?;
+            return self.event("terminated", json!({}));
+        }
+        match reason {
+            Some(reason) => self.stopped(reason),
+            None => {
+                self.running = Some(mode);
+                self.flush_console()
+            }
+        }
+    }
+
+    fn resume(&mut self, mode: RunMode) -> Result<Value, String> {
+        self.target()?;
+        self.running = Some(mode);
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn step_mode(&mut self, over: bool) -> Result<RunMode, String> {
+        let target = self.target()?;
+        let pc = target.cpu.registers().pc;
+        Ok(RunMode::Step {
+            line: target.symbols.source_line(pc).cloned(),
+            over: over,
+            target: None,
+        })
+    }
+
+    fn initialize(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.lines_start_at_1 = arguments["linesStartAt1"].as_bool().unwrap_or(true);
+        Ok(json!({
+            "supportsConfigurationDoneRequest": true,
+            "supportsFunctionBreakpoints": true,
+            "supportsReadMemoryRequest": true,
+        }))
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let console = Box::new(Console(self.console.clone()));
+        let target = (self.launcher)(arguments, console)?;
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.source_root = arguments["sourceRoot"].as_str().map(PathBuf::from);
+        self.target = Some(target);
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"]
+            .as_str()
+            .ok_or_else(|| "setBreakpoints requires a source path".to_string())?
+            .to_string();
+        let lines_start_at_1 = self.lines_start_at_1;
+        let requested: Vec<u64> = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        {
+            let symbols = &self.target()?.symbols;
+            for line in requested {
+                let debug_line = if lines_start_at_1 { line } else { line + 1 } as usize;
+                let found = symbols.addresses_for_line(&path, debug_line);
+                results.push(match found.first() {
+                    Some(address) => json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("0x{:04X}", address),
+                    }),
+                    None => json!({
+                        "verified": false,
+                        "line": line,
+                        "message": "no code was generated for this line",
+                    }),
+                });
+                addresses.extend(found);
+            }
+        }
+        self.source_breakpoints.insert(path, addresses);
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        {
+            let symbols = &self.target()?.symbols;
+            for breakpoint in arguments["breakpoints"].as_array().unwrap_or(&Vec::new()) {
+                let name = breakpoint["name"].as_str().unwrap_or("");
+                let address = symbols.address_of(name).or_else(|| {
+                    parse_number(name)
+                        .and_then(|val| if val <= 0xFFFF { Some(val as u16) } else { None })
+                });
+                results.push(json!({ "verified": address.is_some() }));
+                addresses.extend(address);
+            }
+        }
+        self.function_breakpoints = addresses;
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        // The current location, followed by the call site of each frame on the shadow call stack
+        let locations: Vec<(u16, String, Option<SourceLine>)> = {
+            let target = self.target()?;
+            let pc = target.cpu.registers().pc;
+            let call_sites = target.cpu.backtrace().into_iter().map(|frame| frame.call_site);
+            ::std::iter::once(pc)
+                .chain(call_sites)
+                .map(|address| {
+                    (
+                        address,
+                        target.symbols.format_address(address),
+                        target.symbols.source_line(address).cloned(),
+                    )
+                })
+                .collect()
+        };
+
+        let mut frames = Vec::new();
+        for (id, (address, name, source)) in locations.into_iter().enumerate() {
+            let mut frame = json!({
+                "id": id,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("0x{:04X}", address),
+            });
+            if let Some(source) = source {
+                frame["source"] = self.source(&source);
+                frame["line"] = json!(self.client_line(source.line));
+                frame["column"] = json!(self.client_line(1));
+            }
+            frames.push(frame);
+        }
+        Ok(json!({ "totalFrames": frames.len(), "stackFrames": frames }))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        fn variable(name: String, value: String) -> Value {
+            json!({ "name": name, "value": value, "variablesReference": 0 })
+        }
+        fn memory_variable(name: String, value: String, address: u16) -> Value {
+            json!({
+                "name": name,
+                "value": value,
+                "variablesReference": 0,
+                "memoryReference": format!("0x{:04X}", address),
+            })
+        }
+
+        let cpu = &self.target()?.cpu;
+        let registers = *cpu.registers();
+        let memory = cpu.memory().debug_read();
+        let variables = match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => vec![
+                variable("A".into(), format!("${:02X}", registers.a)),
+                variable("X".into(), format!("${:02X}", registers.x)),
+                variable("Y".into(), format!("${:02X}", registers.y)),
+                variable("SP".into(), format!("${:02X}", registers.sp)),
+                variable("PC".into(), format!("${:04X}", registers.pc)),
+                variable("P".into(), format!("${:02X}", registers.status.value())),
+            ],
+            Some(FLAGS_REFERENCE) => {
+                let status = registers.status;
+                vec![
+                    ("N", status.negative()),
+                    ("V", status.overflow()),
+                    ("B", status.brk()),
+                    ("D", status.decimal()),
+                    ("I", status.interrupt_inhibit()),
+                    ("Z", status.zero()),
+                    ("C", status.carry()),
+                ].into_iter()
+                    .map(|(name, set)| variable(name.into(), (set as u8).to_string()))
+                    .collect()
+            }
+            Some(ZERO_PAGE_REFERENCE) => (0..16)
+                .map(|row| {
+                    let bytes: Vec<String> = (0..16)
+                        .map(|col| format!("{:02X}", memory.byte(row * 16 + col)))
+                        .collect();
+                    memory_variable(format!("${:02X}", row * 16), bytes.join(" "), row * 16)
+                })
+                .collect(),
+            Some(STACK_REFERENCE) => (registers.sp as u16 + 1..0x100)
+                .map(|offset| {
+                    let address = 0x0100 + offset;
+                    memory_variable(
+                        format!("${:04X}", address),
+                        format!("${:02X}", memory.byte(address)),
+                        address,
+                    )
+                })
+                .collect(),
+            _ => return Err("unknown variables reference".into()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn read_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let start = (arguments["memoryReference"]
+            .as_str()
+            .and_then(parse_number)
+            .ok_or_else(|| "invalid memory reference".to_string())? as i64)
+            .saturating_add(arguments["offset"].as_i64().unwrap_or(0));
+        let count = arguments["count"].as_i64().unwrap_or(0).max(0);
+        // The count comes from the client, so only walk the part that's in memory
+        let end = start.saturating_add(count).min(0x10000);
+
+        let memory = self.target()?.cpu.memory().debug_read();
+        let data: Vec<u8> = (start.max(0)..end)
+            .map(|address| memory.byte(address as u16))
+            .collect();
+        Ok(json!({
+            "address": format!("0x{:04X}", start.max(0)),
+            "data": base64(&data),
+            "unreadableBytes": count as usize - data.len(),
+        }))
+    }
+
+    fn handle(&mut self, request: Value) -> io::Result<Flow> {
+        let arguments = request["arguments"].clone();
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let result = match command.as_str() {
+            "initialize" => self.initialize(&arguments),
+            "launch" => self.launch(&arguments),
+            "setBreakpoints" => self.set_breakpoints(&arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(&arguments),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => self.target().map(|_| json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "6502" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Flags", "variablesReference": FLAGS_REFERENCE, "expensive": false },
+                { "name": "Zero Page", "variablesReference": ZERO_PAGE_REFERENCE, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+            ]})),
+            "variables" => self.variables(&arguments),
+            "readMemory" => self.read_memory(&arguments),
+            "continue" => self.resume(RunMode::Continue),
+            "next" => self.step_mode(true).and_then(|mode| self.resume(mode)),
+            "stepIn" => self.step_mode(false).and_then(|mode| self.resume(mode)),
+            "stepOut" => self.target()
+                .map(|target| target.cpu.step_out_target())
+                .and_then(|target| self.resume(RunMode::StepOut { target: target })),
+            "pause" => Ok(json!({})),
+            "disconnect" | "terminate" => Ok(json!({})),
+            other => Err(format!("unsupported request `{}`", other)),
+        };
+        let success = result.is_ok();
+        self.respond(&request, result)?;
+
+        match command.as_str() {
+            "launch" if success => self.event("initialized", json!({}))?,
+            "configurationDone" if success => {
+                if self.stop_on_entry {
+                    self.stopped("entry")?;
+                } else {
+                    self.running = Some(RunMode::Continue);
+                }
+            }
+            "pause" if self.running.is_some() => self.stopped("pause")?,
+            "disconnect" | "terminate" => return Ok(Flow::Disconnect),
+            _ => {}
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn serve(&mut self, requests: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let request = if self.running.is_some() {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(request) = request {
+                if request["type"] == "request" {
+                    if let Flow::Disconnect = self.handle(request)? {
+                        return Ok(());
+                    }
+                }
+            }
+            self.run()?;
+        }
+    }
+}
+
+/// Serves the Debug Adapter Protocol for one debug session
+pub struct DapServer {
+    launcher: Launcher,
+}
+
+impl DapServer {
+    /// Creates a server that uses the given function to handle `launch` requests
+    pub fn new(launcher: Launcher) -> DapServer {
+        DapServer { launcher: launcher }
+    }
+
+    /// Runs a debug session that reads requests from `input` and writes responses
+    /// and events to `output`, such as stdin and stdout, or both halves of a socket.
+    /// Returns when the client disconnects.
+    pub fn serve<R: Read + Send + 'static, W: Write>(&mut self, input: R, output: W) -> io::Result<()> {
+        // Requests are read on another thread so that a running program can be paused
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut session = Session {
+            launcher: &mut self.launcher,
+            output: output,
+            seq: 0,
+            target: None,
+            console: Rc::new(RefCell::new(Vec::new())),
+            source_breakpoints: BTreeMap::new(),
+            function_breakpoints: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: false,
+            lines_start_at_1: true,
+            source_root: None,
+            running: None,
+        };
+        session.serve(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use assembler::Assembler;
+    use emulator::MemoryMap;
+
+    fn request(seq: u64, command: &str, arguments: Value) -> String {
+        let body = json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }).to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn launch(_arguments: &Value, mut console: Box<Write>) -> Result<DapTarget, String> {
+        let program = Assembler::new(0x0200)
+            .source(
+                "start: LDX #1
+                        JSR count
+                        JMP start
+                 count: INX
+                        INX
+                        RTS
+                 .reset start",
+            )
+            .assemble()
+            .map_err(|err| err.to_string())?;
+        let mut memory = MemoryMap::builder().ram(0x0000, 0xFFFF).build();
+        program.image().write_to(&mut memory);
+
+        let mut symbols = program.symbols().clone();
+        for (line, address) in [0x0200, 0x0202, 0x0205, 0x0208, 0x0209, 0x020A].iter().enumerate() {
+            symbols.add_line(
+                *address,
+                *address,
+                SourceLine {
+                    file: "src/main.s".into(),
+                    line: line + 1,
+                },
+            );
+        }
+        console.write_all(b"launched\n").unwrap();
+        Ok(DapTarget {
+            cpu: Cpu::new(memory),
+            symbols: symbols,
+            exit_code: None,
+        })
+    }
+
+    fn messages(output: &[u8]) -> Vec<Value> {
+        let mut input = BufReader::new(Cursor::new(output.to_vec()));
+        let mut result = Vec::new();
+        while let Some(message) = read_message(&mut input).unwrap() {
+            result.push(message);
+        }
+        result
+    }
+
+    #[test]
+    fn test_session() {
+        let input = [
+            request(1, "initialize", json!({ "adapterID": "hassel_emu" })),
+            request(2, "launch", json!({ "program": "test.hex", "sourceRoot": "/home/user/project" })),
+            request(3, "setBreakpoints", json!({
+                "source": { "path": "/home/user/project/src/main.s" },
+                "breakpoints": [{ "line": 4 }, { "line": 99 }],
+            })),
+            request(4, "configurationDone", json!({})),
+            request(5, "next", json!({ "threadId": 1 })),
+            request(6, "stackTrace", json!({ "threadId": 1 })),
+            request(7, "stepOut", json!({ "threadId": 1 })),
+            request(8, "stackTrace", json!({ "threadId": 1 })),
+            request(9, "variables", json!({ "variablesReference": REGISTERS_REFERENCE })),
+            request(10, "readMemory", json!({ "memoryReference": "0x01FE", "count": 2 })),
+            request(11, "readMemory", json!({ "memoryReference": "0xFFFE", "count": i64::max_value() })),
+            request(12, "disconnect", json!({})),
+        ].concat();
+
+        let mut output = Vec::new();
+        let mut server = DapServer::new(Box::new(launch));
+        server.serve(Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+        let messages = messages(&output);
+        let find = |command: &str| {
+            messages
+                .iter()
+                .find(|message| message["command"] == command)
+                .unwrap()
+                .clone()
+        };
+        let stops: Vec<&Value> = messages
+            .iter()
+            .filter(|message| message["event"] == "stopped")
+            .map(|message| &message["body"]["reason"])
+            .collect();
+        assert_eq!(vec!["breakpoint", "step", "step"], stops);
+        assert!(messages.iter().any(|message| message["body"]["output"] == "launched\n"));
+
+        let breakpoints = &find("setBreakpoints")["body"]["breakpoints"];
+        assert_eq!(true, breakpoints[0]["verified"]);
+        assert_eq!(false, breakpoints[1]["verified"]);
+
+        // Stepped over INX at line 4 to line 5, called from the JSR at line 2
+        let traces: Vec<&Value> = messages
+            .iter()
+            .filter(|message| message["command"] == "stackTrace")
+            .map(|message| &message["body"]["stackFrames"])
+            .collect();
+        assert_eq!(5, traces[0][0]["line"]);
+        assert_eq!("count+1", traces[0][0]["name"]);
+        assert_eq!(2, traces[0][1]["line"]);
+        assert_eq!("0x0202", traces[0][1]["instructionPointerReference"]);
+
+        // And then out to the JMP at line 3
+        assert_eq!(1, traces[1].as_array().unwrap().len());
+        assert_eq!(3, traces[1][0]["line"]);
+        assert_eq!("/home/user/project/src/main.s", traces[1][0]["source"]["path"]);
+        assert_eq!("0x0205", traces[1][0]["instructionPointerReference"]);
+
+        let variables = &find("variables")["body"]["variables"];
+        assert_eq!("X", variables[1]["name"]);
+        assert_eq!("$03", variables[1]["value"]);
+
+        let memory = &find("readMemory")["body"];
+        assert_eq!(0, memory["unreadableBytes"]);
+        assert_eq!("BAI=", memory["data"]);
+
+        // A huge count stops at the end of memory instead of overflowing
+        let memory = &messages.iter().filter(|message| message["command"] == "readMemory").nth(1).unwrap()["body"];
+        assert_eq!(i64::max_value() - 2, memory["unreadableBytes"]);
+        assert_eq!(4, memory["data"].as_str().unwrap().len());
+    }
+}
@@ -0,0 +1,323 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use hassel_lib6502::{OpClass, OpCode};
+
+use debug::symbols::SymbolTable;
+use emulator::{AccessKind, BusObserver, CallFrame, Cpu, FrameKind, InstructionObserver, ReadMemory, Registers};
+
+/// Problems that `Diagnostics` can detect
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    /// A push wrapped the stack pointer from $00 around to $FF
+    StackOverflow,
+    /// A pull wrapped the stack pointer from $FF around to $00
+    StackUnderflow,
+    /// More bytes were on the stack than the configured limit
+    StackLimit { used: usize },
+    /// Calls were nested deeper than the configured limit
+    CallDepth { depth: usize },
+    /// An instruction was executed from a region marked as non-executable
+    NonExecutable,
+    /// A write to a read-only region, which the hardware ignores
+    RomWrite { address: u16, value: u8 },
+    /// An instruction jumped or branched to itself, such as `JMP *`
+    InfiniteLoop,
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiagnosticKind::StackOverflow => write!(f, "stack overflow"),
+            DiagnosticKind::StackUnderflow => write!(f, "stack underflow"),
+            DiagnosticKind::StackLimit { used } => write!(f, "{} bytes on the stack", used),
+            DiagnosticKind::CallDepth { depth } => write!(f, "calls nested {} deep", depth),
+            DiagnosticKind::NonExecutable => write!(f, "executing from a non-executable region"),
+            DiagnosticKind::RomWrite { address, value } => {
+                write!(f, "write of ${:02X} to ROM at ${:04X}", value, address)
+            }
+            DiagnosticKind::InfiniteLoop => write!(f, "infinite loop"),
+        }
+    }
+}
+
+/// A problem found while running a program
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Address of the instruction that caused it
+    pub pc: u16,
+}
+
+/// Watches a running program for stack misuse, runaway execution and writes to
+/// ROM. Stack wraps and infinite loops are always checked; the other checks
+/// are enabled by configuring them. Events are collected until they're taken,
+/// so a run loop can stop as soon as one shows up.
+pub struct Diagnostics {
+    max_stack: Option<usize>,
+    max_call_depth: Option<usize>,
+    executable: Vec<bool>,
+    read_only: Vec<bool>,
+    events: Vec<Diagnostic>,
+    reported_writes: HashSet<(u16, u16)>,
+    pc: u16,
+    last: Option<(u16, u8)>,
+    looping: bool,
+    depth: usize,
+    over_stack_limit: bool,
+    over_call_depth: bool,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics {
+            max_stack: None,
+            max_call_depth: None,
+            executable: vec![true; 0x10000],
+            read_only: vec![false; 0x10000],
+            events: Vec::new(),
+            reported_writes: HashSet::new(),
+            pc: 0,
+            last: None,
+            looping: false,
+            depth: 0,
+            over_stack_limit: false,
+            over_call_depth: false,
+        }
+    }
+
+    /// Reports when more than the given number of bytes are on the stack
+    pub fn max_stack(mut self, bytes: usize) -> Self {
+        self.max_stack = Some(bytes);
+        self
+    }
+
+    /// Reports when calls and interrupts are nested deeper than the given depth
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    /// Reports execution from the given range, such as RAM that should only hold data
+    pub fn non_executable(mut self, start: u16, end_inclusive: u16) -> Self {
+        for address in start as usize..end_inclusive as usize + 1 {
+            self.executable[address] = false;
+        }
+        self
+    }
+
+    /// Reports writes to the given range, such as where ROM is mapped
+    pub fn read_only(mut self, start: u16, end_inclusive: u16) -> Self {
+        for address in start as usize..end_inclusive as usize + 1 {
+            self.read_only[address] = true;
+        }
+        self
+    }
+
+    /// Registers the diagnostics with both the CPU and its memory map
+    pub fn attach(self, cpu: &mut Cpu) -> Rc<RefCell<Diagnostics>> {
+        let diagnostics = Rc::new(RefCell::new(self));
+        cpu.add_instruction_observer(diagnostics.clone());
+        cpu.memory_mut().add_bus_observer(diagnostics.clone());
+        diagnostics
+    }
+
+    /// Returns the events that haven't been taken yet
+    pub fn events(&self) -> &[Diagnostic] {
+        &self.events
+    }
+
+    /// Removes and returns the events found so far
+    pub fn take_events(&mut self) -> Vec<Diagnostic> {
+        ::std::mem::replace(&mut self.events, Vec::new())
+    }
+
+    /// Writes one line per event that hasn't been taken yet
+    pub fn write_report<W: Write>(&self, output: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        for event in &self.events {
+            writeln!(output, "{} at {}", event.kind, symbols.describe(event.pc))?;
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, kind: DiagnosticKind) {
+        let pc = self.pc;
+        self.events.push(Diagnostic { kind: kind, pc: pc });
+    }
+}
+
+impl InstructionObserver for Diagnostics {
+    fn executing(&mut self, registers: &Registers, memory: &ReadMemory) {
+        let (pc, sp) = (registers.pc, registers.sp);
+        let previous = ::std::mem::replace(&mut self.last, Some((pc, sp)));
+        self.pc = pc;
+
+        // Running the same instruction again with the same stack means nothing can change
+        // until an interrupt. Only the start of the loop is reported.
+        let looping = previous == Some((pc, sp));
+        if looping && !self.looping {
+            self.report(DiagnosticKind::InfiniteLoop);
+        }
+        self.looping = looping;
+
+        // Likewise, only entering a non-executable region is reported
+        let was_executable = previous.map(|(last_pc, _)| self.executable[last_pc as usize]);
+        if !self.executable[pc as usize] && was_executable != Some(false) {
+            self.report(DiagnosticKind::NonExecutable);
+        }
+
+        let used = 0xFF - sp as usize;
+        let over_stack_limit = self.max_stack.map(|max| used > max).unwrap_or(false);
+        if over_stack_limit && !self.over_stack_limit {
+            self.report(DiagnosticKind::StackLimit { used: used });
+        }
+        self.over_stack_limit = over_stack_limit;
+
+        let (pushed, pulled) = match OpCode::from_value(memory.byte(pc)).map(|code| code.class) {
+            Some(OpClass::Pha) | Some(OpClass::Php) => (1, 0),
+            Some(OpClass::Jsr) => (2, 0),
+            Some(OpClass::Brk) => (3, 0),
+            Some(OpClass::Pla) | Some(OpClass::Plp) => (0, 1),
+            Some(OpClass::Rts) => (0, 2),
+            Some(OpClass::Rti) => (0, 3),
+            _ => (0, 0),
+        };
+        if (sp as usize) < pushed {
+            self.report(DiagnosticKind::StackOverflow);
+        }
+        if sp as usize + pulled > 0xFF {
+            self.report(DiagnosticKind::StackUnderflow);
+        }
+    }
+
+    fn executed(&mut self, _pc: u16, _op_code: u8, _cycles: usize, frames: &[CallFrame]) {
+        // Interrupts taken after the instruction push three bytes without an instruction of their own
+        if frames.len() > self.depth {
+            if let Some(frame) = frames.last() {
+                let interrupt = frame.kind == FrameKind::Interrupt || frame.kind == FrameKind::NonMaskableInterrupt;
+                if interrupt && frame.sp < 3 {
+                    self.report(DiagnosticKind::StackOverflow);
+                }
+            }
+        }
+        self.depth = frames.len();
+
+        let over_call_depth = self.max_call_depth.map(|max| self.depth > max).unwrap_or(false);
+        if over_call_depth && !self.over_call_depth {
+            let depth = self.depth;
+            self.report(DiagnosticKind::CallDepth { depth: depth });
+        }
+        self.over_call_depth = over_call_depth;
+    }
+}
+
+impl BusObserver for Diagnostics {
+    fn access(&mut self, address: u16, value: u8, _cycle: usize, kind: AccessKind) {
+        let read_only = kind == AccessKind::Write && self.read_only[address as usize];
+        if read_only && self.reported_writes.insert((self.pc, address)) {
+            self.report(DiagnosticKind::RomWrite {
+                address: address,
+                value: value,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use emulator::MemoryMap;
+
+    fn run(source: &str, diagnostics: Diagnostics, steps: usize) -> Vec<Diagnostic> {
+        let program = Assembler::new(0xE000)
+            .source(source)
+            .source(".reset $E000")
+            .assemble()
+            .unwrap();
+        let memory = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .rom(0xE000, 0xFFFF, program.to_rom(0xE000, 0xFFFF).unwrap())
+            .build();
+        let mut cpu = Cpu::new(memory);
+        let diagnostics = diagnostics.attach(&mut cpu);
+        for _ in 0..steps {
+            cpu.step();
+        }
+        let events = diagnostics.borrow_mut().take_events();
+        events
+    }
+
+    #[test]
+    fn test_stack_diagnostics() {
+        let events = run("LDX #$01\nTXS\nPHA\nPHA\nPHA\nPLA\nPLA", Diagnostics::new().max_stack(0xF0), 7);
+        assert_eq!(
+            vec![
+                Diagnostic {
+                    kind: DiagnosticKind::StackLimit { used: 0xFE },
+                    pc: 0xE003,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::StackOverflow,
+                    pc: 0xE004,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::StackUnderflow,
+                    pc: 0xE007,
+                },
+            ],
+            events
+        );
+
+        let events = run("start: JSR start", Diagnostics::new().max_call_depth(4), 10);
+        assert_eq!(
+            vec![
+                Diagnostic {
+                    kind: DiagnosticKind::CallDepth { depth: 5 },
+                    pc: 0xE000,
+                },
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn test_runaway_diagnostics() {
+        let diagnostics = Diagnostics::new()
+            .read_only(0xE000, 0xFFFF)
+            .non_executable(0x0200, 0x02FF);
+        let events = run("LDA #$60\nSTA $0200\nSTA $E000\nJSR $0200\nhalt: JMP halt", diagnostics, 8);
+        assert_eq!(
+            vec![
+                Diagnostic {
+                    kind: DiagnosticKind::RomWrite {
+                        address: 0xE000,
+                        value: 0x60,
+                    },
+                    pc: 0xE005,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::NonExecutable,
+                    pc: 0x0200,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::InfiniteLoop,
+                    pc: 0xE00B,
+                },
+            ],
+            events
+        );
+    }
+}
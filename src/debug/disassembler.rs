@@ -0,0 +1,156 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use hassel_lib6502::{OpAddressMode, OpCode};
+
+use assembler::mnemonic;
+use debug::symbols::SymbolTable;
+use emulator::ReadMemory;
+
+/// A single decoded instruction
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    /// Address of the op-code
+    pub address: u16,
+    /// The raw bytes of the instruction, including the op-code
+    pub bytes: Vec<u8>,
+    /// The decoded op-code, or None if the byte at `address` isn't a valid op-code
+    pub code: Option<OpCode>,
+}
+
+impl Instruction {
+    /// Returns the instruction length in bytes
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the address of the following instruction
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.len() as u16)
+    }
+
+    /// Returns the operand as a 16-bit value, or 0 if there isn't one
+    pub fn operand(&self) -> u16 {
+        match self.bytes.len() {
+            2 => self.bytes[1] as u16,
+            3 => (self.bytes[2] as u16) << 8 | self.bytes[1] as u16,
+            _ => 0,
+        }
+    }
+
+    /// Returns the address this instruction refers to, if any. For branches,
+    /// this is the branch target rather than the raw offset.
+    pub fn target(&self) -> Option<u16> {
+        let code = match self.code {
+            Some(code) => code,
+            None => return None,
+        };
+        match code.address_mode {
+            OpAddressMode::Implied | OpAddressMode::Immediate => None,
+            OpAddressMode::PCOffset => Some(
+                self.next_address()
+                    .wrapping_add(self.bytes[1] as i8 as u16),
+            ),
+            _ => Some(self.operand()),
+        }
+    }
+
+    /// Formats the instruction in assembler syntax, substituting labels for
+    /// addresses that exactly match one
+    pub fn format(&self, symbols: Option<&SymbolTable>) -> String {
+        let code = match self.code {
+            Some(code) => code,
+            None => return format!(".byte ${:02X}", self.bytes[0]),
+        };
+
+        let name = |address: u16, zero_page: bool| match symbols.and_then(|symbols| symbols.label_at(address)) {
+            Some(label) => label.to_string(),
+            None if zero_page => format!("${:02X}", address),
+            None => format!("${:04X}", address),
+        };
+        let operand = self.operand();
+        let operand = match code.address_mode {
+            OpAddressMode::Implied => String::new(),
+            OpAddressMode::Immediate => format!("#${:02X}", operand),
+            OpAddressMode::Absolute => name(operand, false),
+            OpAddressMode::AbsoluteOffsetX => format!("{},X", name(operand, false)),
+            OpAddressMode::AbsoluteOffsetY => format!("{},Y", name(operand, false)),
+            OpAddressMode::ZeroPage => name(operand, true),
+            OpAddressMode::ZeroPageOffsetX => format!("{},X", name(operand, true)),
+            OpAddressMode::ZeroPageOffsetY => format!("{},Y", name(operand, true)),
+            OpAddressMode::PCOffset => name(self.target().unwrap(), false),
+            OpAddressMode::Indirect => format!("({})", name(operand, false)),
+            OpAddressMode::PreIndirectX => format!("({},X)", name(operand, true)),
+            OpAddressMode::PostIndirectY => format!("({}),Y", name(operand, true)),
+        };
+
+        if operand.is_empty() {
+            mnemonic(code.class).into()
+        } else {
+            format!("{} {}", mnemonic(code.class), operand)
+        }
+    }
+
+    /// Formats the raw bytes as hex, padded to the width of the longest instruction
+    pub fn format_bytes(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|val| format!("{:02X}", val)).collect();
+        format!("{:<8}", bytes.join(" "))
+    }
+}
+
+/// Decodes the instruction at the given address without side effects
+pub fn disassemble(memory: &ReadMemory, address: u16) -> Instruction {
+    let value = memory.byte(address);
+    let code = OpCode::from_value(value);
+    let len = code.map(|code| code.len as usize).unwrap_or(1);
+    Instruction {
+        address: address,
+        bytes: (0..len)
+            .map(|i| memory.byte(address.wrapping_add(i as u16)))
+            .collect(),
+        code: code,
+    }
+}
+
+/// Decodes consecutive instructions starting at the given address
+pub fn disassemble_range(memory: &ReadMemory, address: u16, count: usize) -> Vec<Instruction> {
+    let mut address = address;
+    let mut result = Vec::with_capacity(count);
+    for _ in 0..count {
+        let instruction = disassemble(memory, address);
+        address = instruction.next_address();
+        result.push(instruction);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::{MemoryMap, WriteMode};
+
+    #[test]
+    fn test_disassemble() {
+        let mut memory = MemoryMap::builder().ram(0x0000, 0xFFFF).build();
+        let program = [0xA9, 0x03, 0x85, 0x10, 0xD0, 0xFA, 0x20, 0x00, 0xE0, 0x02];
+        memory.load(0x0200, &program, WriteMode::Bus);
+
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("print".into(), 0xE000);
+
+        let text: Vec<String> = disassemble_range(memory.debug_read(), 0x0200, 5)
+            .iter()
+            .map(|instruction| instruction.format(Some(&symbols)))
+            .collect();
+        assert_eq!(
+            vec!["LDA #$03", "STA $10", "BNE $0200", "JSR print", ".byte $02"],
+            text
+        );
+    }
+}
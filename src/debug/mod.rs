@@ -0,0 +1,38 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! Debugging and introspection tools that are built on top of the emulator.
+
+#[cfg(feature = "dap")]
+mod dap;
+mod cdl;
+mod diagnostics;
+mod disassembler;
+mod monitor;
+mod profiler;
+mod reassembler;
+mod sanitizer;
+mod semihosting;
+mod symbols;
+mod tracer;
+mod vice;
+
+#[cfg(feature = "dap")]
+pub use self::dap::{DapServer, DapTarget, Launcher};
+pub use self::cdl::{CodeDataLogger, CDL_CODE, CDL_DATA, CDL_INDIRECT, CDL_OPERAND, CDL_WRITTEN};
+pub use self::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+pub use self::disassembler::{disassemble, disassemble_range, Instruction};
+pub use self::monitor::Monitor;
+pub use self::profiler::{Profiler, RoutineStats};
+pub use self::reassembler::Reassembler;
+pub use self::sanitizer::{Sanitizer, UninitializedRead};
+pub use self::semihosting::{SemihostingDevice, SEMIHOSTING_SIZE};
+pub use self::symbols::{parse_number, SourceLine, SymbolError, SymbolTable};
+pub use self::tracer::Tracer;
+pub use self::vice::ViceServer;
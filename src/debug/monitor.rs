@@ -0,0 +1,692 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+
+use assembler::Assembler;
+use debug::disassembler::{disassemble, disassemble_range};
+use debug::symbols::SymbolTable;
+use emulator::{Cpu, FrameKind, InterruptType, WriteMode};
+
+/// Number of cycles `continue` runs for before giving control back, so that
+/// a program stuck in a loop doesn't hang the monitor
+const DEFAULT_RUN_LIMIT: usize = 10_000_000;
+const DEFAULT_DISASSEMBLY_LINES: usize = 10;
+const DUMP_BYTES_PER_LINE: usize = 16;
+
+const HELP: &'static str = "\
+Numbers are hexadecimal ($ and 0x prefixes are optional). Use .name for labels.
+Memory is written the way a device programmer would, so >, f, a and l also patch ROM.
+  r [reg=val ...]        show or set registers (a, x, y, sp, pc, p)
+  z [count]              step into
+  n [count]              step over subroutine calls
+  g [addr]               continue until a breakpoint (optionally from addr)
+  bt                     show the call stack
+  break [addr]           list breakpoints or add one
+  del [index]            delete a breakpoint, or all of them
+  m [start] [end]        dump memory
+  > addr byte ...        write bytes to memory
+  f start end byte ...   fill memory with a repeating pattern
+  who start [end]        show the instruction that last wrote each address
+  a addr instruction     assemble an instruction into memory (labels without the dot)
+  d [start] [end]        disassemble
+  l file addr            load a binary file into memory
+  s file start end       save memory to a binary file
+  screen                 show the screen as text
+  x                      exit the monitor
+";
+
+/// An interactive machine-language monitor in the style of the VICE and Apple II monitors.
+/// Commands are read line by line from `input` and results are written to `output`.
+pub struct Monitor<'a, R: BufRead, W: Write> {
+    cpu: &'a mut Cpu,
+    input: R,
+    output: W,
+    symbols: SymbolTable,
+    breakpoints: Vec<u16>,
+    screen: Option<Box<Fn() -> String + 'a>>,
+    run_limit: usize,
+    next_dump: u16,
+    next_disassembly: Option<u16>,
+}
+
+impl<'a, R: BufRead, W: Write> Monitor<'a, R, W> {
+    /// Creates a monitor that controls the given CPU
+    pub fn new(cpu: &'a mut Cpu, input: R, output: W) -> Monitor<'a, R, W> {
+        Monitor {
+            cpu: cpu,
+            input: input,
+            output: output,
+            symbols: SymbolTable::new(),
+            breakpoints: Vec::new(),
+            screen: None,
+            run_limit: DEFAULT_RUN_LIMIT,
+            next_dump: 0,
+            next_disassembly: None,
+        }
+    }
+
+    /// Uses the given symbols for addresses and disassembly
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Provides the text shown by the `screen` command
+    pub fn with_screen(mut self, screen: Box<Fn() -> String + 'a>) -> Self {
+        self.screen = Some(screen);
+        self
+    }
+
+    /// Sets how many cycles `continue` may run before the monitor takes back control
+    pub fn with_run_limit(mut self, cycles: usize) -> Self {
+        self.run_limit = cycles;
+        self
+    }
+
+    /// Returns the breakpoint addresses
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    /// Reads and executes commands until the input ends or `x` is entered
+    pub fn run(&mut self) -> io::Result<()> {
+        self.show_position()?;
+        loop {
+            write!(self.output, "(C:${:04x}) ", self.cpu.registers().pc)?;
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !self.execute(&line)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes a single command. Returns false if the command was a request to exit.
+    pub fn execute(&mut self, line: &str) -> io::Result<bool> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return Ok(true);
+        }
+        let result = match args[0].to_lowercase().as_str() {
+            "x" | "exit" | "q" | "quit" => return Ok(false),
+            "help" | "?" => write!(self.output, "{}", HELP).map_err(|err| err.to_string()),
+            "r" | "registers" => self.registers(&args[1..]),
+            "z" | "step" => self.step(&args[1..], false),
+            "n" | "next" => self.step(&args[1..], true),
+            "g" | "goto" | "continue" => self.go(&args[1..]),
+            "bt" | "backtrace" => self.backtrace(),
+            "break" | "bk" => self.add_breakpoint(&args[1..]),
+            "del" | "delete" => self.delete_breakpoint(&args[1..]),
+            "m" | "mem" => self.dump(&args[1..]),
+            ">" => self.write_bytes(&args[1..]),
+            "f" | "fill" => self.fill(&args[1..]),
+            "who" => self.who(&args[1..]),
+            "a" => self.assemble(&line),
+            "d" | "disass" => self.disassemble(&args[1..]),
+            "l" | "load" => self.load(&args[1..]),
+            "s" | "save" => self.save(&args[1..]),
+            "screen" => self.screen(),
+            other => Err(format!("unknown command `{}`, type `help` for a list", other)),
+        };
+        if let Err(message) = result {
+            writeln!(self.output, "error: {}", message)?;
+        }
+        Ok(true)
+    }
+
+    fn parse_value(&self, text: &str) -> Result<u16, String> {
+        if text.starts_with('.') {
+            return self.symbols
+                .address_of(&text[1..])
+                .ok_or_else(|| format!("unknown label `{}`", &text[1..]));
+        }
+        let digits = if text.starts_with('$') {
+            &text[1..]
+        } else if text.starts_with("0x") || text.starts_with("0X") {
+            &text[2..]
+        } else {
+            text
+        };
+        u16::from_str_radix(digits, 16).map_err(|_| format!("invalid value `{}`", text))
+    }
+
+    fn parse_byte(&self, text: &str) -> Result<u8, String> {
+        match self.parse_value(text)? {
+            val if val <= 0xFF => Ok(val as u8),
+            _ => Err(format!("`{}` doesn't fit in a byte", text)),
+        }
+    }
+
+    fn show_position(&mut self) -> io::Result<()> {
+        let registers = *self.cpu.registers();
+        let instruction = disassemble(self.cpu.memory().debug_read(), registers.pc);
+        writeln!(
+            self.output,
+            "{}\n.C:{:04x}  {}  {}",
+            self.symbols.describe(registers.pc),
+            registers.pc,
+            instruction.format_bytes(),
+            instruction.format(Some(&self.symbols))
+        )
+    }
+
+    fn registers(&mut self, args: &[&str]) -> Result<(), String> {
+        for assignment in args {
+            let mut parts = assignment.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.to_lowercase(), self.parse_value(value)?),
+                _ => return Err(format!("expected reg=value, found `{}`", assignment)),
+            };
+            let registers = self.cpu.registers_mut();
+            match name.as_str() {
+                "pc" => registers.pc = value,
+                _ if value > 0xFF => return Err(format!("`{}` doesn't fit in a byte", value)),
+                "a" => registers.a = value as u8,
+                "x" => registers.x = value as u8,
+                "y" => registers.y = value as u8,
+                "sp" => registers.sp = value as u8,
+                "p" => registers.status.set_value(value as u8),
+                _ => return Err(format!("unknown register `{}`", name)),
+            }
+        }
+
+        let registers = *self.cpu.registers();
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(bit, name)| {
+                if registers.status.value() & (0x80 >> bit) != 0 {
+                    name
+                } else {
+                    name.to_ascii_lowercase()
+                }
+            })
+            .collect();
+        writeln!(
+            self.output,
+            "  PC   A  X  Y  SP  P  NV-BDIZC  CYCLES\n.;{:04x} {:02x} {:02x} {:02x} {:02x} {:02x} {}  {}",
+            registers.pc,
+            registers.a,
+            registers.x,
+            registers.y,
+            registers.sp,
+            registers.status.value(),
+            flags,
+            self.cpu.cycles()
+        ).map_err(|err| err.to_string())
+    }
+
+    /// Steps once, or over a whole subroutine call if `over` is set and the
+    /// next instruction is a JSR. Returns false if a breakpoint interrupted it.
+    fn step_one(&mut self, over: bool) -> bool {
+        if !over {
+            self.cpu.step();
+            return true;
+        }
+
+        let target = self.cpu.step_over_target();
+        let start_cycles = self.cpu.cycles();
+        while !self.cpu.step_toward(target) {
+            if self.cpu.cycles() - start_cycles >= self.run_limit
+                || self.breakpoints.contains(&self.cpu.registers().pc)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn step(&mut self, args: &[&str], over: bool) -> Result<(), String> {
+        let count = match args.first() {
+            Some(count) => self.parse_value(count)?,
+            None => 1,
+        };
+        for _ in 0..count {
+            if !self.step_one(over) {
+                break;
+            }
+        }
+        self.next_disassembly = None;
+        self.show_position().map_err(|err| err.to_string())
+    }
+
+    fn go(&mut self, args: &[&str]) -> Result<(), String> {
+        if let Some(address) = args.first() {
+            self.cpu.registers_mut().pc = self.parse_value(address)?;
+        }
+
+        let start_cycles = self.cpu.cycles();
+        self.cpu.step();
+        let mut hit = false;
+        while self.cpu.cycles() - start_cycles < self.run_limit {
+            if self.breakpoints.contains(&self.cpu.registers().pc) {
+                hit = true;
+                break;
+            }
+            self.cpu.step();
+        }
+
+        let pc = self.cpu.registers().pc;
+        if hit {
+            let index = self.breakpoints.iter().position(|bp| *bp == pc).unwrap();
+            writeln!(self.output, "#{} (Stop on exec {:04x})", index + 1, pc)
+        } else {
+            writeln!(self.output, "Stopped after {} cycles", self.cpu.cycles() - start_cycles)
+        }.map_err(|err| err.to_string())?;
+        self.next_disassembly = None;
+        self.show_position().map_err(|err| err.to_string())
+    }
+
+    fn backtrace(&mut self) -> Result<(), String> {
+        let frames = self.cpu.backtrace();
+        if frames.is_empty() {
+            return writeln!(self.output, "No calls on the stack").map_err(|err| err.to_string());
+        }
+        for (index, frame) in frames.iter().enumerate() {
+            let entry = match frame.kind {
+                FrameKind::Subroutine => "called from",
+                FrameKind::Break => "BRK at",
+                FrameKind::Interrupt => "IRQ at",
+                FrameKind::NonMaskableInterrupt => "NMI at",
+            };
+            writeln!(
+                self.output,
+                "#{}  {}  {} {}",
+                index,
+                self.symbols.format_address(frame.target),
+                entry,
+                self.symbols.describe(frame.call_site)
+            ).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        if let Some(address) = args.first() {
+            let address = self.parse_value(address)?;
+            if !self.breakpoints.contains(&address) {
+                self.breakpoints.push(address);
+            }
+        }
+        for (index, address) in self.breakpoints.iter().enumerate() {
+            writeln!(
+                self.output,
+                "BREAK: {}  C:${:04x}  {}",
+                index + 1,
+                address,
+                self.symbols.describe(*address)
+            ).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first() {
+            Some(index) => {
+                let index = index
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid breakpoint number `{}`", index))?;
+                if index == 0 || index > self.breakpoints.len() {
+                    return Err(format!("no breakpoint #{}", index));
+                }
+                self.breakpoints.remove(index - 1);
+            }
+            None => self.breakpoints.clear(),
+        }
+        Ok(())
+    }
+
+    fn parse_range(&self, args: &[&str], default_start: u16, default_length: u16) -> Result<(u16, u16), String> {
+        let start = match args.first() {
+            Some(start) => self.parse_value(start)?,
+            None => default_start,
+        };
+        let end = match args.get(1) {
+            Some(end) => self.parse_value(end)?,
+            None => start.saturating_add(default_length - 1),
+        };
+        if end < start {
+            return Err("range ends before it starts".into());
+        }
+        Ok((start, end))
+    }
+
+    fn dump(&mut self, args: &[&str]) -> Result<(), String> {
+        let (start, end) = self.parse_range(args, self.next_dump, 0x80)?;
+        let mut address = start as usize;
+        while address <= end as usize {
+            let line_end = (address + DUMP_BYTES_PER_LINE - 1).min(end as usize);
+            let bytes: Vec<u8> = (address..line_end + 1)
+                .map(|addr| self.cpu.memory().debug_read().byte(addr as u16))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|val| format!("{:02x}", val)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&val| if val >= 0x20 && val < 0x7F { val as char } else { '.' })
+                .collect();
+            writeln!(
+                self.output,
+                ">C:{:04x}  {:<48} {}",
+                address,
+                hex.join(" "),
+                text
+            ).map_err(|err| err.to_string())?;
+            address = line_end + 1;
+        }
+        self.next_dump = (end as usize + 1) as u16;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() < 2 {
+            return Err("expected an address and at least one byte".into());
+        }
+        let address = self.parse_value(args[0])?;
+        let bytes = args[1..]
+            .iter()
+            .map(|arg| self.parse_byte(arg))
+            .collect::<Result<Vec<u8>, String>>()?;
+        self.cpu
+            .memory_mut()
+            .load(address, &bytes, WriteMode::Programmer);
+        Ok(())
+    }
+
+    fn fill(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() < 3 {
+            return Err("expected a start, end and at least one byte".into());
+        }
+        let (start, end) = self.parse_range(&args[..2], 0, 1)?;
+        let pattern = args[2..]
+            .iter()
+            .map(|arg| self.parse_byte(arg))
+            .collect::<Result<Vec<u8>, String>>()?;
+        let data: Vec<u8> = (0..end as usize - start as usize + 1)
+            .map(|i| pattern[i % pattern.len()])
+            .collect();
+        self.cpu
+            .memory_mut()
+            .load(start, &data, WriteMode::Programmer);
+        Ok(())
+    }
+
+    fn who(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("expected an address".into());
+        }
+        let (start, end) = self.parse_range(args, 0, 1)?;
+        let writes = match self.cpu.provenance() {
+            Some(provenance) => provenance.writes_in(start, end),
+            None => return Err("write provenance isn't enabled".into()),
+        };
+        if writes.is_empty() {
+            return writeln!(self.output, "Never written").map_err(|err| err.to_string());
+        }
+        for (address, write) in writes {
+            let writer = match write.interrupt {
+                Some(InterruptType::Maskable) => "IRQ at",
+                Some(InterruptType::NonMaskable) => "NMI at",
+                None => "by",
+            };
+            writeln!(
+                self.output,
+                "{}  {:02x} (was {:02x})  {} {}  cycle {}",
+                self.symbols.format_address(address),
+                write.value,
+                write.previous,
+                writer,
+                self.symbols.describe(write.pc),
+                write.cycle
+            ).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn assemble(&mut self, line: &str) -> Result<(), String> {
+        let mut parts = line.trim().splitn(3, char::is_whitespace);
+        parts.next();
+        let address = match parts.next() {
+            Some(address) => self.parse_value(address)?,
+            None => return Err("expected an address".into()),
+        };
+        let source = parts.next().unwrap_or("").trim();
+        if source.is_empty() {
+            return Err("expected an instruction".into());
+        }
+
+        // Make the monitor's labels available to the assembled instruction.
+        // Unlike elsewhere in the monitor, they're written without the leading dot.
+        let mut assembler = Assembler::new(address);
+        for (label_address, name) in self.symbols.labels() {
+            assembler = assembler.constant(name, label_address.into());
+        }
+        let program = assembler
+            .source(source)
+            .assemble()
+            .map_err(|err| err.to_string())?;
+        let mut next = address;
+        for segment in program.image().segments() {
+            self.cpu
+                .memory_mut()
+                .load(segment.address, &segment.data, WriteMode::Programmer);
+            next = segment.address.wrapping_add(segment.data.len() as u16);
+        }
+        let instruction = disassemble(self.cpu.memory().debug_read(), address);
+        writeln!(
+            self.output,
+            ".C:{:04x}  {}  {}\n(next: {:04x})",
+            address,
+            instruction.format_bytes(),
+            instruction.format(Some(&self.symbols)),
+            next
+        ).map_err(|err| err.to_string())
+    }
+
+    fn disassemble(&mut self, args: &[&str]) -> Result<(), String> {
+        let start = match args.first() {
+            Some(start) => self.parse_value(start)?,
+            None => self.next_disassembly
+                .unwrap_or(self.cpu.registers().pc),
+        };
+        let end = match args.get(1) {
+            Some(end) => Some(self.parse_value(end)?),
+            None => None,
+        };
+
+        let instructions = match end {
+            Some(end) => {
+                let mut result = Vec::new();
+                let mut address = start;
+                while address <= end && address >= start {
+                    let instruction = disassemble(self.cpu.memory().debug_read(), address);
+                    address = instruction.next_address();
+                    result.push(instruction);
+                }
+                result
+            }
+            None => disassemble_range(self.cpu.memory().debug_read(), start, DEFAULT_DISASSEMBLY_LINES),
+        };
+        for instruction in &instructions {
+            if let Some(label) = self.symbols.label_at(instruction.address) {
+                writeln!(self.output, "{}:", label).map_err(|err| err.to_string())?;
+            }
+            writeln!(
+                self.output,
+                ".C:{:04x}  {}  {}",
+                instruction.address,
+                instruction.format_bytes(),
+                instruction.format(Some(&self.symbols))
+            ).map_err(|err| err.to_string())?;
+        }
+        self.next_disassembly = instructions.last().map(|last| last.next_address());
+        Ok(())
+    }
+
+    fn load(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() != 2 {
+            return Err("expected a file name and an address".into());
+        }
+        let path = args[0].trim_matches('"');
+        let address = self.parse_value(args[1])?;
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|err| format!("failed to read `{}`: {}", path, err))?;
+        if address as usize + data.len() > 0x10000 {
+            return Err(format!("`{}` doesn't fit at ${:04x}", path, address));
+        }
+        self.cpu
+            .memory_mut()
+            .load(address, &data, WriteMode::Programmer);
+        writeln!(
+            self.output,
+            "Loaded {} bytes to ${:04x}-${:04x}",
+            data.len(),
+            address,
+            (address as usize + data.len()).saturating_sub(1)
+        ).map_err(|err| err.to_string())
+    }
+
+    fn save(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() != 3 {
+            return Err("expected a file name, start and end".into());
+        }
+        let path = args[0].trim_matches('"');
+        let (start, end) = self.parse_range(&args[1..], 0, 1)?;
+        let data: Vec<u8> = (start as usize..end as usize + 1)
+            .map(|addr| self.cpu.memory().debug_read().byte(addr as u16))
+            .collect();
+        File::create(path)
+            .and_then(|mut file| file.write_all(&data))
+            .map_err(|err| format!("failed to write `{}`: {}", path, err))
+    }
+
+    fn screen(&mut self) -> Result<(), String> {
+        let text = match self.screen {
+            Some(ref screen) => screen(),
+            None => return Err("this system doesn't have a screen".into()),
+        };
+        write!(self.output, "{}", text).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use emulator::MemoryMap;
+
+    fn run_commands(cpu: &mut Cpu, commands: &str) -> String {
+        let mut output = Vec::new();
+        {
+            let mut monitor = Monitor::new(cpu, Cursor::new(commands.as_bytes().to_vec()), &mut output);
+            monitor.run().unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    fn new_cpu() -> Cpu {
+        let mut memory = MemoryMap::builder().ram(0x0000, 0xFFFF).build();
+        memory.write().byte(0xFFFC, 0x00);
+        memory.write().byte(0xFFFD, 0x02);
+        Cpu::new(memory)
+    }
+
+    #[test]
+    fn test_assemble_and_step() {
+        let mut cpu = new_cpu();
+        let output = run_commands(
+            &mut cpu,
+            "a 0200 LDA #$42\n\
+             a 0202 STA $10\n\
+             a 0204 JMP $0204\n\
+             z 2\n\
+             m 10 10\n\
+             r x=7\n",
+        );
+        assert_eq!(0x42, cpu.memory().debug_read().byte(0x0010));
+        assert_eq!(7, cpu.registers().x);
+        assert_eq!(0x0204, cpu.registers().pc);
+        assert!(output.contains(">C:0010  42"));
+        assert!(output.contains("JMP $0204"));
+
+        cpu.enable_provenance();
+        let output = run_commands(&mut cpu, "r pc=0202
+z
+who 10
+who 11
+");
+        assert!(output.contains("$0010  42 (was 42)  by $0202  cycle 5"));
+        assert!(output.contains("Never written"));
+    }
+
+    #[test]
+    fn test_breakpoints_and_next() {
+        let mut cpu = new_cpu();
+        run_commands(
+            &mut cpu,
+            "a 0200 JSR $0300\n\
+             a 0203 INX\n\
+             a 0204 JMP $0204\n\
+             a 0300 INY\n\
+             a 0301 RTS\n\
+             n\n",
+        );
+        assert_eq!(0x0203, cpu.registers().pc);
+        assert_eq!(1, cpu.registers().y);
+
+        let output = run_commands(&mut cpu, "break 0204\ng\n");
+        assert!(output.contains("Stop on exec 0204"));
+        assert_eq!(1, cpu.registers().x);
+
+        cpu.reset();
+        let output = run_commands(&mut cpu, "z\nbt\n");
+        assert!(output.contains("#0  $0300  called from $0200"));
+    }
+
+    #[test]
+    fn test_patch_rom() {
+        let mut rom = vec![0xEA; 0x2000];
+        rom[0x1FFC] = 0x00;
+        rom[0x1FFD] = 0xE0;
+        let memory = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .rom(0xE000, 0xFFFF, rom)
+            .build();
+        let mut cpu = Cpu::new(memory);
+        let output = run_commands(
+            &mut cpu,
+            "a e000 LDA #$42
+             > e002 85 10
+             f e004 e007 4c 04
+             z 2
+",
+        );
+        assert!(output.contains(".C:e000  A9 42     LDA #$42"));
+        assert_eq!(0x42, cpu.memory().debug_read().byte(0x0010));
+        assert_eq!(0x4C, cpu.memory().debug_read().byte(0xE006));
+        assert_eq!(0x04, cpu.memory().debug_read().byte(0xE007));
+    }
+
+    #[test]
+    fn test_fill_and_errors() {
+        let mut cpu = new_cpu();
+        let output = run_commands(&mut cpu, "f 1000 1004 aa bb\nbogus\nr q=1\n");
+        assert_eq!(0xAA, cpu.memory().debug_read().byte(0x1004));
+        assert_eq!(0xBB, cpu.memory().debug_read().byte(0x1003));
+        assert!(output.contains("error: unknown command `bogus`"));
+        assert!(output.contains("error: unknown register `q`"));
+    }
+}
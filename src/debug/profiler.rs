@@ -0,0 +1,279 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use hassel_lib6502::OpCode;
+
+use assembler::mnemonic;
+use debug::symbols::SymbolTable;
+use emulator::{CallFrame, InstructionObserver};
+
+/// Number of addresses listed in the hot spot section of the report
+const HOT_SPOT_COUNT: usize = 20;
+const ROOT_NAME: &'static str = "root";
+
+/// Execution statistics for a subroutine or interrupt handler
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RoutineStats {
+    /// Number of times it was called
+    pub calls: u64,
+    /// Cycles spent in the routine itself
+    pub exclusive_cycles: u64,
+    /// Cycles spent in the routine and everything it called
+    pub inclusive_cycles: u64,
+}
+
+/// Counts executions and cycles per address, per subroutine and per op-code.
+/// Register it on a CPU with `Cpu::add_instruction_observer`.
+pub struct Profiler {
+    counts: Vec<u64>,
+    cycles: Vec<u64>,
+    op_codes: Vec<u64>,
+    routines: BTreeMap<Option<u16>, RoutineStats>,
+    folded: HashMap<Vec<u16>, u64>,
+    /// Entry addresses of the routines on the call stack before the next instruction
+    stack: Vec<u16>,
+    stack_cycles: u64,
+    total_instructions: u64,
+    total_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            counts: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            op_codes: vec![0; 0x100],
+            routines: BTreeMap::new(),
+            folded: HashMap::new(),
+            stack: Vec::new(),
+            stack_cycles: 0,
+            total_instructions: 0,
+            total_cycles: 0,
+        }
+    }
+
+    /// Returns the number of times the instruction at the given address was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// Returns the cycles spent on the instruction at the given address
+    pub fn cycles(&self, address: u16) -> u64 {
+        self.cycles[address as usize]
+    }
+
+    /// Returns the number of times the given op-code was executed
+    pub fn op_code_count(&self, op_code: u8) -> u64 {
+        self.op_codes[op_code as usize]
+    }
+
+    /// Returns the statistics for the routine that starts at the given address,
+    /// or for code that isn't in any routine if the address is None
+    pub fn routine(&self, entry: Option<u16>) -> RoutineStats {
+        self.routines.get(&entry).cloned().unwrap_or_default()
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.total_instructions
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Returns the cycles spent in each distinct call stack, keyed by the
+    /// routine entry addresses from outermost to innermost
+    pub fn stacks(&self) -> HashMap<Vec<u16>, u64> {
+        let mut result = self.folded.clone();
+        if self.stack_cycles > 0 {
+            *result.entry(self.stack.clone()).or_insert(0) += self.stack_cycles;
+        }
+        result
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        if self.total_cycles == 0 {
+            0.0
+        } else {
+            cycles as f64 * 100.0 / self.total_cycles as f64
+        }
+    }
+
+    fn routine_name(symbols: &SymbolTable, entry: Option<u16>) -> String {
+        match entry {
+            Some(address) => symbols.format_address(address),
+            None => ROOT_NAME.into(),
+        }
+    }
+
+    /// Writes a human readable report of the subroutines, addresses and op-codes
+    /// that took the most time
+    pub fn write_report<W: Write>(&self, output: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        writeln!(
+            output,
+            "{} instructions, {} cycles\n",
+            self.total_instructions, self.total_cycles
+        )?;
+
+        let mut routines: Vec<(&Option<u16>, &RoutineStats)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.exclusive_cycles.cmp(&a.1.exclusive_cycles));
+        writeln!(output, "Routines:")?;
+        writeln!(output, "  exclusive       %   inclusive       %      calls  name")?;
+        for (entry, stats) in routines {
+            writeln!(
+                output,
+                "{:>11} {:>6.2}% {:>11} {:>6.2}% {:>10}  {}",
+                stats.exclusive_cycles,
+                self.percent(stats.exclusive_cycles),
+                stats.inclusive_cycles,
+                self.percent(stats.inclusive_cycles),
+                stats.calls,
+                Profiler::routine_name(symbols, *entry)
+            )?;
+        }
+
+        let mut addresses: Vec<usize> = (0..0x10000).filter(|&address| self.counts[address] > 0).collect();
+        addresses.sort_by(|&a, &b| self.cycles[b].cmp(&self.cycles[a]));
+        writeln!(output, "\nHot spots:")?;
+        writeln!(output, "     cycles       %      count  address")?;
+        for address in addresses.into_iter().take(HOT_SPOT_COUNT) {
+            writeln!(
+                output,
+                "{:>11} {:>6.2}% {:>10}  {}",
+                self.cycles[address],
+                self.percent(self.cycles[address]),
+                self.counts[address],
+                symbols.describe(address as u16)
+            )?;
+        }
+
+        let mut op_codes: Vec<usize> = (0..0x100).filter(|&op_code| self.op_codes[op_code] > 0).collect();
+        op_codes.sort_by(|&a, &b| self.op_codes[b].cmp(&self.op_codes[a]));
+        writeln!(output, "\nOp-codes:")?;
+        writeln!(output, "      count  op-code")?;
+        for op_code in op_codes {
+            let name = match OpCode::from_value(op_code as u8) {
+                Some(code) => format!("{} {:?}", mnemonic(code.class), code.address_mode),
+                None => "invalid".into(),
+            };
+            writeln!(output, "{:>11}  ${:02X} {}", self.op_codes[op_code], op_code, name)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the cycles per call stack in the folded stacks format used by
+    /// flamegraph tools, such as `root;main;print_string 1234`
+    pub fn write_folded<W: Write>(&self, output: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        let mut lines: Vec<(String, u64)> = self.stacks()
+            .into_iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = ::std::iter::once(ROOT_NAME.to_string())
+                    .chain(stack.iter().map(|entry| symbols.format_address(*entry)))
+                    .collect();
+                (names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        for (stack, cycles) in lines {
+            writeln!(output, "{} {}", stack, cycles)?;
+        }
+        Ok(())
+    }
+}
+
+impl InstructionObserver for Profiler {
+    fn executed(&mut self, pc: u16, op_code: u8, cycles: usize, frames: &[CallFrame]) {
+        let cycles = cycles as u64;
+        self.counts[pc as usize] += 1;
+        self.cycles[pc as usize] += cycles;
+        self.op_codes[op_code as usize] += 1;
+        self.total_instructions += 1;
+        self.total_cycles += cycles;
+
+        // The frames are from after the instruction, so the instruction itself belongs
+        // to the stack that was recorded after the previous one
+        let innermost = self.stack.last().cloned();
+        self.routines.entry(innermost).or_insert_with(Default::default).exclusive_cycles += cycles;
+        self.routines.entry(None).or_insert_with(Default::default).inclusive_cycles += cycles;
+        for (i, entry) in self.stack.iter().enumerate() {
+            // Count recursive routines once
+            if !self.stack[..i].contains(entry) {
+                self.routines.entry(Some(*entry)).or_insert_with(Default::default).inclusive_cycles += cycles;
+            }
+        }
+        self.stack_cycles += cycles;
+
+        let common = frames
+            .iter()
+            .zip(self.stack.iter())
+            .take_while(|&(frame, entry)| frame.target == *entry)
+            .count();
+        if common != frames.len() || common != self.stack.len() {
+            let previous = ::std::mem::replace(&mut self.stack, frames.iter().map(|frame| frame.target).collect());
+            *self.folded.entry(previous).or_insert(0) += self.stack_cycles;
+            self.stack_cycles = 0;
+
+            for frame in &frames[common..] {
+                self.routines
+                    .entry(Some(frame.target))
+                    .or_insert_with(Default::default)
+                    .calls += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use test_support::{load_program, run_to_label};
+
+    #[test]
+    fn test_profile() {
+        let (mut cpu, program) = load_program(
+            "start: LDX #3
+             loop:  JSR work
+                    DEX
+                    BNE loop
+             halt:  JMP halt
+             work:  JSR leaf
+                    RTS
+             leaf:  NOP
+                    RTS
+             .reset start",
+        );
+
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        cpu.add_instruction_observer(profiler.clone());
+        run_to_label(&mut cpu, &program, "halt");
+        let symbols = program.symbols();
+
+        let profiler = profiler.borrow();
+        let work = profiler.routine(symbols.address_of("work"));
+        let leaf = profiler.routine(symbols.address_of("leaf"));
+        assert_eq!(3, profiler.count(symbols.address_of("loop").unwrap()));
+        assert_eq!(6, profiler.op_code_count(0x60));
+        assert_eq!((3, 3), (work.calls, leaf.calls));
+        assert_eq!(3 * (2 + 6), leaf.exclusive_cycles);
+        assert_eq!(3 * (6 + 6), work.exclusive_cycles);
+        assert_eq!(work.exclusive_cycles + leaf.inclusive_cycles, work.inclusive_cycles);
+        assert_eq!(profiler.total_cycles(), profiler.routine(None).inclusive_cycles);
+
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded, symbols).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.contains("root;work 36\n"));
+        assert!(folded.contains("root;work;leaf 24\n"));
+    }
+}
@@ -0,0 +1,441 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use hassel_lib6502::{OpAddressMode, OpClass, OpCode};
+
+use assembler::{find_op_code, is_branch, mnemonic};
+use debug::cdl::{CodeDataLogger, CDL_CODE, CDL_DATA, CDL_INDIRECT};
+use debug::symbols::SymbolTable;
+use emulator::ImageError;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// Number of bytes per `.byte` line
+const BYTES_PER_LINE: usize = 8;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ByteKind {
+    Unknown,
+    /// Known to be data from a code/data log
+    Data,
+    OpCode,
+    Operand,
+}
+
+/// Turns a ROM image back into assembly source by following control flow from the
+/// vectors and any other entry points, rather than decoding every byte as an
+/// instruction. Bytes that aren't reached are kept as `.byte` data, so the source
+/// reassembles with ca65 (`ca65 rom.s && ld65 -t none -o rom.bin rom.o`) to the
+/// exact bytes it came from.
+pub struct Reassembler {
+    origin: u16,
+    data: Vec<u8>,
+    entry_points: Vec<(u16, String)>,
+    symbols: SymbolTable,
+    kinds: Vec<ByteKind>,
+}
+
+impl Reassembler {
+    /// Creates a reassembler for an image that starts at `origin`. If the image covers
+    /// the vectors at $FFFA-$FFFF, the reset, NMI and IRQ handlers are entry points.
+    /// Fails if the image runs past the end of the address space.
+    pub fn new(origin: u16, data: Vec<u8>) -> Result<Reassembler, ImageError> {
+        if origin as usize + data.len() > 0x10000 {
+            return Err(ImageError::OutOfRange {
+                address: origin as u32 + data.len() as u32 - 1,
+            });
+        }
+        let kinds = vec![ByteKind::Unknown; data.len()];
+        let mut reassembler = Reassembler {
+            origin: origin,
+            data: data,
+            entry_points: Vec::new(),
+            symbols: SymbolTable::new(),
+            kinds: kinds,
+        };
+        for &(vector, name) in &[(RESET_VECTOR, "reset"), (NMI_VECTOR, "nmi"), (IRQ_VECTOR, "irq")] {
+            if let Some(address) = reassembler.word(vector) {
+                reassembler.entry_points.push((address, name.into()));
+            }
+        }
+        Ok(reassembler)
+    }
+
+    /// Adds another address to start tracing from, such as the load address of a program.
+    /// The first one is labeled `start`, and any others `start_XXXX` after their address.
+    pub fn entry_point(mut self, address: u16) -> Self {
+        let name = if self.entry_points.iter().any(|&(_, ref name)| name == "start") {
+            format!("start_{:04X}", address)
+        } else {
+            "start".into()
+        };
+        self.entry_points.push((address, name));
+        self
+    }
+
+    /// Uses the labels from the given table instead of generated ones
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Seeds the trace with a code/data log from running the image. Every executed
+    /// op-code becomes an entry point, and bytes that were only used as data are
+    /// never decoded as code.
+    pub fn with_code_data_log(mut self, logger: &CodeDataLogger) -> Self {
+        for index in 0..self.data.len() {
+            let address = self.origin + index as u16;
+            let flags = logger.flags(address);
+            if flags & CDL_CODE != 0 {
+                self.entry_points.push((address, String::new()));
+            } else if flags & (CDL_DATA | CDL_INDIRECT) != 0 {
+                self.kinds[index] = ByteKind::Data;
+            }
+        }
+        self
+    }
+
+    fn index(&self, address: u16) -> Option<usize> {
+        if address >= self.origin && ((address - self.origin) as usize) < self.data.len() {
+            Some((address - self.origin) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn byte(&self, address: u16) -> Option<u8> {
+        self.index(address).map(|index| self.data[index])
+    }
+
+    fn word(&self, address: u16) -> Option<u16> {
+        match (self.byte(address), self.byte(address.wrapping_add(1))) {
+            (Some(low), Some(high)) => Some((high as u16) << 8 | low as u16),
+            _ => None,
+        }
+    }
+
+    /// Decodes the instruction at the given address, if it's entirely inside the image
+    /// and would be assembled back to the same bytes
+    fn decode(&self, address: u16) -> Option<(OpCode, u16)> {
+        let code = match self.byte(address).and_then(OpCode::from_value) {
+            Some(code) => code,
+            None => return None,
+        };
+        let canonical = find_op_code(code.class, code.address_mode).map(|(value, _)| value);
+        if code.class == OpClass::Top || canonical != Some(code.value) {
+            return None;
+        }
+        let operand = match code.len {
+            2 => self.byte(address.wrapping_add(1)).map(|low| low as u16),
+            3 => self.word(address.wrapping_add(1)),
+            _ => Some(0),
+        };
+        operand.map(|operand| (code, operand))
+    }
+
+    /// Marks everything reachable from the entry points as code
+    fn trace(&mut self) {
+        let mut pending: Vec<u16> = self.entry_points.iter().map(|&(address, _)| address).collect();
+        while let Some(mut address) = pending.pop() {
+            loop {
+                let (code, operand) = match self.decode(address) {
+                    Some(decoded) => decoded,
+                    None => break,
+                };
+                let start = self.index(address).unwrap();
+                let end = start + code.len as usize;
+                if self.kinds[start..end].iter().any(|kind| *kind != ByteKind::Unknown) {
+                    break;
+                }
+                self.kinds[start] = ByteKind::OpCode;
+                for kind in &mut self.kinds[start + 1..end] {
+                    *kind = ByteKind::Operand;
+                }
+
+                let next = address.wrapping_add(code.len as u16);
+                match code.class {
+                    OpClass::Jmp => {
+                        if code.address_mode == OpAddressMode::Absolute {
+                            pending.push(operand);
+                        }
+                        break;
+                    }
+                    OpClass::Jsr => pending.push(operand),
+                    OpClass::Rts | OpClass::Rti | OpClass::Brk => break,
+                    class if is_branch(class) => pending.push(branch_target(next, operand)),
+                    _ => {}
+                }
+                address = next;
+            }
+        }
+    }
+
+    /// Picks a name for every address inside the image that's referred to by code or the vectors
+    fn generate_labels(&self) -> BTreeMap<u16, String> {
+        let mut labels = BTreeMap::new();
+        for &(address, ref name) in &self.entry_points {
+            if !name.is_empty() && self.index(address).is_some() {
+                labels.entry(address).or_insert_with(|| name.clone());
+            }
+        }
+        for (index, kind) in self.kinds.iter().enumerate() {
+            if *kind != ByteKind::OpCode {
+                continue;
+            }
+            let address = self.origin + index as u16;
+            let (code, operand) = self.decode(address).unwrap();
+            let (target, prefix) = match code.address_mode {
+                OpAddressMode::Implied | OpAddressMode::Immediate => continue,
+                OpAddressMode::PCOffset => (branch_target(address.wrapping_add(2), operand), "L"),
+                _ if code.class == OpClass::Jsr => (operand, "sub_"),
+                _ if code.class == OpClass::Jmp && code.address_mode == OpAddressMode::Absolute => (operand, "L"),
+                _ => (operand, "D"),
+            };
+            // Operand bytes can't be labeled, so refer to them relative to their instruction
+            let target = match self.index(target) {
+                Some(target_index) => {
+                    let mut start = target_index;
+                    while self.kinds[start] == ByteKind::Operand {
+                        start -= 1;
+                    }
+                    self.origin + start as u16
+                }
+                None => continue,
+            };
+            labels
+                .entry(target)
+                .or_insert_with(|| format!("{}{:04X}", prefix, target));
+        }
+        for (address, name) in labels.iter_mut() {
+            if let Some(label) = self.symbols.label_at(*address) {
+                *name = label.into();
+            }
+        }
+        labels
+    }
+
+    /// Formats an address operand, by name if it has one
+    fn format_address(&self, labels: &BTreeMap<u16, String>, address: u16, zero_page: bool) -> String {
+        if let Some(label) = labels.get(&address) {
+            return label.clone();
+        }
+        if let Some(index) = self.index(address) {
+            if self.kinds[index] == ByteKind::Operand {
+                let (base, name) = labels.range(..address).next_back().unwrap();
+                return format!("{}+{}", name, address - base);
+            }
+        }
+        match self.symbols.label_at(address) {
+            Some(label) => label.into(),
+            None if zero_page => format!("${:02X}", address),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    fn format_instruction(&self, labels: &BTreeMap<u16, String>, address: u16) -> String {
+        let (code, operand) = self.decode(address).unwrap();
+        // ca65 picks zero page addressing whenever it can, so absolute
+        // addressing of the zero page has to be forced with `a:`
+        let absolute = |address: u16| {
+            let name = self.format_address(labels, address, false);
+            if address < 0x100 && code.class != OpClass::Jmp && code.class != OpClass::Jsr {
+                format!("a:{}", name)
+            } else {
+                name
+            }
+        };
+        let operand = match code.address_mode {
+            OpAddressMode::Implied => String::new(),
+            OpAddressMode::Immediate => format!("#${:02X}", operand),
+            OpAddressMode::Absolute => absolute(operand),
+            OpAddressMode::AbsoluteOffsetX => format!("{},X", absolute(operand)),
+            OpAddressMode::AbsoluteOffsetY => format!("{},Y", absolute(operand)),
+            OpAddressMode::ZeroPage => self.format_address(labels, operand, true),
+            OpAddressMode::ZeroPageOffsetX => format!("{},X", self.format_address(labels, operand, true)),
+            OpAddressMode::ZeroPageOffsetY => format!("{},Y", self.format_address(labels, operand, true)),
+            OpAddressMode::PCOffset => {
+                self.format_address(labels, branch_target(address.wrapping_add(2), operand), false)
+            }
+            OpAddressMode::Indirect => format!("({})", self.format_address(labels, operand, false)),
+            OpAddressMode::PreIndirectX => format!("({},X)", self.format_address(labels, operand, true)),
+            OpAddressMode::PostIndirectY => format!("({}),Y", self.format_address(labels, operand, true)),
+        };
+        if operand.is_empty() {
+            mnemonic(code.class).into()
+        } else {
+            format!("{} {}", mnemonic(code.class), operand)
+        }
+    }
+
+    /// Returns true if tracing found an instruction starting at the given address.
+    /// The trace is done by `write_ca65`.
+    pub fn is_code(&self, address: u16) -> bool {
+        self.index(address)
+            .map(|index| self.kinds[index] == ByteKind::OpCode)
+            .unwrap_or(false)
+    }
+
+    /// Traces the image and writes ca65 source for it
+    pub fn write_ca65<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        self.trace();
+        let labels = self.generate_labels();
+
+        // Name the labels from the symbol table that code refers to outside of the image
+        let mut externals = BTreeMap::new();
+        for index in 0..self.data.len() {
+            let address = self.origin + index as u16;
+            if self.kinds[index] != ByteKind::OpCode {
+                continue;
+            }
+            let (code, operand) = self.decode(address).unwrap();
+            let target = match code.address_mode {
+                OpAddressMode::Implied | OpAddressMode::Immediate => continue,
+                OpAddressMode::PCOffset => branch_target(address.wrapping_add(2), operand),
+                _ => operand,
+            };
+            if let (None, Some(label)) = (self.index(target), self.symbols.label_at(target)) {
+                externals.insert(target, label);
+            }
+        }
+        // Labels can't be placed in the middle of an instruction, so any that point
+        // there are defined as constants
+        for (address, label) in &labels {
+            if self.kinds[self.index(*address).unwrap()] == ByteKind::Operand {
+                externals.insert(*address, label);
+            }
+        }
+
+        writeln!(output, "; Disassembled by hassel_emu")?;
+        if !externals.is_empty() {
+            writeln!(output)?;
+            for (address, label) in &externals {
+                writeln!(output, "{} = ${:04X}", label, address)?;
+            }
+        }
+        writeln!(output, "\n.org ${:04X}", self.origin)?;
+
+        let mut index = 0;
+        while index < self.data.len() {
+            let address = self.origin + index as u16;
+            if let Some(label) = labels.get(&address) {
+                writeln!(output, "{}:", label)?;
+            }
+            if self.kinds[index] == ByteKind::OpCode {
+                writeln!(output, "    {}", self.format_instruction(&labels, address))?;
+                index += self.decode(address).unwrap().0.len as usize;
+                continue;
+            }
+
+            let is_data = |index: usize| self.kinds[index] != ByteKind::OpCode;
+            if address == NMI_VECTOR && self.data.len() - index == 6 && (index..index + 6).all(&is_data) {
+                let vectors: Vec<String> = (0..3)
+                    .map(|vector| {
+                        let target = self.word(NMI_VECTOR + vector * 2).unwrap();
+                        self.format_address(&labels, target, false)
+                    })
+                    .collect();
+                writeln!(output, "    .word {}", vectors.join(", "))?;
+                break;
+            }
+
+            let mut end = index + 1;
+            while end < self.data.len() && end - index < BYTES_PER_LINE && is_data(end)
+                && !labels.contains_key(&(self.origin + end as u16))
+                && self.origin as usize + end != NMI_VECTOR as usize
+            {
+                end += 1;
+            }
+            let bytes: Vec<String> = self.data[index..end]
+                .iter()
+                .map(|val| format!("${:02X}", val))
+                .collect();
+            writeln!(output, "    .byte {}", bytes.join(", "))?;
+            index = end;
+        }
+        Ok(())
+    }
+}
+
+fn branch_target(next: u16, offset: u16) -> u16 {
+    next.wrapping_add(offset as u8 as i8 as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    #[test]
+    fn test_reassemble() {
+        let source = "start: LDX #0
+                      loop:  LDA message,X
+                             BEQ done
+                             JSR print
+                             INX
+                             BNE loop
+                      done:  JMP (pointer)
+                      print: STA $F001
+                             RTS
+                      message: .byte 'H', 'I', 0
+                      pointer: .word start
+                      nmi:   RTI
+                      .reset start
+                      .irq nmi
+                      .nmi nmi";
+        let rom = Assembler::new(0xFF00)
+            .source(source)
+            .assemble()
+            .unwrap()
+            .to_rom(0xFF00, 0xFFFF)
+            .unwrap();
+
+        let mut reassembler = Reassembler::new(0xFF00, rom.clone()).unwrap();
+        let mut output = Vec::new();
+        reassembler.write_ca65(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("reset:\n    LDX #$00\n"));
+        assert!(output.contains("    JSR sub_FF10\n"));
+        assert!(output.contains("    JMP (DFF17)\n"));
+        assert!(output.contains("    .word nmi, reset, nmi\n"));
+        assert!(reassembler.is_code(0xFF02));
+        assert!(!reassembler.is_code(0xFF14));
+
+        let reassembled = Assembler::new(0xFF00)
+            .source(&output)
+            .assemble()
+            .unwrap()
+            .to_rom(0xFF00, 0xFFFF)
+            .unwrap();
+        assert_eq!(rom, reassembled);
+    }
+    #[test]
+    fn test_entry_points() {
+        // LDA #$01; RTS; LDA #$02; RTS
+        let rom = vec![0xA9, 0x01, 0x60, 0xA9, 0x02, 0x60];
+        let mut reassembler = Reassembler::new(0x0200, rom)
+            .unwrap()
+            .entry_point(0x0200)
+            .entry_point(0x0203);
+        let mut output = Vec::new();
+        reassembler.write_ca65(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("start:\n    LDA #$01\n"));
+        assert!(output.contains("start_0203:\n    LDA #$02\n"));
+
+        match Reassembler::new(0xFFFF, vec![0; 2]) {
+            Err(ImageError::OutOfRange { address: 0x10000 }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+}
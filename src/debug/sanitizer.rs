@@ -0,0 +1,209 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use debug::symbols::SymbolTable;
+use emulator::{AccessKind, BusObserver, CallFrame, Cpu, FrameKind, InstructionObserver, ReadMemory, Registers};
+
+/// A read of a RAM byte that hadn't been written yet
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UninitializedRead {
+    pub address: u16,
+    /// Address of the instruction that made the read
+    pub pc: u16,
+    /// Cycle count at the start of that instruction
+    pub cycle: usize,
+    /// The shadow call stack during the read, innermost frame first
+    pub backtrace: Vec<CallFrame>,
+}
+
+/// Tracks which bytes of RAM have been written and reports reads of those that
+/// haven't, which return whatever the RAM powered on with on real hardware.
+/// Each instruction is only reported once per address. Pair it with
+/// `MemoryMapBuilder::ram_fill` to reproduce the resulting bugs.
+pub struct Sanitizer {
+    checked: Vec<bool>,
+    written: Vec<bool>,
+    pc: u16,
+    frames: Vec<CallFrame>,
+    reads: Vec<UninitializedRead>,
+    reported: HashSet<(u16, u16)>,
+}
+
+impl Sanitizer {
+    /// Creates a sanitizer that doesn't check any addresses yet
+    pub fn new() -> Sanitizer {
+        Sanitizer {
+            checked: vec![false; 0x10000],
+            written: vec![false; 0x10000],
+            pc: 0,
+            frames: Vec::new(),
+            reads: Vec::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Checks reads of the given range of RAM
+    pub fn ram(mut self, start: u16, end_inclusive: u16) -> Self {
+        for address in start as usize..end_inclusive as usize + 1 {
+            self.checked[address] = true;
+        }
+        self
+    }
+
+    /// Marks a range as written, such as where a program was loaded
+    pub fn initialize(&mut self, start: u16, end_inclusive: u16) {
+        for address in start as usize..end_inclusive as usize + 1 {
+            self.written[address] = true;
+        }
+    }
+
+    /// Returns true if the address is checked and hasn't been written yet
+    pub fn is_uninitialized(&self, address: u16) -> bool {
+        self.checked[address as usize] && !self.written[address as usize]
+    }
+
+    /// Returns the uninitialized reads found so far, in the order they happened
+    pub fn reads(&self) -> &[UninitializedRead] {
+        &self.reads
+    }
+
+    /// Registers the sanitizer with both the CPU and its memory map. It needs
+    /// the CPU to know which instruction made each read.
+    pub fn attach(self, cpu: &mut Cpu) -> Rc<RefCell<Sanitizer>> {
+        let sanitizer = Rc::new(RefCell::new(self));
+        cpu.add_instruction_observer(sanitizer.clone());
+        cpu.memory_mut().add_bus_observer(sanitizer.clone());
+        sanitizer
+    }
+
+    /// Writes a description of every uninitialized read along with its backtrace
+    pub fn write_report<W: Write>(&self, output: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        for read in &self.reads {
+            writeln!(
+                output,
+                "uninitialized read of {} by {} at cycle {}",
+                symbols.format_address(read.address),
+                symbols.describe(read.pc),
+                read.cycle
+            )?;
+            for (index, frame) in read.backtrace.iter().enumerate() {
+                let entry = match frame.kind {
+                    FrameKind::Subroutine => "called from",
+                    FrameKind::Break => "BRK at",
+                    FrameKind::Interrupt => "IRQ at",
+                    FrameKind::NonMaskableInterrupt => "NMI at",
+                };
+                writeln!(
+                    output,
+                    "    #{}  {}  {} {}",
+                    index,
+                    symbols.format_address(frame.target),
+                    entry,
+                    symbols.describe(frame.call_site)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BusObserver for Sanitizer {
+    fn access(&mut self, address: u16, _value: u8, cycle: usize, kind: AccessKind) {
+        if kind == AccessKind::Write {
+            self.written[address as usize] = true;
+        } else if self.is_uninitialized(address) && self.reported.insert((self.pc, address)) {
+            self.reads.push(UninitializedRead {
+                address: address,
+                pc: self.pc,
+                cycle: cycle,
+                backtrace: self.frames.iter().rev().cloned().collect(),
+            });
+        }
+    }
+}
+
+impl InstructionObserver for Sanitizer {
+    fn executing(&mut self, registers: &Registers, _memory: &ReadMemory) {
+        self.pc = registers.pc;
+    }
+
+    fn executed(&mut self, _pc: u16, _op_code: u8, _cycles: usize, frames: &[CallFrame]) {
+        // The frames are from after the instruction, so they're the ones the next one runs in
+        if self.frames.as_slice() != frames {
+            self.frames = frames.to_vec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use emulator::{MemoryMap, RamFill};
+
+    #[test]
+    fn test_uninitialized_reads() {
+        let program = Assembler::new(0xE000)
+            .source(
+                "start:  LDA #1
+                         STA $10
+                         JSR check
+                         JSR check
+                 halt:   JMP halt
+                 check:  LDA $10
+                         ADC $11
+                         RTS
+                 .reset start",
+            )
+            .assemble()
+            .unwrap();
+        let memory = MemoryMap::builder()
+            .ram_fill(RamFill::Random(6502))
+            .ram(0x0000, 0xDFFF)
+            .rom(0xE000, 0xFFFF, program.to_rom(0xE000, 0xFFFF).unwrap())
+            .build();
+        let mut cpu = Cpu::new(memory);
+        let sanitizer = Sanitizer::new().ram(0x0000, 0xDFFF).attach(&mut cpu);
+
+        let symbols = program.symbols();
+        while cpu.registers().pc != symbols.address_of("halt").unwrap() {
+            cpu.step();
+        }
+
+        let sanitizer = sanitizer.borrow();
+        assert_eq!(1, sanitizer.reads().len());
+        let read = &sanitizer.reads()[0];
+        assert_eq!(0x0011, read.address);
+        assert_eq!(symbols.address_of("check").unwrap() + 2, read.pc);
+        assert_eq!(1, read.backtrace.len());
+        assert_eq!(symbols.address_of("start").unwrap() + 4, read.backtrace[0].call_site);
+        assert!(!sanitizer.is_uninitialized(0x0010));
+
+        let mut report = Vec::new();
+        sanitizer.write_report(&mut report, symbols).unwrap();
+        assert!(String::from_utf8(report)
+            .unwrap()
+            .contains("uninitialized read of $0011 by check+2 at cycle"));
+    }
+
+    #[test]
+    fn test_random_fill() {
+        let memory = MemoryMap::builder()
+            .ram_fill(RamFill::Random(1))
+            .ram(0x0000, 0xFFFF)
+            .build();
+        let nonzero = (0..0x100).filter(|&address| memory.debug_read().byte(address) != 0).count();
+        assert!(nonzero > 200);
+    }
+}
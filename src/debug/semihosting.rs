@@ -0,0 +1,77 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::io::Write;
+
+use emulator::{InterruptType, MemoryMap, MemoryMappedDevice};
+
+/// Writing to this register requests that the emulator exit with the written value
+const REG_EXIT: u16 = 0;
+/// Writing to this register sends a character to the host console
+const REG_PUTCHAR: u16 = 1;
+
+/// The number of addresses occupied by the semihosting device
+pub const SEMIHOSTING_SIZE: u16 = 2;
+
+/// A two-register device that lets programs running in the emulator talk to the
+/// host, which is useful for running test ROMs from scripts:
+///
+/// * `base + 0`: writing a byte requests an exit with that byte as the status
+/// * `base + 1`: writing a byte prints it to the console
+pub struct SemihostingDevice {
+    base: u16,
+    console: Box<Write>,
+    exit_code: Option<u8>,
+}
+
+impl SemihostingDevice {
+    /// Creates a new semihosting device mapped at `base`
+    pub fn new(base: u16, console: Box<Write>) -> SemihostingDevice {
+        SemihostingDevice {
+            base: base,
+            console: console,
+            exit_code: None,
+        }
+    }
+
+    /// Returns the exit status requested by the program, if any
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+}
+
+impl MemoryMappedDevice for SemihostingDevice {
+    fn read_byte(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn read_byte_mut(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        match addr.wrapping_sub(self.base) {
+            REG_EXIT => self.exit_code = Some(val),
+            REG_PUTCHAR => {
+                // Console output is best effort; a closed pipe shouldn't stop the emulator
+                let _ = self.console.write_all(&[val]);
+                let _ = self.console.flush();
+            }
+            _ => {}
+        }
+    }
+
+    fn requires_step(&self) -> bool {
+        false
+    }
+
+    fn step(&mut self, _memory: &mut MemoryMap) -> Option<InterruptType> {
+        None
+    }
+}
@@ -0,0 +1,536 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::{BTreeMap, HashMap};
+use std::collections::Bound::{Included, Unbounded};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Errors that can occur while loading debug info
+#[derive(Debug)]
+pub enum SymbolError {
+    /// The debug info file couldn't be read
+    Io(io::Error),
+    /// A line in the file couldn't be parsed. Line numbers start at 1.
+    Parse { line: usize, reason: &'static str },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SymbolError::Io(ref err) => write!(f, "failed to read debug info: {}", err),
+            SymbolError::Parse { line, reason } => write!(f, "failed to parse line {}: {}", line, reason),
+        }
+    }
+}
+
+impl error::Error for SymbolError {
+    fn description(&self) -> &str {
+        match *self {
+            SymbolError::Io(_) => "failed to read debug info",
+            SymbolError::Parse { .. } => "failed to parse debug info",
+        }
+    }
+}
+
+impl From<io::Error> for SymbolError {
+    fn from(err: io::Error) -> SymbolError {
+        SymbolError::Io(err)
+    }
+}
+
+/// A location in a source file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LineSpan {
+    end_inclusive: u16,
+    source: SourceLine,
+}
+
+/// Parses a number in any of the notations 6502 toolchains commonly
+/// emit: `$E000`, `0xE000`, `%1010` or plain decimal
+pub fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if text.starts_with('$') {
+        u32::from_str_radix(&text[1..], 16).ok()
+    } else if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16).ok()
+    } else if text.starts_with('%') {
+        u32::from_str_radix(&text[1..], 2).ok()
+    } else {
+        text.parse::<u32>().ok()
+    }
+}
+
+fn parse_address(line: usize, text: &str) -> Result<u16, SymbolError> {
+    match parse_number(text) {
+        Some(val) if val <= 0xFFFF => Ok(val as u16),
+        _ => Err(SymbolError::Parse {
+            line: line,
+            reason: "invalid address",
+        }),
+    }
+}
+
+/// Splits a ca65 debug info attribute list (`id=0,name="a,b",size=3`) into key/value pairs
+fn parse_dbg_attributes(text: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut key = String::new();
+    let mut value = String::new();
+    let mut in_value = false;
+    let mut in_quotes = false;
+    for chr in text.chars() {
+        match chr {
+            '"' => in_quotes = !in_quotes,
+            '=' if !in_value && !in_quotes => in_value = true,
+            ',' if !in_quotes => {
+                result.insert(key.trim().into(), value.clone());
+                key.clear();
+                value.clear();
+                in_value = false;
+            }
+            _ if in_value => value.push(chr),
+            _ => key.push(chr),
+        }
+    }
+    if !key.is_empty() {
+        result.insert(key.trim().into(), value);
+    }
+    result
+}
+
+/// Symbol and source line information for a program. This is shared by
+/// the disassembler, tracer, monitor and debugger front-ends so that they
+/// can all report addresses as `print_string+12 (src/io.s:88)`.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    by_address: BTreeMap<u16, Vec<String>>,
+    by_name: HashMap<String, u16>,
+    lines: BTreeMap<u16, LineSpan>,
+}
+
+impl SymbolTable {
+    /// Creates an empty symbol table
+    pub fn new() -> SymbolTable {
+        Default::default()
+    }
+
+    /// Loads debug info from a file, detecting whether it's a ca65/ld65 `.dbg` file,
+    /// a VICE label file, or a plain `label = $addr` list
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SymbolTable, SymbolError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        SymbolTable::parse(&text)
+    }
+
+    /// Parses debug info, detecting the format the same way `load` does
+    pub fn parse(text: &str) -> Result<SymbolTable, SymbolError> {
+        let first_line = text.lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with(';'))
+            .unwrap_or("");
+        if first_line.starts_with("version") {
+            SymbolTable::from_ca65_dbg(text)
+        } else if first_line.starts_with("al ") {
+            SymbolTable::from_vice_labels(text)
+        } else {
+            SymbolTable::from_label_list(text)
+        }
+    }
+
+    /// Parses a ca65/ld65 debug info file (as written by `ld65 --dbgfile`).
+    /// Labels and the address ranges of source lines are loaded; everything
+    /// else is ignored.
+    pub fn from_ca65_dbg(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut files: HashMap<String, String> = HashMap::new();
+        let mut segments: HashMap<String, u32> = HashMap::new();
+        let mut spans: HashMap<String, (String, u32, u32)> = HashMap::new();
+        let mut line_records: Vec<(usize, HashMap<String, String>)> = Vec::new();
+        let mut table = SymbolTable::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let raw_line = raw_line.trim();
+            let split = raw_line.find(|chr: char| chr.is_whitespace());
+            let (kind, rest) = match split {
+                Some(split) => (&raw_line[..split], &raw_line[split..]),
+                None => continue,
+            };
+            let attributes = parse_dbg_attributes(rest.trim());
+            let id = attributes.get("id").cloned().unwrap_or_default();
+            match kind {
+                "file" => {
+                    if let Some(name) = attributes.get("name") {
+                        files.insert(id, name.clone());
+                    }
+                }
+                "seg" => {
+                    let start = attributes
+                        .get("start")
+                        .and_then(|start| parse_number(start))
+                        .ok_or(SymbolError::Parse {
+                            line: line,
+                            reason: "segment without a start address",
+                        })?;
+                    segments.insert(id, start);
+                }
+                "span" => {
+                    let seg = attributes.get("seg").cloned().unwrap_or_default();
+                    let start = attributes.get("start").and_then(|val| parse_number(val));
+                    let size = attributes.get("size").and_then(|val| parse_number(val));
+                    match (start, size) {
+                        (Some(start), Some(size)) => {
+                            spans.insert(id, (seg, start, size));
+                        }
+                        _ => {
+                            return Err(SymbolError::Parse {
+                                line: line,
+                                reason: "span without a start or size",
+                            })
+                        }
+                    }
+                }
+                "line" => line_records.push((line, attributes)),
+                "sym" => {
+                    let is_label = attributes.get("type").map(|val| val == "lab").unwrap_or(false);
+                    if let (true, Some(name), Some(val)) = (is_label, attributes.get("name"), attributes.get("val")) {
+                        table.add_symbol(name.clone(), parse_address(line, val)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (line, attributes) in line_records {
+            // Skip lines that come from macro expansions; the invocation site is more useful
+            if attributes.get("type").map(|val| val == "2").unwrap_or(false) {
+                continue;
+            }
+            let file = match attributes.get("file").and_then(|id| files.get(id)) {
+                Some(file) => file.clone(),
+                None => continue,
+            };
+            let line_number = attributes
+                .get("line")
+                .and_then(|val| parse_number(val))
+                .ok_or(SymbolError::Parse {
+                    line: line,
+                    reason: "line record without a line number",
+                })?;
+            let span_ids = match attributes.get("span") {
+                Some(span_ids) => span_ids,
+                None => continue,
+            };
+            for span_id in span_ids.split('+') {
+                if let Some(&(ref seg, start, size)) = spans.get(span_id) {
+                    if let (Some(seg_start), true) = (segments.get(seg), size > 0) {
+                        let address = seg_start + start;
+                        if address + size - 1 > 0xFFFF {
+                            return Err(SymbolError::Parse {
+                                line: line,
+                                reason: "span is outside of the address space",
+                            });
+                        }
+                        table.add_line(
+                            address as u16,
+                            (address + size - 1) as u16,
+                            SourceLine {
+                                file: file.clone(),
+                                line: line_number as usize,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    /// Parses a VICE label file (as written by `ld65 -Ln` or the VICE monitor),
+    /// where each line looks like `al C:e010 .print_string`
+    pub fn from_vice_labels(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::new();
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let mut parts = raw_line.split_whitespace();
+            match parts.next() {
+                Some("al") => {}
+                _ => continue,
+            }
+            let (address, name) = match (parts.next(), parts.next()) {
+                (Some(address), Some(name)) => (address, name),
+                _ => {
+                    return Err(SymbolError::Parse {
+                        line: line,
+                        reason: "expected an address and a label",
+                    })
+                }
+            };
+            let address = match address.find(':') {
+                Some(colon) => &address[colon + 1..],
+                None => address,
+            };
+            let address = u16::from_str_radix(address, 16).map_err(|_| SymbolError::Parse {
+                line: line,
+                reason: "invalid address",
+            })?;
+            let name = if name.starts_with('.') { &name[1..] } else { name };
+            table.add_symbol(name.into(), address);
+        }
+        Ok(table)
+    }
+
+    /// Parses a plain list of `label = $addr` assignments. Comments start with `;`.
+    pub fn from_label_list(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::new();
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let content = match raw_line.find(';') {
+                Some(comment) => &raw_line[..comment],
+                None => raw_line,
+            }.trim();
+            if content.is_empty() {
+                continue;
+            }
+            let equals = content.find('=').ok_or(SymbolError::Parse {
+                line: line,
+                reason: "expected `label = address`",
+            })?;
+            let name = content[..equals].trim_matches(':').trim();
+            if name.is_empty() {
+                return Err(SymbolError::Parse {
+                    line: line,
+                    reason: "missing label name",
+                });
+            }
+            let address = parse_address(line, &content[equals + 1..])?;
+            table.add_symbol(name.into(), address);
+        }
+        Ok(table)
+    }
+
+    /// Adds a label, moving it if it was already defined at another address
+    pub fn add_symbol(&mut self, name: String, address: u16) {
+        if let Some(previous) = self.by_name.insert(name.clone(), address) {
+            if previous != address {
+                let now_empty = match self.by_address.get_mut(&previous) {
+                    Some(names) => {
+                        names.retain(|other| *other != name);
+                        names.is_empty()
+                    }
+                    None => false,
+                };
+                if now_empty {
+                    self.by_address.remove(&previous);
+                }
+            }
+        }
+        let names = self.by_address.entry(address).or_insert_with(Vec::new);
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    /// Records that the given address range was generated by a source line
+    pub fn add_line(&mut self, start: u16, end_inclusive: u16, source: SourceLine) {
+        self.lines.insert(
+            start,
+            LineSpan {
+                end_inclusive: end_inclusive,
+                source: source,
+            },
+        );
+    }
+
+    /// Adds all symbols and lines from another table to this one
+    pub fn merge(&mut self, other: SymbolTable) {
+        for (address, names) in other.by_address {
+            for name in names {
+                self.add_symbol(name, address);
+            }
+        }
+        self.lines.extend(other.lines);
+    }
+
+    /// Returns true if there are no symbols or lines
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty() && self.lines.is_empty()
+    }
+
+    /// Looks up the address of a label
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).cloned()
+    }
+
+    /// Returns the first label defined exactly at the given address
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.by_address
+            .get(&address)
+            .and_then(|names| names.first())
+            .map(|name| name.as_str())
+    }
+
+    /// Returns the closest label at or before the given address along with the offset from it
+    pub fn nearest_label(&self, address: u16) -> Option<(&str, u16)> {
+        self.by_address
+            .range((Unbounded, Included(address)))
+            .next_back()
+            .and_then(|(label_address, names)| {
+                names
+                    .first()
+                    .map(|name| (name.as_str(), address - label_address))
+            })
+    }
+
+    /// Returns the source line that generated the byte at the given address
+    pub fn source_line(&self, address: u16) -> Option<&SourceLine> {
+        self.lines
+            .range((Unbounded, Included(address)))
+            .next_back()
+            .and_then(|(_, span)| {
+                if span.end_inclusive >= address {
+                    Some(&span.source)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Returns every address range that was generated by the given source line
+    pub fn addresses_for_line(&self, file: &str, line: usize) -> Vec<u16> {
+        self.lines
+            .iter()
+            .filter(|&(_, span)| span.source.line == line && file_matches(&span.source.file, file))
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    /// Iterates over all labels in address order
+    pub fn labels<'a>(&'a self) -> Box<Iterator<Item = (u16, &'a str)> + 'a> {
+        Box::new(
+            self.by_address
+                .iter()
+                .flat_map(|(address, names)| names.iter().map(move |name| (*address, name.as_str()))),
+        )
+    }
+
+    /// Iterates over the address ranges generated by source lines, as
+    /// `(start, end_inclusive, source)` in address order
+    pub fn lines<'a>(&'a self) -> Box<Iterator<Item = (u16, u16, &'a SourceLine)> + 'a> {
+        Box::new(
+            self.lines
+                .iter()
+                .map(|(start, span)| (*start, span.end_inclusive, &span.source)),
+        )
+    }
+
+    /// Formats an address symbolically as `label+offset`, falling back to `$XXXX`
+    pub fn format_address(&self, address: u16) -> String {
+        match self.nearest_label(address) {
+            Some((label, 0)) => label.into(),
+            Some((label, offset)) => format!("{}+{}", label, offset),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    /// Describes an address with as much information as is available,
+    /// such as `print_string+12 (src/io.s:88)`
+    pub fn describe(&self, address: u16) -> String {
+        match self.source_line(address) {
+            Some(source) => format!("{} ({})", self.format_address(address), source),
+            None => self.format_address(address),
+        }
+    }
+}
+
+/// Source file names in debug info are often relative to wherever the assembler ran,
+/// so a path matches if either one is a suffix of the other on a path boundary
+fn file_matches(debug_path: &str, requested: &str) -> bool {
+    let debug_path = debug_path.replace('\\', "/");
+    let requested = requested.replace('\\', "/");
+    if debug_path == requested {
+        return true;
+    }
+    let (longer, shorter) = if debug_path.len() > requested.len() {
+        (&debug_path, &requested)
+    } else {
+        (&requested, &debug_path)
+    };
+    longer.ends_with(shorter.as_str()) && longer[..longer.len() - shorter.len()].ends_with('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA65_DBG: &'static str = "version\tmajor=2,minor=0\n\
+        info\tcsym=0,file=1,lib=0,line=2,mod=1,scope=1,seg=1,span=2,sym=2,type=1\n\
+        file\tid=0,name=\"src/io.s\",size=512,mtime=0x5A000000,mod=0\n\
+        line\tid=0,file=0,line=80,span=0\n\
+        line\tid=1,file=0,line=88,span=1\n\
+        seg\tid=0,name=\"CODE\",start=0x00E000,size=0x0100,addrsize=absolute,type=ro\n\
+        span\tid=0,seg=0,start=16,size=12\n\
+        span\tid=1,seg=0,start=28,size=3\n\
+        sym\tid=0,name=\"print_string\",addrsize=absolute,size=40,scope=0,def=0,val=0xE010,seg=0,type=lab\n\
+        sym\tid=1,name=\"SCREEN_WIDTH\",addrsize=zeropage,scope=0,def=0,val=0x47,type=equ\n";
+
+    #[test]
+    fn test_ca65_dbg() {
+        let table = SymbolTable::parse(CA65_DBG).unwrap();
+        assert_eq!(Some(0xE010), table.address_of("print_string"));
+        assert_eq!(None, table.address_of("SCREEN_WIDTH"));
+        assert_eq!("print_string+12 (src/io.s:88)", table.describe(0xE01C));
+        assert_eq!("print_string+2 (src/io.s:80)", table.describe(0xE012));
+        assert_eq!("$0000", table.describe(0x0000));
+        assert_eq!(vec![0xE01C], table.addresses_for_line("io.s", 88));
+    }
+
+    #[test]
+    fn test_vice_labels() {
+        let table = SymbolTable::parse("al C:e010 .print_string\nal C:0200 .buffer\n").unwrap();
+        assert_eq!(Some("print_string"), table.label_at(0xE010));
+        assert_eq!(Some(("buffer", 5)), table.nearest_label(0x0205));
+    }
+
+    #[test]
+    fn test_label_list() {
+        let table = SymbolTable::parse("; zero page\nptr = $10\nreset := 0xE000\ncount = 32\n").unwrap();
+        assert_eq!(Some(0x10), table.address_of("ptr"));
+        assert_eq!(Some(0xE000), table.address_of("reset"));
+        assert_eq!(Some(32), table.address_of("count"));
+        assert!(SymbolTable::from_label_list("oops $10").is_err());
+    }
+
+    #[test]
+    fn test_redefine() {
+        let table = SymbolTable::parse("loop = $0200
+start = $0200
+loop = $0210
+start = $0300
+").unwrap();
+        assert_eq!(Some(0x0210), table.address_of("loop"));
+        assert_eq!(None, table.label_at(0x0200));
+        assert_eq!(Some("loop"), table.label_at(0x0210));
+        assert_eq!(vec![(0x0210, "loop"), (0x0300, "start")], table.labels().collect::<Vec<_>>());
+    }
+}
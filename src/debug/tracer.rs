@@ -0,0 +1,70 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::io::{self, Write};
+
+use debug::disassembler::disassemble;
+use debug::symbols::SymbolTable;
+use emulator::Cpu;
+
+/// Writes one line per executed instruction, in the style of most 6502 emulator
+/// trace logs. Call `trace` before each `Cpu::step`.
+pub struct Tracer<W: Write> {
+    output: W,
+    symbols: Option<SymbolTable>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Creates a tracer that writes to the given output
+    pub fn new(output: W) -> Tracer<W> {
+        Tracer {
+            output: output,
+            symbols: None,
+        }
+    }
+
+    /// Uses the given symbols to label addresses and operands
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Writes a line describing the instruction that the CPU is about to execute
+    pub fn trace(&mut self, cpu: &Cpu) -> io::Result<()> {
+        let registers = cpu.registers();
+        let instruction = disassemble(cpu.memory().debug_read(), registers.pc);
+
+        if let Some(label) = self.symbols.as_ref().and_then(|symbols| symbols.label_at(registers.pc)) {
+            writeln!(self.output, "{}:", label)?;
+        }
+        write!(
+            self.output,
+            "{:04X}  {}  {:<16} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} CYC:{}",
+            registers.pc,
+            instruction.format_bytes(),
+            instruction.format(self.symbols.as_ref()),
+            registers.a,
+            registers.x,
+            registers.y,
+            registers.sp,
+            registers.status.value(),
+            cpu.cycles()
+        )?;
+        if let Some(source) = self.symbols.as_ref().and_then(|symbols| symbols.source_line(registers.pc)) {
+            write!(self.output, "  ; {}", source)?;
+        }
+        writeln!(self.output)
+    }
+
+    /// Flushes and returns the underlying output
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
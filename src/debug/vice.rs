@@ -0,0 +1,700 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! A server for the VICE binary monitor protocol, so that debuggers written for VICE
+//! can attach to the emulator. Only the main CPU memory space and exec checkpoints
+//! are supported.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use emulator::{Cpu, StepTarget, WriteMode};
+
+const STX: u8 = 0x02;
+const API_VERSION: u8 = 0x02;
+const COMMAND_HEADER_SIZE: usize = 11;
+const EVENT_REQUEST_ID: u32 = 0xFFFF_FFFF;
+
+const MEMORY_GET: u8 = 0x01;
+const MEMORY_SET: u8 = 0x02;
+const CHECKPOINT_GET: u8 = 0x11;
+const CHECKPOINT_SET: u8 = 0x12;
+const CHECKPOINT_DELETE: u8 = 0x13;
+const CHECKPOINT_LIST: u8 = 0x14;
+const CHECKPOINT_TOGGLE: u8 = 0x15;
+const REGISTERS_GET: u8 = 0x31;
+const REGISTERS_SET: u8 = 0x32;
+const STOPPED: u8 = 0x62;
+const RESUMED: u8 = 0x63;
+const ADVANCE_INSTRUCTIONS: u8 = 0x71;
+const EXECUTE_UNTIL_RETURN: u8 = 0x73;
+const PING: u8 = 0x81;
+const REGISTERS_AVAILABLE: u8 = 0x83;
+const EXIT: u8 = 0xAA;
+const QUIT: u8 = 0xBB;
+
+const OK: u8 = 0x00;
+const ERR_OBJECT_MISSING: u8 = 0x01;
+const ERR_INVALID_MEMSPACE: u8 = 0x02;
+const ERR_INVALID_LENGTH: u8 = 0x80;
+const ERR_INVALID_PARAMETER: u8 = 0x81;
+const ERR_UNSUPPORTED_VERSION: u8 = 0x82;
+const ERR_UNSUPPORTED_COMMAND: u8 = 0x83;
+
+const MAIN_MEMSPACE: u8 = 0x00;
+const CPU_OP_EXEC: u8 = 0x04;
+
+const REG_A: u8 = 0x00;
+const REG_X: u8 = 0x01;
+const REG_Y: u8 = 0x02;
+const REG_PC: u8 = 0x03;
+const REG_SP: u8 = 0x04;
+const REG_FLAGS: u8 = 0x05;
+
+/// Register ID, size in bits, and name
+const REGISTERS: [(u8, u8, &'static str); 6] = [
+    (REG_A, 8, "A"),
+    (REG_X, 8, "X"),
+    (REG_Y, 8, "Y"),
+    (REG_PC, 16, "PC"),
+    (REG_SP, 8, "SP"),
+    (REG_FLAGS, 8, "FL"),
+];
+
+/// Number of instructions to execute between checks for incoming commands while running
+const POLL_INTERVAL: usize = 1000;
+
+fn read_u16(data: &[u8]) -> u16 {
+    (data[1] as u16) << 8 | data[0] as u16
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (data[3] as u32) << 24 | (data[2] as u32) << 16 | (data[1] as u32) << 8 | data[0] as u32
+}
+
+fn push_u16(data: &mut Vec<u8>, val: u16) {
+    data.push(val as u8);
+    data.push((val >> 8) as u8);
+}
+
+fn push_u32(data: &mut Vec<u8>, val: u32) {
+    push_u16(data, val as u16);
+    push_u16(data, (val >> 16) as u16);
+}
+
+fn check_length(body: &[u8], min_length: usize) -> Result<(), u8> {
+    if body.len() < min_length {
+        Err(ERR_INVALID_LENGTH)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_memspace(memspace: u8) -> Result<(), u8> {
+    if memspace == MAIN_MEMSPACE {
+        Ok(())
+    } else {
+        Err(ERR_INVALID_MEMSPACE)
+    }
+}
+
+/// Several commands respond with another command's response type
+fn response_type(command_type: u8) -> u8 {
+    match command_type {
+        CHECKPOINT_SET => CHECKPOINT_GET,
+        REGISTERS_SET => REGISTERS_GET,
+        other => other,
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Checkpoint {
+    start: u16,
+    end_inclusive: u16,
+    stop: bool,
+    enabled: bool,
+    temporary: bool,
+    hit_count: u32,
+}
+
+impl Checkpoint {
+    fn info(&self, number: u32, hit: bool) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_u32(&mut body, number);
+        body.push(hit as u8);
+        push_u16(&mut body, self.start);
+        push_u16(&mut body, self.end_inclusive);
+        body.push(self.stop as u8);
+        body.push(self.enabled as u8);
+        body.push(CPU_OP_EXEC);
+        body.push(self.temporary as u8);
+        push_u32(&mut body, self.hit_count);
+        push_u32(&mut body, 0); // ignore count
+        body.push(0); // has condition
+        body.push(MAIN_MEMSPACE);
+        body
+    }
+}
+
+#[derive(Debug)]
+enum RunMode {
+    Resume,
+    Advance {
+        remaining: u16,
+        step_over: bool,
+        target: Option<StepTarget>,
+    },
+    /// Runs until the innermost call returns, or like `Resume` outside of any call
+    UntilReturn { target: Option<StepTarget> },
+}
+
+enum Action {
+    Stay,
+    Run(RunMode),
+    Quit,
+}
+
+struct Command {
+    version: u8,
+    request_id: u32,
+    kind: u8,
+    body: Vec<u8>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn take_command(&mut self) -> io::Result<Option<Command>> {
+        if self.buffer.len() < COMMAND_HEADER_SIZE {
+            return Ok(None);
+        }
+        if self.buffer[0] != STX {
+            return Err(io::Error::new(ErrorKind::InvalidData, "command doesn't start with STX"));
+        }
+        let length = COMMAND_HEADER_SIZE + read_u32(&self.buffer[2..]) as usize;
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+        let command = Command {
+            version: self.buffer[1],
+            request_id: read_u32(&self.buffer[6..]),
+            kind: self.buffer[10],
+            body: self.buffer[COMMAND_HEADER_SIZE..length].to_vec(),
+        };
+        self.buffer.drain(..length);
+        Ok(Some(command))
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let mut data = [0u8; 4096];
+        let count = self.stream.read(&mut data)?;
+        if count == 0 {
+            self.closed = true;
+        }
+        self.buffer.extend_from_slice(&data[..count]);
+        Ok(())
+    }
+
+    /// Blocks until a command arrives. Returns None if the client disconnected.
+    fn next_command(&mut self) -> io::Result<Option<Command>> {
+        loop {
+            if let Some(command) = self.take_command()? {
+                return Ok(Some(command));
+            }
+            if self.closed {
+                return Ok(None);
+            }
+            self.fill()?;
+        }
+    }
+
+    /// Returns true if a command is waiting or the client disconnected
+    fn poll(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let result = self.fill();
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {}
+            other => other?,
+        }
+        Ok(self.closed || self.buffer.len() >= COMMAND_HEADER_SIZE)
+    }
+
+    fn send(&mut self, kind: u8, error: u8, request_id: u32, body: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(12 + body.len());
+        packet.push(STX);
+        packet.push(API_VERSION);
+        push_u32(&mut packet, body.len() as u32);
+        packet.push(kind);
+        packet.push(error);
+        push_u32(&mut packet, request_id);
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)
+    }
+}
+
+fn memory_get(cpu: &mut Cpu, body: &[u8]) -> Result<Vec<u8>, u8> {
+    check_length(body, 8)?;
+    check_memspace(body[5])?;
+    let (side_effects, start, end) = (body[0] != 0, read_u16(&body[1..]), read_u16(&body[3..]));
+    if end < start {
+        return Err(ERR_INVALID_PARAMETER);
+    }
+
+    let mut response = Vec::new();
+    push_u16(&mut response, (end - start).wrapping_add(1));
+    for address in start as usize..end as usize + 1 {
+        response.push(if side_effects {
+            cpu.memory_mut().read().byte(address as u16)
+        } else {
+            cpu.memory().debug_read().byte(address as u16)
+        });
+    }
+    Ok(response)
+}
+
+fn memory_set(cpu: &mut Cpu, body: &[u8]) -> Result<Vec<u8>, u8> {
+    check_length(body, 8)?;
+    check_memspace(body[5])?;
+    let (side_effects, start, end) = (body[0] != 0, read_u16(&body[1..]), read_u16(&body[3..]));
+    if end < start {
+        return Err(ERR_INVALID_PARAMETER);
+    }
+    let data = &body[8..];
+    if data.len() != (end - start) as usize + 1 {
+        return Err(ERR_INVALID_LENGTH);
+    }
+    // Without side effects, write like a device programmer so that ROM can be patched
+    let mode = if side_effects {
+        WriteMode::Bus
+    } else {
+        WriteMode::Programmer
+    };
+    cpu.memory_mut().load(start, data, mode);
+    Ok(Vec::new())
+}
+
+fn registers_get(cpu: &Cpu) -> Vec<u8> {
+    let registers = cpu.registers();
+    let mut response = Vec::new();
+    push_u16(&mut response, REGISTERS.len() as u16);
+    for &(id, _, _) in REGISTERS.iter() {
+        let value = match id {
+            REG_A => registers.a as u16,
+            REG_X => registers.x as u16,
+            REG_Y => registers.y as u16,
+            REG_PC => registers.pc,
+            REG_SP => registers.sp as u16,
+            _ => registers.status.value() as u16,
+        };
+        response.push(3);
+        response.push(id);
+        push_u16(&mut response, value);
+    }
+    response
+}
+
+fn registers_set(cpu: &mut Cpu, body: &[u8]) -> Result<Vec<u8>, u8> {
+    check_length(body, 3)?;
+    check_memspace(body[0])?;
+    let count = read_u16(&body[1..]);
+
+    // Validate everything before changing anything
+    let mut updates = Vec::new();
+    let mut offset = 3;
+    for _ in 0..count {
+        check_length(body, offset + 4)?;
+        let size = body[offset] as usize;
+        if size < 3 {
+            return Err(ERR_INVALID_LENGTH);
+        }
+        let (id, value) = (body[offset + 1], read_u16(&body[offset + 2..]));
+        if !REGISTERS.iter().any(|&(reg, _, _)| reg == id) {
+            return Err(ERR_OBJECT_MISSING);
+        }
+        updates.push((id, value));
+        offset += size + 1;
+    }
+
+    {
+        let registers = cpu.registers_mut();
+        for (id, value) in updates {
+            match id {
+                REG_A => registers.a = value as u8,
+                REG_X => registers.x = value as u8,
+                REG_Y => registers.y = value as u8,
+                REG_PC => registers.pc = value,
+                REG_SP => registers.sp = value as u8,
+                _ => registers.status.set_value(value as u8),
+            }
+        }
+    }
+    Ok(registers_get(cpu))
+}
+
+fn registers_available(body: &[u8]) -> Result<Vec<u8>, u8> {
+    check_length(body, 1)?;
+    check_memspace(body[0])?;
+    let mut response = Vec::new();
+    push_u16(&mut response, REGISTERS.len() as u16);
+    for &(id, bits, name) in REGISTERS.iter() {
+        response.push(3 + name.len() as u8);
+        response.push(id);
+        response.push(bits);
+        response.push(name.len() as u8);
+        response.extend_from_slice(name.as_bytes());
+    }
+    Ok(response)
+}
+
+fn parse_advance(body: &[u8]) -> Result<RunMode, u8> {
+    check_length(body, 3)?;
+    Ok(RunMode::Advance {
+        remaining: read_u16(&body[1..]).max(1),
+        step_over: body[0] != 0,
+        target: None,
+    })
+}
+
+/// Executes one instruction and returns true if that completes the run mode
+fn step(cpu: &mut Cpu, mode: &mut RunMode) -> bool {
+    match *mode {
+        RunMode::Resume | RunMode::UntilReturn { target: None } => {
+            cpu.step();
+            false
+        }
+        RunMode::Advance {
+            ref mut remaining,
+            step_over,
+            ref mut target,
+        } => {
+            if step_over {
+                let goal = target.take().unwrap_or_else(|| cpu.step_over_target());
+                if !cpu.step_toward(goal) {
+                    *target = Some(goal);
+                    return false;
+                }
+            } else {
+                cpu.step();
+            }
+            *remaining -= 1;
+            *remaining == 0
+        }
+        RunMode::UntilReturn { target: Some(target) } => cpu.step_toward(target),
+    }
+}
+
+/// Serves the VICE binary monitor protocol over TCP.
+///
+/// The CPU starts out stopped when a client connects. Sending any command while the CPU
+/// is running stops it again, the same as it does in VICE.
+pub struct ViceServer {
+    listener: TcpListener,
+    checkpoints: BTreeMap<u32, Checkpoint>,
+    next_checkpoint: u32,
+}
+
+impl ViceServer {
+    /// Listens on the given address. VICE uses port 6502 by default.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<ViceServer> {
+        Ok(ViceServer {
+            listener: TcpListener::bind(address)?,
+            checkpoints: BTreeMap::new(),
+            next_checkpoint: 1,
+        })
+    }
+
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Waits for a client to connect, and then debugs the CPU until that client
+    /// disconnects or sends a quit command. Checkpoints are kept between clients.
+    pub fn serve(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream: stream,
+            buffer: Vec::new(),
+            closed: false,
+        };
+
+        let mut running: Option<RunMode> = None;
+        loop {
+            let mut mode = match running.take() {
+                Some(mode) => mode,
+                None => {
+                    let command = match connection.next_command()? {
+                        Some(command) => command,
+                        None => return Ok(()),
+                    };
+                    match self.handle(cpu, &mut connection, command)? {
+                        Action::Stay => {}
+                        Action::Run(mode) => running = Some(mode),
+                        Action::Quit => return Ok(()),
+                    }
+                    continue;
+                }
+            };
+
+            let mut stopped = false;
+            for _ in 0..POLL_INTERVAL {
+                let finished = step(cpu, &mut mode);
+                if self.check_checkpoints(cpu, &mut connection)? || finished {
+                    stopped = true;
+                    break;
+                }
+            }
+            if stopped || connection.poll()? {
+                connection.send(REGISTERS_GET, OK, EVENT_REQUEST_ID, &registers_get(cpu))?;
+                let mut pc = Vec::new();
+                push_u16(&mut pc, cpu.registers().pc);
+                connection.send(STOPPED, OK, EVENT_REQUEST_ID, &pc)?;
+            } else {
+                running = Some(mode);
+            }
+        }
+    }
+
+    /// Counts hits on the exec checkpoints at the current PC, and returns true
+    /// if any of them should stop execution
+    fn check_checkpoints(&mut self, cpu: &Cpu, connection: &mut Connection) -> io::Result<bool> {
+        let pc = cpu.registers().pc;
+        let mut stop = false;
+        let mut temporary = Vec::new();
+        for (number, checkpoint) in &mut self.checkpoints {
+            if !checkpoint.enabled || pc < checkpoint.start || pc > checkpoint.end_inclusive {
+                continue;
+            }
+            checkpoint.hit_count += 1;
+            if checkpoint.stop {
+                stop = true;
+                connection.send(CHECKPOINT_GET, OK, EVENT_REQUEST_ID, &checkpoint.info(*number, true))?;
+            }
+            if checkpoint.temporary {
+                temporary.push(*number);
+            }
+        }
+        for number in temporary {
+            self.checkpoints.remove(&number);
+        }
+        Ok(stop)
+    }
+
+    fn checkpoint_number(&self, body: &[u8]) -> Result<u32, u8> {
+        check_length(body, 4)?;
+        let number = read_u32(body);
+        if self.checkpoints.contains_key(&number) {
+            Ok(number)
+        } else {
+            Err(ERR_OBJECT_MISSING)
+        }
+    }
+
+    fn checkpoint_set(&mut self, body: &[u8]) -> Result<Vec<u8>, u8> {
+        check_length(body, 8)?;
+        if body.len() > 8 {
+            check_memspace(body[8])?;
+        }
+        let (start, end_inclusive) = (read_u16(body), read_u16(&body[2..]));
+        if end_inclusive < start || body[6] != CPU_OP_EXEC {
+            return Err(ERR_INVALID_PARAMETER);
+        }
+
+        let number = self.next_checkpoint;
+        self.next_checkpoint += 1;
+        let checkpoint = Checkpoint {
+            start: start,
+            end_inclusive: end_inclusive,
+            stop: body[4] != 0,
+            enabled: body[5] != 0,
+            temporary: body[7] != 0,
+            hit_count: 0,
+        };
+        let info = checkpoint.info(number, false);
+        self.checkpoints.insert(number, checkpoint);
+        Ok(info)
+    }
+
+    fn handle(&mut self, cpu: &mut Cpu, connection: &mut Connection, command: Command) -> io::Result<Action> {
+        let request_id = command.request_id;
+        let kind = response_type(command.kind);
+        if command.version != 0x01 && command.version != API_VERSION {
+            connection.send(kind, ERR_UNSUPPORTED_VERSION, request_id, &[])?;
+            return Ok(Action::Stay);
+        }
+
+        let body = &command.body[..];
+        let mut action = Action::Stay;
+        let result = match command.kind {
+            MEMORY_GET => memory_get(cpu, body),
+            MEMORY_SET => memory_set(cpu, body),
+            CHECKPOINT_GET => self.checkpoint_number(body)
+                .map(|number| self.checkpoints[&number].info(number, false)),
+            CHECKPOINT_SET => self.checkpoint_set(body),
+            CHECKPOINT_DELETE => self.checkpoint_number(body).map(|number| {
+                self.checkpoints.remove(&number);
+                Vec::new()
+            }),
+            CHECKPOINT_LIST => {
+                for (number, checkpoint) in &self.checkpoints {
+                    connection.send(CHECKPOINT_GET, OK, request_id, &checkpoint.info(*number, false))?;
+                }
+                let mut response = Vec::new();
+                push_u32(&mut response, self.checkpoints.len() as u32);
+                Ok(response)
+            }
+            CHECKPOINT_TOGGLE => self.checkpoint_number(body).and_then(|number| {
+                check_length(body, 5)?;
+                self.checkpoints.get_mut(&number).unwrap().enabled = body[4] != 0;
+                Ok(Vec::new())
+            }),
+            REGISTERS_GET => check_length(body, 1)
+                .and_then(|_| check_memspace(body[0]))
+                .map(|_| registers_get(cpu)),
+            REGISTERS_SET => registers_set(cpu, body),
+            ADVANCE_INSTRUCTIONS => parse_advance(body).map(|mode| {
+                action = Action::Run(mode);
+                Vec::new()
+            }),
+            EXECUTE_UNTIL_RETURN => {
+                action = Action::Run(RunMode::UntilReturn {
+                    target: cpu.step_out_target(),
+                });
+                Ok(Vec::new())
+            }
+            PING => Ok(Vec::new()),
+            REGISTERS_AVAILABLE => registers_available(body),
+            EXIT => {
+                action = Action::Run(RunMode::Resume);
+                Ok(Vec::new())
+            }
+            QUIT => {
+                action = Action::Quit;
+                Ok(Vec::new())
+            }
+            _ => Err(ERR_UNSUPPORTED_COMMAND),
+        };
+
+        match result {
+            Ok(response) => connection.send(kind, OK, request_id, &response)?,
+            Err(error) => connection.send(kind, error, request_id, &[])?,
+        }
+        if let Action::Run(_) = action {
+            let mut pc = Vec::new();
+            push_u16(&mut pc, cpu.registers().pc);
+            connection.send(RESUMED, OK, EVENT_REQUEST_ID, &pc)?;
+        }
+        Ok(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use emulator::MemoryMap;
+
+    fn send(stream: &mut TcpStream, kind: u8, body: &[u8]) {
+        let mut packet = vec![STX, API_VERSION];
+        push_u32(&mut packet, body.len() as u32);
+        push_u32(&mut packet, 0x1234);
+        packet.push(kind);
+        packet.extend_from_slice(body);
+        stream.write_all(&packet).unwrap();
+    }
+
+    /// Reads one response, and returns its type, error code and body
+    fn receive(stream: &mut TcpStream) -> (u8, u8, Vec<u8>) {
+        let mut header = [0u8; 12];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(STX, header[0]);
+        let mut body = vec![0u8; read_u32(&header[2..]) as usize];
+        stream.read_exact(&mut body).unwrap();
+        (header[6], header[7], body)
+    }
+
+    #[test]
+    fn test_session() {
+        let mut rom = vec![0; 0x1000];
+        rom[0xFFD] = 0x02;
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0xEFFF)
+            .rom(0xF000, 0xFFFF, rom)
+            .build();
+        // LDA #$42; STA $10; JSR $0300; INX; JMP $0208; ... $0300: INY; RTS
+        let program = [0xA9, 0x42, 0x85, 0x10, 0x20, 0x00, 0x03, 0xE8, 0x4C, 0x08, 0x02];
+        memory.load(0x0200, &program, WriteMode::Bus);
+        memory.load(0x0300, &[0xC8, 0x60], WriteMode::Bus);
+        let mut cpu = Cpu::new(memory);
+
+        let mut server = ViceServer::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            send(&mut stream, PING, &[]);
+            assert_eq!((PING, OK, vec![]), receive(&mut stream));
+
+            send(&mut stream, MEMORY_GET, &[0, 0x00, 0x02, 0x01, 0x02, 0, 0, 0]);
+            assert_eq!((MEMORY_GET, OK, vec![2, 0, 0xA9, 0x42]), receive(&mut stream));
+
+            // Writes without side effects reach ROM, and bus writes don't
+            send(&mut stream, MEMORY_SET, &[0, 0x00, 0xF0, 0x00, 0xF0, 0, 0, 0, 0x55]);
+            assert_eq!((MEMORY_SET, OK, vec![]), receive(&mut stream));
+            send(&mut stream, MEMORY_SET, &[1, 0x01, 0xF0, 0x01, 0xF0, 0, 0, 0, 0x66]);
+            assert_eq!((MEMORY_SET, OK, vec![]), receive(&mut stream));
+
+            send(&mut stream, CHECKPOINT_SET, &[0x07, 0x02, 0x07, 0x02, 1, 1, CPU_OP_EXEC, 0]);
+            let (kind, error, body) = receive(&mut stream);
+            assert_eq!((CHECKPOINT_GET, OK, 1), (kind, error, read_u32(&body)));
+
+            send(&mut stream, ADVANCE_INSTRUCTIONS, &[0, 1, 0]);
+            assert_eq!((ADVANCE_INSTRUCTIONS, OK, vec![]), receive(&mut stream));
+            assert_eq!((RESUMED, OK, vec![0x00, 0x02]), receive(&mut stream));
+            assert_eq!(REGISTERS_GET, receive(&mut stream).0);
+            assert_eq!((STOPPED, OK, vec![0x02, 0x02]), receive(&mut stream));
+
+            send(&mut stream, EXIT, &[]);
+            assert_eq!(EXIT, receive(&mut stream).0);
+            assert_eq!(RESUMED, receive(&mut stream).0);
+            let (kind, _, body) = receive(&mut stream);
+            assert_eq!((CHECKPOINT_GET, 1, 1), (kind, read_u32(&body), body[4]));
+            assert_eq!(REGISTERS_GET, receive(&mut stream).0);
+            assert_eq!((STOPPED, OK, vec![0x07, 0x02]), receive(&mut stream));
+
+            send(&mut stream, REGISTERS_SET, &[0, 1, 0, 3, REG_X, 0x10, 0x00]);
+            let (kind, error, body) = receive(&mut stream);
+            assert_eq!((REGISTERS_GET, OK), (kind, error));
+            assert_eq!(&[3, REG_X, 0x10, 0x00], &body[6..10]);
+
+            send(&mut stream, REGISTERS_SET, &[0, 1, 0, 3, 0x77, 0x00, 0x00]);
+            assert_eq!((REGISTERS_GET, ERR_OBJECT_MISSING), {
+                let (kind, error, _) = receive(&mut stream);
+                (kind, error)
+            });
+
+            send(&mut stream, QUIT, &[]);
+            assert_eq!(QUIT, receive(&mut stream).0);
+        });
+
+        server.serve(&mut cpu).unwrap();
+        client.join().unwrap();
+        assert_eq!(0x42, cpu.memory().debug_read().byte(0x0010));
+        assert_eq!(0x55, cpu.memory().debug_read().byte(0xF000));
+        assert_eq!(0x00, cpu.memory().debug_read().byte(0xF001));
+        assert_eq!(1, cpu.registers().y);
+        assert_eq!(0x10, cpu.registers().x);
+    }
+}
@@ -0,0 +1,274 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use emulator::cpu::{InterruptType, ResetKind};
+use emulator::memory::{MemoryMap, MemoryMappedDevice, RAMDevice, ROMDevice};
+
+/// Selects which bank a banked window or overlay shows. Clones share the
+/// selection, so the emulator's host can switch banks the same way the bank
+/// register does.
+#[derive(Clone, Debug)]
+pub struct BankSwitch {
+    selected: Rc<Cell<usize>>,
+    count: usize,
+    /// The bank that shows after a cold reset
+    power_on: usize,
+}
+
+impl BankSwitch {
+    fn new(count: usize, selected: usize) -> BankSwitch {
+        BankSwitch {
+            selected: Rc::new(Cell::new(selected)),
+            count: count,
+            power_on: selected,
+        }
+    }
+
+    fn power_on(&self) {
+        self.selected.set(self.power_on);
+    }
+
+    /// Shows the given bank. Bank numbers wrap around the number of banks,
+    /// the same as when a bank register ignores its unused high bits.
+    pub fn select(&self, bank: usize) {
+        self.selected.set(bank % self.count);
+    }
+
+    /// Returns the bank that is showing
+    pub fn selected(&self) -> usize {
+        self.selected.get()
+    }
+
+    /// Returns the number of banks
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// A window that forwards accesses to whichever of its banks is selected.
+/// Every bank is stepped and reset, selected or not, since hardware keeps
+/// running while it's switched out. A cold reset selects bank 0 again, and
+/// a warm reset leaves the selection alone.
+pub struct BankedDevice {
+    banks: Vec<Rc<RefCell<MemoryMappedDevice>>>,
+    switch: BankSwitch,
+}
+
+impl BankedDevice {
+    pub fn new(banks: Vec<Rc<RefCell<MemoryMappedDevice>>>) -> BankedDevice {
+        assert!(!banks.is_empty(), "a banked device needs at least one bank");
+        let count = banks.len();
+        BankedDevice {
+            banks: banks,
+            switch: BankSwitch::new(count, 0),
+        }
+    }
+
+    /// Returns the switch that selects the bank
+    pub fn switch(&self) -> BankSwitch {
+        self.switch.clone()
+    }
+
+    fn selected(&self) -> &Rc<RefCell<MemoryMappedDevice>> {
+        &self.banks[self.switch.selected()]
+    }
+}
+
+impl MemoryMappedDevice for BankedDevice {
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.selected().borrow().read_byte(addr)
+    }
+
+    fn read_byte_mut(&mut self, addr: u16) -> u8 {
+        self.selected().borrow_mut().read_byte_mut(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        self.selected().borrow_mut().write_byte(addr, val)
+    }
+
+    fn program_byte(&mut self, addr: u16, val: u8) {
+        self.selected().borrow_mut().program_byte(addr, val)
+    }
+
+    fn requires_step(&self) -> bool {
+        self.banks.iter().any(|bank| bank.borrow().requires_step())
+    }
+
+    fn step(&mut self, memory: &mut MemoryMap) -> Option<InterruptType> {
+        let mut result = None;
+        for bank in &self.banks {
+            if let Some(interrupt) = bank.borrow_mut().step(memory) {
+                if result != Some(InterruptType::NonMaskable) {
+                    result = Some(interrupt);
+                }
+            }
+        }
+        result
+    }
+
+    fn reset(&mut self, kind: ResetKind) {
+        for bank in &self.banks {
+            bank.borrow_mut().reset(kind);
+        }
+        if kind == ResetKind::Cold {
+            self.switch.power_on();
+        }
+    }
+}
+
+/// ROM that shadows RAM until it's disabled, such as a boot ROM that copies
+/// itself into the RAM underneath. Writes always go to the RAM. Bank 1 of
+/// its switch shows the ROM, which is the default, and bank 0 shows the RAM.
+/// Any reset shows the ROM again, so the boot code runs.
+pub struct OverlayDevice {
+    rom: ROMDevice,
+    ram: RAMDevice,
+    switch: BankSwitch,
+}
+
+impl OverlayDevice {
+    pub fn new(rom: ROMDevice, ram: RAMDevice) -> OverlayDevice {
+        OverlayDevice {
+            rom: rom,
+            ram: ram,
+            switch: BankSwitch::new(2, 1),
+        }
+    }
+
+    /// Returns the switch that enables and disables the ROM
+    pub fn switch(&self) -> BankSwitch {
+        self.switch.clone()
+    }
+}
+
+impl MemoryMappedDevice for OverlayDevice {
+    fn read_byte(&self, addr: u16) -> u8 {
+        match self.switch.selected() {
+            0 => self.ram.read_byte(addr),
+            _ => self.rom.read_byte(addr),
+        }
+    }
+
+    fn read_byte_mut(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        self.ram.write_byte(addr, val);
+    }
+
+    /// Programs whichever of the ROM and RAM is showing
+    fn program_byte(&mut self, addr: u16, val: u8) {
+        match self.switch.selected() {
+            0 => self.ram.program_byte(addr, val),
+            _ => self.rom.program_byte(addr, val),
+        }
+    }
+
+    fn requires_step(&self) -> bool {
+        false
+    }
+
+    fn step(&mut self, _memory: &mut MemoryMap) -> Option<InterruptType> {
+        None
+    }
+
+    fn reset(&mut self, kind: ResetKind) {
+        self.ram.reset(kind);
+        self.switch.power_on();
+    }
+}
+
+/// A one-byte register that selects a bank when written, and reads back the
+/// selected bank
+pub struct BankRegister {
+    switch: BankSwitch,
+}
+
+impl BankRegister {
+    pub fn new(switch: BankSwitch) -> BankRegister {
+        BankRegister { switch: switch }
+    }
+}
+
+impl MemoryMappedDevice for BankRegister {
+    fn read_byte(&self, _addr: u16) -> u8 {
+        self.switch.selected() as u8
+    }
+
+    fn read_byte_mut(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    fn write_byte(&mut self, _addr: u16, val: u8) {
+        self.switch.select(val as usize);
+    }
+
+    fn requires_step(&self) -> bool {
+        false
+    }
+
+    fn step(&mut self, _memory: &mut MemoryMap) -> Option<InterruptType> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emulator::{MemoryMap, MemoryMapProblem};
+
+    #[test]
+    fn test_banked_ram() {
+        let problems = MemoryMap::builder()
+            .ram(0x0000, 0xBFFF)
+            .banked_ram("empty", 0xC000, 0xFFFE, 0)
+            .bank_register(0xFFFF, "missing")
+            .try_build()
+            .err()
+            .unwrap()
+            .problems;
+        assert_eq!(
+            vec![
+                MemoryMapProblem::NoBanks { name: "empty".into() },
+                MemoryMapProblem::UnknownBank { name: "missing".into() },
+            ],
+            problems
+        );
+
+        // 512K of RAM in 32 banks of 16K, like the planned Hasseldorf revision
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0x7FFF)
+            .banked_ram("ext", 0x8000, 0xBFFF, 32)
+            .bank_register(0xC000, "ext")
+            .bank_register(0xC001, "boot")
+            .ram(0xC002, 0xDFFF)
+            .overlay("boot", 0xE000, 0xFFFF, vec![0xEA; 0x2000])
+            .build();
+
+        for bank in 0..32 {
+            memory.write().byte(0xC000, bank);
+            memory.write().byte(0x8123, bank + 100);
+        }
+        memory.write().byte(0xC000, 33);
+        assert_eq!(1, memory.read().byte(0xC000));
+        assert_eq!(101, memory.read().byte(0x8123));
+        memory.bank_switch("ext").unwrap().select(31);
+        assert_eq!(131, memory.read().byte(0x8123));
+
+        memory.write().byte(0xE000, 0x42);
+        assert_eq!(0xEA, memory.read().byte(0xE000));
+        memory.write().byte(0xC001, 0);
+        assert_eq!(0x42, memory.read().byte(0xE000));
+        assert!(memory.bank_switch("missing").is_none());
+    }
+}
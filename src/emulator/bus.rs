@@ -0,0 +1,136 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use emulator::cpu::{InterruptType, ResetKind};
+use emulator::memory::ReadMemory;
+
+/// Everything the CPU is connected to. `MemoryMap` is the usual implementation,
+/// but anything that can read and write bytes will do, such as a flat array, a
+/// bus that counts cycles, or a bridge to another simulator.
+///
+/// Only `read`, `write` and `peek` are required. The other methods let a bus
+/// take part in timing and interrupts, and do nothing by default.
+pub trait Bus {
+    /// Reads a byte, which may change the state of the device at that address
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// Writes a byte
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// Reads a byte without affecting anything, for debuggers and observers
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Reads a little-endian 16-bit word
+    fn read_word(&mut self, addr: u16) -> u16 {
+        let lsb = self.read(addr);
+        let msb = self.read(addr.wrapping_add(1));
+        (msb as u16) << 8 | (lsb as u16)
+    }
+
+    /// Reads a little-endian 16-bit word from the zero page, wrapping within it
+    fn read_word_zero_page(&mut self, addr: u8) -> u16 {
+        let lsb = self.read(addr as u16);
+        let msb = self.read(addr.wrapping_add(1) as u16);
+        (msb as u16) << 8 | (lsb as u16)
+    }
+
+    /// Reads the op-code byte of an instruction
+    fn fetch_op_code(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Reads an operand byte of an instruction
+    fn fetch_operand(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Called with the CPU's cycle count before each instruction and interrupt
+    fn set_cycle(&mut self, _cycle: usize) {}
+
+    /// Returns and resets the extra cycles that accesses have taken since the
+    /// last call, which the CPU adds to the instruction that made them
+    fn take_wait_cycles(&mut self) -> usize {
+        0
+    }
+
+    /// Called after each instruction to update any devices, which can request an interrupt
+    fn step(&mut self) -> Option<InterruptType> {
+        None
+    }
+
+    /// Called when the CPU is reset, before it reads the reset vector
+    fn reset(&mut self, _kind: ResetKind) {}
+}
+
+/// Exposes `Bus::peek` as `ReadMemory`, for observers and debuggers
+pub struct BusPeek<'a, B: Bus + 'a> {
+    bus: &'a B,
+}
+
+impl<'a, B: Bus + 'a> BusPeek<'a, B> {
+    pub fn new(bus: &'a B) -> BusPeek<'a, B> {
+        BusPeek { bus: bus }
+    }
+}
+
+impl<'a, B: Bus + 'a> ReadMemory for BusPeek<'a, B> {
+    fn byte(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Cpu;
+    use test_support::{assemble, run_to_label};
+
+    /// The smallest possible bus: 64K of RAM in an array
+    struct FlatBus {
+        memory: Vec<u8>,
+        reads: usize,
+    }
+
+    impl Bus for FlatBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.reads += 1;
+            self.memory[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.memory[addr as usize] = val;
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+    }
+
+    #[test]
+    fn test_custom_bus() {
+        let program = assemble(
+            "start: LDX #3
+             loop:  TXA
+                    STA $10,X
+                    DEX
+                    BNE loop
+                    JSR done
+             done:  JMP done
+             .reset start",
+        );
+        let mut cpu = Cpu::new(FlatBus {
+            memory: program.image().to_rom_filled(0x0000, 0xFFFF, 0).unwrap(),
+            reads: 0,
+        });
+        run_to_label(&mut cpu, &program, "done");
+        assert_eq!(vec![1, 2, 3], cpu.memory().memory[0x11..0x14].to_vec());
+        assert_eq!(1, cpu.backtrace().len());
+        assert!(cpu.memory().reads > 0);
+    }
+}
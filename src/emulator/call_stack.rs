@@ -0,0 +1,212 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use emulator::opcode::OpClass;
+
+/// How a call stack frame was entered
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FrameKind {
+    /// A JSR instruction
+    Subroutine,
+    /// A BRK instruction
+    Break,
+    /// A maskable interrupt request
+    Interrupt,
+    /// A non-maskable interrupt
+    NonMaskableInterrupt,
+}
+
+/// A single entry of the shadow call stack
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CallFrame {
+    pub kind: FrameKind,
+    /// Address of the JSR or BRK instruction, or the address that was
+    /// about to execute when a hardware interrupt was taken
+    pub call_site: u16,
+    /// Address of the subroutine or interrupt handler
+    pub target: u16,
+    /// Address that the matching RTS or RTI should return to
+    pub return_address: u16,
+    /// Stack pointer before the call pushed anything
+    pub sp: u8,
+    /// Cycle count when the call was made
+    pub cycle: usize,
+}
+
+/// A return that didn't match the innermost frame on the shadow call stack.
+/// This is normal for tricks like pushing an address and using RTS to jump
+/// to it, but otherwise usually points to stack corruption.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ReturnMismatch {
+    /// Address of the RTS or RTI instruction
+    pub address: u16,
+    /// Address that was returned to
+    pub returned_to: u16,
+    /// The innermost frame at the time of the return, if there was one
+    pub expected: Option<CallFrame>,
+    /// Number of frames discarded to get back in sync with the stack pointer
+    pub discarded: usize,
+}
+
+/// Tracks calls and returns alongside the real stack, since the 6502 stack mixes
+/// return addresses with pushed data and can't be walked reliably
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    mismatch: Option<ReturnMismatch>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        Default::default()
+    }
+
+    /// Returns the frames, from outermost to innermost
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    /// Returns the mismatch caused by the most recent instruction, if any
+    pub fn mismatch(&self) -> Option<ReturnMismatch> {
+        self.mismatch
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.mismatch = None;
+    }
+
+    pub fn push(&mut self, frame: CallFrame) {
+        self.frames.push(frame);
+    }
+
+    /// Updates the stack after an instruction executed. `pc` and `sp` are the
+    /// values from before the instruction and `new_pc` and `new_sp` from after it.
+    pub fn track(&mut self, class: OpClass, pc: u16, sp: u8, new_pc: u16, new_sp: u8, cycle: usize) {
+        self.mismatch = None;
+        match class {
+            OpClass::Jsr => self.push(CallFrame {
+                kind: FrameKind::Subroutine,
+                call_site: pc,
+                target: new_pc,
+                return_address: pc.wrapping_add(3),
+                sp: sp,
+                cycle: cycle,
+            }),
+            OpClass::Brk => self.push(CallFrame {
+                kind: FrameKind::Break,
+                call_site: pc,
+                target: new_pc,
+                return_address: pc.wrapping_add(2),
+                sp: sp,
+                cycle: cycle,
+            }),
+            OpClass::Rts | OpClass::Rti => self.pop(class == OpClass::Rti, pc, new_pc, new_sp),
+            _ => {}
+        }
+    }
+
+    fn pop(&mut self, from_interrupt: bool, pc: u16, new_pc: u16, new_sp: u8) {
+        let expected = self.frames.last().cloned();
+
+        // Every frame at or below the new stack pointer has been returned from,
+        // whether or not it was done cleanly
+        let mut discarded = 0;
+        while self.frames.last().map(|frame| frame.sp <= new_sp) == Some(true) {
+            self.frames.pop();
+            discarded += 1;
+        }
+
+        let matched = match expected {
+            Some(frame) if discarded == 1 => {
+                let kind_matches = (frame.kind == FrameKind::Subroutine) != from_interrupt;
+                kind_matches && frame.sp == new_sp && frame.return_address == new_pc
+            }
+            _ => false,
+        };
+        if !matched {
+            self.mismatch = Some(ReturnMismatch {
+                address: pc,
+                returned_to: new_pc,
+                expected: expected,
+                discarded: discarded,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Cpu;
+    use test_support::load_program;
+
+    fn new_cpu() -> Cpu {
+        let (cpu, _) = load_program(
+            "start: JSR outer
+                    NOP
+             halt:  JMP halt
+             outer: JSR inner
+                    RTS
+             inner: NOP
+                    RTS
+             trick: LDA #$02
+                    PHA
+                    LDA #$03
+                    PHA
+                    RTS
+             .reset start",
+        );
+        cpu
+    }
+
+    #[test]
+    fn test_backtrace_and_stepping() {
+        let mut cpu = new_cpu();
+        cpu.step();
+        cpu.step();
+        let backtrace = cpu.backtrace();
+        assert_eq!(2, backtrace.len());
+        assert_eq!((0x0207, 0x020B, 0x020A), (backtrace[0].call_site, backtrace[0].target, backtrace[0].return_address));
+        assert_eq!((0x0200, 0xFF), (backtrace[1].call_site, backtrace[1].sp));
+        assert_eq!(FrameKind::Subroutine, backtrace[1].kind);
+
+        assert!(cpu.step_out(100));
+        assert_eq!(0x020A, cpu.registers().pc);
+        assert_eq!(1, cpu.backtrace().len());
+        assert_eq!(None, cpu.return_mismatch());
+
+        cpu.reset();
+        assert!(cpu.step_over(100));
+        assert_eq!(0x0203, cpu.registers().pc);
+        assert!(cpu.backtrace().is_empty());
+        assert!(!cpu.step_out(100));
+    }
+
+    #[test]
+    fn test_return_mismatch() {
+        let mut cpu = new_cpu();
+        cpu.registers_mut().pc = 0x020D;
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(0x0204, cpu.registers().pc);
+        assert_eq!(
+            Some(ReturnMismatch {
+                address: 0x0213,
+                returned_to: 0x0204,
+                expected: None,
+                discarded: 0,
+            }),
+            cpu.return_mismatch()
+        );
+        cpu.step();
+        assert_eq!(None, cpu.return_mismatch());
+    }
+}
@@ -7,7 +7,15 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use emulator::memory::MemoryMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator::bus::{Bus, BusPeek};
+use emulator::call_stack::{CallFrame, CallStack, FrameKind, ReturnMismatch};
+use emulator::memory::{MemoryMap, ReadMemory};
+use emulator::opcode::OpCode;
+use emulator::pins::Pin;
+use emulator::provenance::{ProvenanceMap, WriteRecord};
 use emulator::registers::Registers;
 use emulator::instruction::Executor;
 use emulator::instruction::InstructionResult;
@@ -18,6 +26,9 @@ const IRQ_VECTOR: u16 = 0xFFFE;
 
 const STACK_ADDR: u16 = 0x0100;
 
+/// Cycles taken by the reset sequence
+const RESET_CYCLES: usize = 7;
+
 /// Types of interrupts possible on the 6502
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum InterruptType {
@@ -28,37 +39,113 @@ pub enum InterruptType {
     NonMaskable,
 }
 
-/// The MOS 6502 CPU emulator
-pub struct Cpu {
+/// Types of reset
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ResetKind {
+    /// Power on, which puts RAM back the way it was built and loses the state of every device
+    Cold,
+    /// The RES line was pulled low while the system was running
+    Warm,
+}
+
+/// Where a step over or step out finishes, for debuggers that run a few instructions
+/// at a time between checking for breakpoints and commands. Pass it to
+/// `Cpu::step_toward` until that returns true.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StepTarget {
+    /// Finished once the shadow call stack has this many frames or fewer
+    depth: usize,
+}
+
+/// Receives every instruction that the CPU executes, such as for profiling.
+/// Observers are called after the instruction and any interrupt it triggered.
+pub trait InstructionObserver {
+    /// Called before the instruction at `registers.pc` executes, for observers that need
+    /// the state it will run with. Does nothing by default.
+    fn executing(&mut self, _registers: &Registers, _memory: &ReadMemory) {}
+
+    /// Called with the address and op-code of the instruction, the number of cycles it
+    /// took, and the shadow call stack afterwards, from outermost to innermost frame
+    fn executed(&mut self, pc: u16, op_code: u8, cycles: usize, frames: &[CallFrame]);
+}
+
+/// The MOS 6502 CPU emulator. It runs on any `Bus`, which is a `MemoryMap` by default.
+pub struct Cpu<B: Bus = MemoryMap> {
     registers: Registers,
-    memory: MemoryMap,
+    memory: B,
     cycle: usize,
     executor: Executor,
+    call_stack: CallStack,
+    observers: Vec<Rc<RefCell<InstructionObserver>>>,
+    provenance: Option<ProvenanceMap>,
+    rdy: Pin,
+    so: Pin,
+    res: Pin,
+    /// Set while RES is held low, so that the reset runs when it's released
+    held_in_reset: bool,
+    /// Interrupt requested by a device while the CPU was held by RDY or RES
+    pending_interrupt: Option<InterruptType>,
 }
 
-impl Cpu {
-    /// Creates a new CPU with the given memory map
-    pub fn new(memory: MemoryMap) -> Cpu {
+impl<B: Bus> Cpu<B> {
+    /// Creates a new CPU with the given memory map. The CPU starts at the reset
+    /// vector with no cycles counted, and devices aren't reset, so memory can be
+    /// loaded before it's created.
+    pub fn new(memory: B) -> Cpu<B> {
         let mut cpu = Cpu {
             registers: Registers::new(),
             memory: memory,
             cycle: 0,
             executor: Executor::new(),
+            call_stack: CallStack::new(),
+            observers: Vec::new(),
+            provenance: None,
+            rdy: Pin::new(),
+            so: Pin::new(),
+            res: Pin::new(),
+            held_in_reset: false,
+            pending_interrupt: None,
         };
 
-        cpu.reset();
+        cpu.jump_to_reset_vector();
         cpu
     }
 
-    /// Resets the CPU. This will set the program counter to the
-    /// value found at the reset vector at address 0xFFFC. This is called
-    /// on construction, so you should only need to call it again after
-    /// construction if you want to reset, or if you modify memory that
-    /// the reset vector points too after constructing.
+    /// Performs a warm reset, as when the RES line is pulled low. Devices are reset,
+    /// but RAM keeps its contents. Like the real reset sequence, this takes 7 cycles,
+    /// moves the stack pointer down by 3 without writing anything, sets the interrupt
+    /// inhibit flag, and jumps through the reset vector at 0xFFFC.
     pub fn reset(&mut self) {
-        let entry_point = self.memory.read().word(RESET_VECTOR);
+        self.memory.reset(ResetKind::Warm);
+        self.reset_sequence();
+    }
+
+    /// Performs a cold reset, as when the system is powered on. RAM is filled again the
+    /// way it was built, every device is reset, and the registers are cleared before
+    /// the reset sequence runs, which leaves the stack pointer at 0xFD.
+    pub fn power_on(&mut self) {
+        self.memory.reset(ResetKind::Cold);
+        self.registers = Registers::new();
+        self.registers.sp = 0;
+        self.reset_sequence();
+    }
+
+    fn reset_sequence(&mut self) {
+        self.memory.set_cycle(self.cycle);
+        self.registers.sp = self.registers.sp.wrapping_sub(3);
+        self.cycle += RESET_CYCLES;
+        self.jump_to_reset_vector();
+    }
+
+    /// Loads the program counter from the reset vector. This is all that construction
+    /// does, so that the CPU starts at cycle 0 with the whole stack free.
+    fn jump_to_reset_vector(&mut self) {
+        let entry_point = self.memory.read_word(RESET_VECTOR);
+        // Wait states are already included in the reset sequence's cycles
+        self.memory.take_wait_cycles();
         self.registers.pc = entry_point;
         self.registers.status.set_interrupt_inhibit(true);
+        self.call_stack.clear();
     }
 
     /// Returns all of the registers
@@ -66,22 +153,80 @@ impl Cpu {
         &self.registers
     }
 
+    /// Returns all of the registers mutably
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Returns the number of cycles executed since construction
+    pub fn cycles(&self) -> usize {
+        self.cycle
+    }
+
+    /// Returns the shadow call stack, innermost frame first. Frames are recorded
+    /// for JSR, BRK and interrupts, and removed by the matching RTS or RTI.
+    pub fn backtrace(&self) -> Vec<CallFrame> {
+        self.call_stack.frames().iter().rev().cloned().collect()
+    }
+
+    /// Returns the mismatch if the last instruction was an RTS or RTI that
+    /// didn't match the innermost frame of the shadow call stack
+    pub fn return_mismatch(&self) -> Option<ReturnMismatch> {
+        self.call_stack.mismatch()
+    }
+
+    /// Starts recording the instruction that last wrote each address. This
+    /// costs a little time on every write, so it's off by default.
+    pub fn enable_provenance(&mut self) {
+        if self.provenance.is_none() {
+            self.provenance = Some(ProvenanceMap::new());
+        }
+    }
+
+    /// Returns the write provenance, if it was enabled
+    pub fn provenance(&self) -> Option<&ProvenanceMap> {
+        self.provenance.as_ref()
+    }
+
+    /// Returns the RDY pin. While it's low, the CPU halts before its next
+    /// instruction, and each step only counts a cycle and steps the devices.
+    /// DMA devices can hold a clone of it to take the bus between instructions.
+    /// It isn't checked on the read cycles inside an instruction, so use wait
+    /// states to model slow memory.
+    pub fn rdy(&self) -> Pin {
+        self.rdy.clone()
+    }
+
+    /// Returns the SO (set overflow) pin. The overflow flag is set before the
+    /// next instruction whenever the pin goes from high to low.
+    pub fn so(&self) -> Pin {
+        self.so.clone()
+    }
+
+    /// Returns the RES pin. While it's low, the CPU is held in reset the same
+    /// way as with RDY, and when it's released the next step runs the same
+    /// warm reset as `reset`.
+    pub fn res(&self) -> Pin {
+        self.res.clone()
+    }
+
     /// Returns the memory map
-    pub fn memory(&self) -> &MemoryMap {
+    pub fn memory(&self) -> &B {
         &self.memory
     }
 
     /// Returns the memory map mutably
-    pub fn memory_mut(&mut self) -> &mut MemoryMap {
+    pub fn memory_mut(&mut self) -> &mut B {
         &mut self.memory
     }
 
     /// Requests a maskable interrupt and returns true if
     /// the interrupt wasn't masked
     pub fn request_interrupt(&mut self) -> bool {
+        self.memory.set_cycle(self.cycle);
         if !self.registers.status.interrupt_inhibit() {
-            let interrupt_addr = self.memory.read().word(IRQ_VECTOR);
-            self.interrupt(interrupt_addr);
+            let interrupt_addr = self.memory.read_word(IRQ_VECTOR);
+            self.interrupt(interrupt_addr, FrameKind::Interrupt, InterruptType::Maskable);
             true
         } else {
             false
@@ -90,26 +235,77 @@ impl Cpu {
 
     /// Requests a non-maskable interrupt
     pub fn request_non_maskable_interrupt(&mut self) {
-        let nmi_addr = self.memory.read().word(NMI_VECTOR);
-        self.interrupt(nmi_addr);
+        self.memory.set_cycle(self.cycle);
+        let nmi_addr = self.memory.read_word(NMI_VECTOR);
+        self.interrupt(nmi_addr, FrameKind::NonMaskableInterrupt, InterruptType::NonMaskable);
     }
 
     /// Executes a single instruction on the CPU.
     /// Also steps any peripheral devices attached to
     /// the memory map.
     pub fn step(&mut self) -> usize {
+        if self.res.is_low() {
+            self.held_in_reset = true;
+            return self.hold();
+        }
+        if self.held_in_reset {
+            self.held_in_reset = false;
+            self.reset();
+            return RESET_CYCLES;
+        }
+        if self.so.take_falling_edge() {
+            self.registers.status.set_overflow(true);
+        }
+        if self.rdy.is_low() {
+            return self.hold();
+        }
+
+        let (pc, sp, cycle) = (self.registers.pc, self.registers.sp, self.cycle);
+        self.memory.set_cycle(cycle);
+        let op_code = self.memory.peek(pc);
+        let class = OpCode::from_value(op_code).map(|code| code.class);
+        if !self.observers.is_empty() {
+            let memory = BusPeek::new(&self.memory);
+            for observer in &self.observers {
+                observer.borrow_mut().executing(&self.registers, &memory);
+            }
+        }
+
         let mut result = InstructionResult::new();
         result = self.executor
             .execute_instruction(&self.registers, &mut self.memory, result);
 
         for write in &result.writes {
-            self.memory.write().byte(write.address, write.value);
+            if let Some(ref mut provenance) = self.provenance {
+                provenance.record(
+                    write.address,
+                    WriteRecord {
+                        pc: pc,
+                        cycle: cycle,
+                        previous: self.memory.peek(write.address),
+                        value: write.value,
+                        interrupt: None,
+                    },
+                );
+            }
+            self.memory.write(write.address, write.value);
         }
+        result.cycles += self.memory.take_wait_cycles();
 
         self.registers = result.reg;
         self.cycle += result.cycles;
+        if let Some(class) = class {
+            self.call_stack
+                .track(class, pc, sp, self.registers.pc, self.registers.sp, cycle);
+        }
 
-        match self.memory.step() {
+        let interrupt = match (self.memory.step(), self.pending_interrupt.take()) {
+            (Some(InterruptType::NonMaskable), _) | (_, Some(InterruptType::NonMaskable)) => {
+                Some(InterruptType::NonMaskable)
+            }
+            (interrupt, pending) => interrupt.or(pending),
+        };
+        match interrupt {
             Some(InterruptType::Maskable) => {
                 self.request_interrupt();
             }
@@ -119,25 +315,278 @@ impl Cpu {
             _ => {}
         }
 
+        if !self.observers.is_empty() {
+            let frames = self.call_stack.frames();
+            for observer in &self.observers {
+                observer
+                    .borrow_mut()
+                    .executed(pc, op_code, result.cycles, frames);
+            }
+        }
+
         result.cycles
     }
 
+    /// Spends a cycle without executing anything while a pin holds the CPU. Devices
+    /// keep running, and any interrupt they request waits for the CPU to continue.
+    fn hold(&mut self) -> usize {
+        self.memory.set_cycle(self.cycle);
+        self.cycle += 1;
+        if let Some(interrupt) = self.memory.step() {
+            if self.pending_interrupt != Some(InterruptType::NonMaskable) {
+                self.pending_interrupt = Some(interrupt);
+            }
+        }
+        1
+    }
+
+    /// Registers an observer to be told about every executed instruction
+    pub fn add_instruction_observer(&mut self, observer: Rc<RefCell<InstructionObserver>>) {
+        self.observers.push(observer);
+    }
+
+    /// Removes all of the instruction observers
+    pub fn clear_instruction_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    /// Executes a single instruction, but if it's a JSR (or an interrupt is taken),
+    /// keeps going until that call returns. Returns false if it didn't return within
+    /// `max_cycles`.
+    pub fn step_over(&mut self, max_cycles: usize) -> bool {
+        let target = self.step_over_target();
+        self.run_to(target, max_cycles)
+    }
+
+    /// Executes until the innermost call on the shadow call stack returns. Returns
+    /// false if there's no call to return from, or if it didn't return within `max_cycles`.
+    pub fn step_out(&mut self, max_cycles: usize) -> bool {
+        match self.step_out_target() {
+            Some(target) => self.run_to(target, max_cycles),
+            None => false,
+        }
+    }
+
+    /// Returns the target for stepping over the next instruction, and over the call
+    /// if it's a JSR or an interrupt is taken
+    pub fn step_over_target(&self) -> StepTarget {
+        StepTarget {
+            depth: self.call_stack.frames().len(),
+        }
+    }
+
+    /// Returns the target for stepping out of the innermost call on the shadow call
+    /// stack, or None if there's no call to return from
+    pub fn step_out_target(&self) -> Option<StepTarget> {
+        self.call_stack
+            .frames()
+            .len()
+            .checked_sub(1)
+            .map(|depth| StepTarget { depth: depth })
+    }
+
+    /// Executes a single instruction and returns true if that reached the target
+    pub fn step_toward(&mut self, target: StepTarget) -> bool {
+        self.step();
+        self.call_stack.frames().len() <= target.depth
+    }
+
+    fn run_to(&mut self, target: StepTarget, max_cycles: usize) -> bool {
+        let start_cycle = self.cycle;
+        while !self.step_toward(target) {
+            if self.cycle - start_cycle >= max_cycles {
+                return false;
+            }
+        }
+        true
+    }
+
     #[inline]
-    fn push(&mut self, registers: &mut Registers, val: u8) {
-        self.memory
-            .write()
-            .byte(STACK_ADDR + registers.sp as u16, val);
+    fn push(&mut self, registers: &mut Registers, val: u8, interrupt: InterruptType) {
+        let address = STACK_ADDR + registers.sp as u16;
+        if let Some(ref mut provenance) = self.provenance {
+            provenance.record(
+                address,
+                WriteRecord {
+                    pc: self.registers.pc,
+                    cycle: self.cycle,
+                    previous: self.memory.peek(address),
+                    value: val,
+                    interrupt: Some(interrupt),
+                },
+            );
+        }
+        self.memory.write(address, val);
         registers.sp = registers.sp.wrapping_sub(1);
     }
 
-    fn interrupt(&mut self, handler_address: u16) {
+    fn interrupt(&mut self, handler_address: u16, kind: FrameKind, interrupt: InterruptType) {
+        self.call_stack.push(CallFrame {
+            kind: kind,
+            call_site: self.registers.pc,
+            target: handler_address,
+            return_address: self.registers.pc,
+            sp: self.registers.sp,
+            cycle: self.cycle,
+        });
+
         let mut registers = self.registers;
         let cur_pc = registers.pc;
         let cur_status = registers.status.value();
-        self.push(&mut registers, (cur_pc >> 8) as u8);
-        self.push(&mut registers, (cur_pc & 0xFF) as u8);
-        self.push(&mut registers, cur_status);
+        self.push(&mut registers, (cur_pc >> 8) as u8, interrupt);
+        self.push(&mut registers, (cur_pc & 0xFF) as u8, interrupt);
+        self.push(&mut registers, cur_status, interrupt);
         registers.pc = handler_address;
         self.registers = registers;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::{AccessKind, BankedDevice, BusObserver, MemoryMappedDevice, RamFill};
+
+    /// Records the resets it receives
+    struct ResetRecorder {
+        resets: Vec<ResetKind>,
+    }
+
+    impl MemoryMappedDevice for ResetRecorder {
+        fn read_byte(&self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn read_byte_mut(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn write_byte(&mut self, _addr: u16, _val: u8) {}
+
+        fn requires_step(&self) -> bool {
+            false
+        }
+
+        fn step(&mut self, _memory: &mut MemoryMap) -> Option<InterruptType> {
+            None
+        }
+
+        fn reset(&mut self, kind: ResetKind) {
+            self.resets.push(kind);
+        }
+    }
+
+    #[test]
+    fn test_wait_states() {
+        // NOP; LDA $E000; STA $E000; STA $10
+        let mut rom = vec![0xEA; 0x2000];
+        rom[..9].copy_from_slice(&[0xEA, 0xAD, 0x00, 0xE0, 0x8D, 0x00, 0xE0, 0x85, 0x10]);
+        rom[0x1FFC] = 0x00;
+        rom[0x1FFD] = 0xE0;
+        let memory = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .rom(0xE000, 0xFFFF, rom)
+            .wait_states(2)
+            .write_wait_states(5)
+            .build();
+        let mut cpu = Cpu::new(memory);
+        assert_eq!(0, cpu.cycles());
+
+        // Each access to ROM adds its wait states to the instruction's own cycles:
+        // the op-code fetch, then the operand fetches, then the data read or write
+        assert_eq!(2 + 2, cpu.step());
+        assert_eq!(4 + 3 * 2 + 2, cpu.step());
+        assert_eq!(4 + 3 * 2 + 5, cpu.step());
+        assert_eq!(3 + 2 * 2, cpu.step());
+        assert_eq!(4 + 12 + 15 + 7, cpu.cycles());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut rom = vec![0xEA; 0x1000];
+        rom[0xFFC] = 0x00;
+        rom[0xFFD] = 0xF0;
+        let switched_out = Rc::new(RefCell::new(ResetRecorder { resets: Vec::new() }));
+        let memory = MemoryMap::builder()
+            .ram_fill(RamFill::Value(0x5A))
+            .ram(0x0000, 0xDFFF)
+            .device("recorder", 0xE000, 0xE0FF, Rc::new(RefCell::new(ResetRecorder { resets: Vec::new() })))
+            .banked(
+                "banks",
+                0xE100,
+                0xEFFF,
+                vec![
+                    Rc::new(RefCell::new(ResetRecorder { resets: Vec::new() })),
+                    switched_out.clone(),
+                ],
+            )
+            .rom(0xF000, 0xFFFF, rom)
+            .build();
+        let mut cpu = Cpu::new(memory);
+        assert_eq!((0xF000, 0xFF, 0), (cpu.registers().pc, cpu.registers().sp, cpu.cycles()));
+        let recorder = cpu.memory().device::<ResetRecorder>("recorder").unwrap();
+        assert!(recorder.borrow().resets.is_empty());
+
+        cpu.memory_mut().write().byte(0x0010, 0x01);
+        cpu.memory().bank_switch("banks").unwrap().select(0);
+        cpu.registers_mut().a = 0x42;
+        cpu.step();
+        cpu.reset();
+        assert_eq!((0xF000, 0xFC, 9), (cpu.registers().pc, cpu.registers().sp, cpu.cycles()));
+        assert_eq!((0x42, 0x01), (cpu.registers().a, cpu.memory().debug_read().byte(0x0010)));
+        assert_eq!(vec![ResetKind::Warm], recorder.borrow().resets);
+        assert_eq!(vec![ResetKind::Warm], switched_out.borrow().resets);
+
+        cpu.memory().bank_switch("banks").unwrap().select(1);
+        cpu.power_on();
+        assert_eq!((0xF000, 0xFD, 16), (cpu.registers().pc, cpu.registers().sp, cpu.cycles()));
+        assert_eq!((0x00, 0x5A), (cpu.registers().a, cpu.memory().debug_read().byte(0x0010)));
+        assert!(cpu.registers().status.interrupt_inhibit());
+        assert_eq!(vec![ResetKind::Warm, ResetKind::Cold], recorder.borrow().resets);
+        assert_eq!(vec![ResetKind::Warm, ResetKind::Cold], switched_out.borrow().resets);
+        assert_eq!(0, cpu.memory().bank_switch("banks").unwrap().selected());
+        assert!(cpu.memory().device::<BankedDevice>("banks").is_some());
+    }
+
+    struct BusRecorder {
+        accesses: Vec<(u16, u8, usize, AccessKind)>,
+    }
+
+    impl BusObserver for BusRecorder {
+        fn access(&mut self, address: u16, value: u8, cycle: usize, kind: AccessKind) {
+            self.accesses.push((address, value, cycle, kind));
+        }
+    }
+
+    #[test]
+    fn test_bus_observer() {
+        let mut memory = MemoryMap::builder().ram(0x0000, 0xFFFF).build();
+        // LDA $10; INC $10
+        for (i, val) in [0xA5, 0x10, 0xE6, 0x10].iter().enumerate() {
+            memory.write().byte(0x0200 + i as u16, *val);
+        }
+        memory.write().byte(0x0010, 0x42);
+        memory.write().byte(0xFFFD, 0x02);
+
+        let recorder = Rc::new(RefCell::new(BusRecorder { accesses: Vec::new() }));
+        memory.add_bus_observer(recorder.clone());
+        let mut cpu = Cpu::new(memory);
+        cpu.step();
+        cpu.step();
+        cpu.memory().debug_read().byte(0x0010);
+
+        assert_eq!(
+            vec![
+                (0xFFFC, 0x00, 0, AccessKind::Read),
+                (0xFFFD, 0x02, 0, AccessKind::Read),
+                (0x0200, 0xA5, 0, AccessKind::OpCodeFetch),
+                (0x0201, 0x10, 0, AccessKind::OperandFetch),
+                (0x0010, 0x42, 0, AccessKind::Read),
+                (0x0202, 0xE6, 3, AccessKind::OpCodeFetch),
+                (0x0203, 0x10, 3, AccessKind::OperandFetch),
+                (0x0010, 0x42, 3, AccessKind::Read),
+                (0x0010, 0x43, 3, AccessKind::Write),
+            ],
+            recorder.borrow().accesses
+        );
+    }
+}
@@ -0,0 +1,622 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! Loaders for the program image formats commonly produced by 6502 toolchains:
+//! Intel HEX, Motorola S-records, Commodore-style PRG files, and raw binaries
+//! placed at a fixed offset.
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use emulator::memory::{MemoryMap, RAMDevice, ROMDevice, WriteMode};
+
+const ADDRESS_SPACE_SIZE: usize = 0x10000;
+
+/// Errors that can occur while parsing or placing an image
+#[derive(Debug)]
+pub enum ImageError {
+    /// The image file couldn't be read
+    Io(io::Error),
+    /// A record was malformed. Line numbers start at 1.
+    InvalidRecord { line: usize, reason: &'static str },
+    /// A record's checksum didn't match its contents
+    Checksum { line: usize, expected: u8, actual: u8 },
+    /// Two records tried to place data at the same address
+    Overlap { address: u16 },
+    /// Data was placed outside of the 16-bit address space or the requested region
+    OutOfRange { address: u32 },
+    /// A region that needed to be fully populated had no data in the given range
+    Gap { start: u16, end_inclusive: u16 },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref err) => write!(f, "failed to read image: {}", err),
+            ImageError::InvalidRecord { line, reason } => write!(f, "invalid record on line {}: {}", line, reason),
+            ImageError::Checksum {
+                line,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "checksum mismatch on line {}: expected ${:02X}, found ${:02X}",
+                line, expected, actual
+            ),
+            ImageError::Overlap { address } => write!(f, "multiple records write to ${:04X}", address),
+            ImageError::OutOfRange { address } => write!(f, "address ${:X} is out of range", address),
+            ImageError::Gap {
+                start,
+                end_inclusive,
+            } => write!(f, "no data for ${:04X}-${:04X}", start, end_inclusive),
+        }
+    }
+}
+
+impl error::Error for ImageError {
+    fn description(&self) -> &str {
+        match *self {
+            ImageError::Io(_) => "failed to read image",
+            ImageError::InvalidRecord { .. } => "invalid record",
+            ImageError::Checksum { .. } => "checksum mismatch",
+            ImageError::Overlap { .. } => "overlapping records",
+            ImageError::OutOfRange { .. } => "address out of range",
+            ImageError::Gap { .. } => "gap in image",
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+/// Supported image file formats
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageFormat {
+    /// A raw binary that gets loaded starting at the given address
+    Raw(u16),
+    /// Intel HEX (.hex, .ihx)
+    IntelHex,
+    /// Motorola S-records (.s19, .s28, .s37, .srec)
+    SRecord,
+    /// Commodore-style program file with a 2-byte little-endian load address (.prg)
+    Prg,
+}
+
+impl ImageFormat {
+    /// Guesses the format of a file from its extension. Anything
+    /// unrecognized is treated as a raw binary at the given offset.
+    pub fn from_path<P: AsRef<Path>>(path: P, raw_offset: u16) -> ImageFormat {
+        let extension = path.as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_ref().map(|ext| ext.as_str()) {
+            Some("hex") | Some("ihx") | Some("ihex") => ImageFormat::IntelHex,
+            Some("s19") | Some("s28") | Some("s37") | Some("srec") | Some("mot") => ImageFormat::SRecord,
+            Some("prg") => ImageFormat::Prg,
+            _ => ImageFormat::Raw(raw_offset),
+        }
+    }
+}
+
+/// A contiguous run of bytes that belongs at a specific address
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageSegment {
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+impl ImageSegment {
+    /// Returns the last address covered by this segment
+    pub fn end_inclusive(&self) -> u16 {
+        (self.address as usize + self.data.len() - 1) as u16
+    }
+}
+
+/// Collects bytes by address while detecting overlapping records
+struct ImageAssembler {
+    bytes: Vec<Option<u8>>,
+}
+
+impl ImageAssembler {
+    fn new() -> ImageAssembler {
+        ImageAssembler {
+            bytes: vec![None; ADDRESS_SPACE_SIZE],
+        }
+    }
+
+    fn place(&mut self, address: u32, data: &[u8]) -> Result<(), ImageError> {
+        for (i, val) in data.iter().enumerate() {
+            let addr = address as usize + i;
+            if addr >= ADDRESS_SPACE_SIZE {
+                return Err(ImageError::OutOfRange { address: addr as u32 });
+            }
+            if self.bytes[addr].is_some() {
+                return Err(ImageError::Overlap { address: addr as u16 });
+            }
+            self.bytes[addr] = Some(*val);
+        }
+        Ok(())
+    }
+
+    fn finish(self, entry_point: Option<u16>) -> Image {
+        let mut segments: Vec<ImageSegment> = Vec::new();
+        for (addr, val) in self.bytes.into_iter().enumerate() {
+            if let Some(val) = val {
+                let extend = match segments.last() {
+                    Some(last) => last.end_inclusive() as usize + 1 == addr,
+                    None => false,
+                };
+                if extend {
+                    segments.last_mut().unwrap().data.push(val);
+                } else {
+                    segments.push(ImageSegment {
+                        address: addr as u16,
+                        data: vec![val],
+                    });
+                }
+            }
+        }
+        Image {
+            segments: segments,
+            entry_point: entry_point,
+        }
+    }
+}
+
+/// Parses a string of hex digit pairs into bytes
+fn parse_hex_bytes(line: usize, text: &str) -> Result<Vec<u8>, ImageError> {
+    if text.len() % 2 != 0 {
+        return Err(ImageError::InvalidRecord {
+            line: line,
+            reason: "odd number of hex digits",
+        });
+    }
+    let mut result = Vec::with_capacity(text.len() / 2);
+    for i in 0..(text.len() / 2) {
+        let byte = text.get(i * 2..i * 2 + 2)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or(ImageError::InvalidRecord {
+                line: line,
+                reason: "invalid hex digit",
+            })?;
+        result.push(byte);
+    }
+    Ok(result)
+}
+
+/// A program image made of one or more non-overlapping segments
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Image {
+    segments: Vec<ImageSegment>,
+    entry_point: Option<u16>,
+}
+
+impl Image {
+    /// Reads and parses an image file in the given format
+    pub fn load<P: AsRef<Path>>(path: P, format: ImageFormat) -> Result<Image, ImageError> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Image::parse(format, &data)
+    }
+
+    /// Parses an image from memory in the given format
+    pub fn parse(format: ImageFormat, data: &[u8]) -> Result<Image, ImageError> {
+        match format {
+            ImageFormat::Raw(offset) => Image::from_raw(offset, data),
+            ImageFormat::Prg => Image::from_prg(data),
+            ImageFormat::IntelHex | ImageFormat::SRecord => {
+                let text = String::from_utf8_lossy(data);
+                if format == ImageFormat::IntelHex {
+                    Image::from_intel_hex(&text)
+                } else {
+                    Image::from_srecord(&text)
+                }
+            }
+        }
+    }
+
+    /// Creates an image from a raw binary that starts at the given address
+    pub fn from_raw(offset: u16, data: &[u8]) -> Result<Image, ImageError> {
+        let mut assembler = ImageAssembler::new();
+        assembler.place(offset as u32, data)?;
+        Ok(assembler.finish(None))
+    }
+
+    /// Creates an image from individual segments, merging any that are adjacent.
+    /// Fails if any of the segments overlap.
+    pub fn from_segments(segments: Vec<ImageSegment>, entry_point: Option<u16>) -> Result<Image, ImageError> {
+        let mut assembler = ImageAssembler::new();
+        for segment in segments {
+            assembler.place(segment.address as u32, &segment.data)?;
+        }
+        Ok(assembler.finish(entry_point))
+    }
+
+    /// Creates an image from a Commodore-style PRG file, where the
+    /// first two bytes are the little-endian load address
+    pub fn from_prg(data: &[u8]) -> Result<Image, ImageError> {
+        if data.len() < 2 {
+            return Err(ImageError::InvalidRecord {
+                line: 1,
+                reason: "missing load address",
+            });
+        }
+        let load_address = (data[1] as u16) << 8 | data[0] as u16;
+        Image::from_raw(load_address, &data[2..])
+    }
+
+    /// Parses Intel HEX text. Supports data, end-of-file, extended segment
+    /// address, extended linear address, and start address records.
+    pub fn from_intel_hex(text: &str) -> Result<Image, ImageError> {
+        let mut assembler = ImageAssembler::new();
+        let mut base_address: u32 = 0;
+        let mut entry_point = None;
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let record = raw_line.trim();
+            if record.is_empty() {
+                continue;
+            }
+            if !record.starts_with(':') {
+                return Err(ImageError::InvalidRecord {
+                    line: line,
+                    reason: "record doesn't start with ':'",
+                });
+            }
+
+            let bytes = parse_hex_bytes(line, &record[1..])?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(ImageError::InvalidRecord {
+                    line: line,
+                    reason: "record length doesn't match byte count",
+                });
+            }
+
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = body.iter()
+                .fold(0u8, |sum, val| sum.wrapping_add(*val))
+                .wrapping_neg();
+            if expected != checksum[0] {
+                return Err(ImageError::Checksum {
+                    line: line,
+                    expected: expected,
+                    actual: checksum[0],
+                });
+            }
+
+            let address = (body[1] as u32) << 8 | body[2] as u32;
+            let data = &body[4..];
+            match body[3] {
+                0x00 => assembler.place(base_address + address, data)?,
+                0x01 => break,
+                0x02 if data.len() == 2 => {
+                    base_address = ((data[0] as u32) << 8 | data[1] as u32) << 4;
+                }
+                0x04 if data.len() == 2 => {
+                    base_address = ((data[0] as u32) << 8 | data[1] as u32) << 16;
+                }
+                0x03 | 0x05 if data.len() == 4 => {
+                    entry_point = Some((data[2] as u16) << 8 | data[3] as u16);
+                }
+                _ => {
+                    return Err(ImageError::InvalidRecord {
+                        line: line,
+                        reason: "unsupported record type",
+                    })
+                }
+            }
+        }
+        Ok(assembler.finish(entry_point))
+    }
+
+    /// Parses Motorola S-record text. Supports S1/S2/S3 data records and
+    /// S7/S8/S9 termination records. Header and count records are ignored.
+    pub fn from_srecord(text: &str) -> Result<Image, ImageError> {
+        let mut assembler = ImageAssembler::new();
+        let mut entry_point = None;
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let record = raw_line.trim();
+            if record.is_empty() {
+                continue;
+            }
+            if record.len() < 4 || !record.starts_with('S') {
+                return Err(ImageError::InvalidRecord {
+                    line: line,
+                    reason: "record doesn't start with 'S'",
+                });
+            }
+
+            let record_type = record.as_bytes()[1];
+            let digits = record.get(2..).ok_or(ImageError::InvalidRecord {
+                line: line,
+                reason: "invalid hex digit",
+            })?;
+            let bytes = parse_hex_bytes(line, digits)?;
+            if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+                return Err(ImageError::InvalidRecord {
+                    line: line,
+                    reason: "record length doesn't match byte count",
+                });
+            }
+
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = !body.iter().fold(0u8, |sum, val| sum.wrapping_add(*val));
+            if expected != checksum[0] {
+                return Err(ImageError::Checksum {
+                    line: line,
+                    expected: expected,
+                    actual: checksum[0],
+                });
+            }
+
+            let address_len = match record_type {
+                b'0' | b'1' | b'5' | b'9' => 2,
+                b'2' | b'6' | b'8' => 3,
+                b'3' | b'7' => 4,
+                _ => {
+                    return Err(ImageError::InvalidRecord {
+                        line: line,
+                        reason: "unsupported record type",
+                    })
+                }
+            };
+            if body.len() < 1 + address_len {
+                return Err(ImageError::InvalidRecord {
+                    line: line,
+                    reason: "record too short for its address",
+                });
+            }
+
+            let address = body[1..1 + address_len]
+                .iter()
+                .fold(0u32, |addr, val| addr << 8 | *val as u32);
+            let data = &body[1 + address_len..];
+            match record_type {
+                b'1' | b'2' | b'3' => assembler.place(address, data)?,
+                b'7' | b'8' | b'9' => {
+                    if address >= ADDRESS_SPACE_SIZE as u32 {
+                        return Err(ImageError::OutOfRange { address: address });
+                    }
+                    entry_point = Some(address as u16);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        Ok(assembler.finish(entry_point))
+    }
+
+    /// Returns the segments of this image in address order
+    pub fn segments(&self) -> &[ImageSegment] {
+        &self.segments
+    }
+
+    /// Returns the start address given by the image, if the format supports one
+    pub fn entry_point(&self) -> Option<u16> {
+        self.entry_point
+    }
+
+    /// Returns the byte placed at the given address, if any
+    pub fn byte(&self, addr: u16) -> Option<u8> {
+        for segment in &self.segments {
+            if segment.address <= addr && segment.end_inclusive() >= addr {
+                return Some(segment.data[(addr - segment.address) as usize]);
+            }
+        }
+        None
+    }
+
+    /// Flattens the image into a buffer covering exactly the given region, such as
+    /// for `MemoryMapBuilder::rom`. Fails if any part of the region isn't populated,
+    /// or if the image has data outside of the region.
+    pub fn to_rom(&self, start: u16, end_inclusive: u16) -> Result<Vec<u8>, ImageError> {
+        let data = self.to_region(start, end_inclusive, None)?;
+        Ok(data.into_iter().map(|val| val.unwrap()).collect())
+    }
+
+    /// Flattens the image into a buffer covering exactly the given region,
+    /// filling any unpopulated bytes with `fill`
+    pub fn to_rom_filled(&self, start: u16, end_inclusive: u16, fill: u8) -> Result<Vec<u8>, ImageError> {
+        let data = self.to_region(start, end_inclusive, Some(fill))?;
+        Ok(data.into_iter().map(|val| val.unwrap()).collect())
+    }
+
+    /// Creates a ROM device for the given region. Fails on gaps.
+    pub fn rom_device(&self, start: u16, end_inclusive: u16) -> Result<ROMDevice, ImageError> {
+        Ok(ROMDevice::new(start, self.to_rom(start, end_inclusive)?))
+    }
+
+    /// Creates a RAM device for the given region that is preloaded
+    /// with the image, and loads it again on a cold reset. Unpopulated
+    /// bytes are zeroed.
+    pub fn ram_device(&self, start: u16, end_inclusive: u16) -> Result<RAMDevice, ImageError> {
+        Ok(RAMDevice::with_data(start, self.to_rom_filled(start, end_inclusive, 0)?))
+    }
+
+    /// Writes the image into an existing memory map the way a device programmer
+    /// would, so bytes that land on ROM are written too. Bytes in unmapped ranges
+    /// are dropped.
+    pub fn write_to(&self, memory: &mut MemoryMap) {
+        for segment in &self.segments {
+            memory.load(segment.address, &segment.data, WriteMode::Programmer);
+        }
+    }
+
+    fn to_region(&self, start: u16, end_inclusive: u16, fill: Option<u8>) -> Result<Vec<Option<u8>>, ImageError> {
+        if end_inclusive < start {
+            return Err(ImageError::OutOfRange {
+                address: end_inclusive as u32,
+            });
+        }
+        for segment in &self.segments {
+            if segment.address < start {
+                return Err(ImageError::OutOfRange {
+                    address: segment.address as u32,
+                });
+            } else if segment.end_inclusive() > end_inclusive {
+                return Err(ImageError::OutOfRange {
+                    address: segment.end_inclusive() as u32,
+                });
+            }
+        }
+
+        let length = (end_inclusive as usize + 1) - start as usize;
+        let mut result = vec![fill; length];
+        for segment in &self.segments {
+            let offset = (segment.address - start) as usize;
+            for (i, val) in segment.data.iter().enumerate() {
+                result[offset + i] = Some(*val);
+            }
+        }
+
+        if let Some(gap_start) = result.iter().position(|val| val.is_none()) {
+            let gap_length = result[gap_start..]
+                .iter()
+                .position(|val| val.is_some())
+                .unwrap_or(length - gap_start);
+            return Err(ImageError::Gap {
+                start: start + gap_start as u16,
+                end_inclusive: start + (gap_start + gap_length - 1) as u16,
+            });
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Cpu;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_intel_hex() {
+        let hex = ":03FFFA00001000F4\n\
+                   :03FFFD00100000F1\n\
+                   :00000001FF\n";
+        let image = Image::from_intel_hex(hex).unwrap();
+        assert_eq!(1, image.segments().len());
+        assert_eq!(0xFFFA, image.segments()[0].address);
+        assert_eq!(
+            vec![0x00, 0x10, 0x00, 0x10, 0x00, 0x00],
+            image.to_rom(0xFFFA, 0xFFFF).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_intel_hex_checksum() {
+        match Image::from_intel_hex(":0100000001FF\n") {
+            Err(ImageError::Checksum {
+                line: 1,
+                expected: 0xFE,
+                actual: 0xFF,
+            }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_srecord() {
+        let srec = "S00600004844521B\n\
+                    S1070200A9038500C5\n\
+                    S9030200FA\n";
+        let image = Image::from_srecord(srec).unwrap();
+        assert_eq!(Some(0x0200), image.entry_point());
+        assert_eq!(Some(0xA9), image.byte(0x0200));
+        assert_eq!(Some(0x00), image.byte(0x0203));
+        assert_eq!(None, image.byte(0x0204));
+
+        match Image::from_srecord("S\u{e9}0302\n") {
+            Err(ImageError::InvalidRecord { line: 1, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_prg_and_gaps() {
+        let image = Image::from_prg(&[0x00, 0xC0, 0xEA, 0xEA]).unwrap();
+        assert_eq!(Some(0xEA), image.byte(0xC001));
+        match image.to_rom(0xC000, 0xC003) {
+            Err(ImageError::Gap {
+                start: 0xC002,
+                end_inclusive: 0xC003,
+            }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            vec![0xEA, 0xEA, 0xFF, 0xFF],
+            image.to_rom_filled(0xC000, 0xC003, 0xFF).unwrap()
+        );
+        match image.ram_device(0xC003, 0xC000) {
+            Err(ImageError::OutOfRange { address: 0xC000 }) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_write_to_rom() {
+        let image = Image::from_prg(&[0xFE, 0xBF, 0xA9, 0x01, 0xEA]).unwrap();
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0xBFFF)
+            .rom(0xC000, 0xFFFF, vec![0; 0x4000])
+            .build();
+        image.write_to(&mut memory);
+        assert_eq!(0xA9, memory.debug_read().byte(0xBFFE));
+        assert_eq!(0xEA, memory.debug_read().byte(0xC000));
+    }
+
+    #[test]
+    fn test_ram_device_power_on() {
+        let image = Image::from_segments(
+            vec![
+                ImageSegment {
+                    address: 0x0200,
+                    data: vec![0xA9, 0x01],
+                },
+                ImageSegment {
+                    address: 0xFFFC,
+                    data: vec![0x00, 0x02],
+                },
+            ],
+            None,
+        ).unwrap();
+        let ram = image.ram_device(0x0000, 0xFFFF).unwrap();
+        let memory = MemoryMap::builder()
+            .peripheral(0x0000, 0xFFFF, Rc::new(RefCell::new(ram)))
+            .build();
+        let mut cpu = Cpu::new(memory);
+
+        // A cold reset puts the image back rather than clearing it
+        cpu.memory_mut().write().byte(0x0201, 0x05);
+        cpu.power_on();
+        cpu.step();
+        assert_eq!(0x01, cpu.registers().a);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let hex = ":02100000EAEA1A\n\
+                   :01100100EA04\n";
+        match Image::from_intel_hex(hex) {
+            Err(ImageError::Overlap { address: 0x1001 }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
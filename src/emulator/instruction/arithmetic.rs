@@ -50,7 +50,7 @@ impl_instruction!(SBC => execute_sbc [mode, params, reg, memory, result] {
 // TODO: unit test
 impl_instruction!(DEC => execute_dec [mode, params, reg, memory, result] {
     let address = mode.address(params, reg, memory).1;
-    let val = memory.read().byte(address).wrapping_sub(1);
+    let val = memory.read(address).wrapping_sub(1);
     result.reg.status.set_nz_from(val);
     result.writes.push(Write::new(address, val));
 });
@@ -68,7 +68,7 @@ impl_instruction!(DEY => execute_dey [_mode, _params, reg, _memory, result] {
 // TODO: unit test
 impl_instruction!(INC => execute_inc [mode, params, reg, memory, result] {
     let address = mode.address(params, reg, memory).1;
-    let val = memory.read().byte(address).wrapping_add(1);
+    let val = memory.read(address).wrapping_add(1);
     result.reg.status.set_nz_from(val);
     result.writes.push(Write::new(address, val));
 });
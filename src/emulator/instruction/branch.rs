@@ -57,7 +57,7 @@ impl_instruction!(BVS => execute_bvs [_mode, params, reg, _memory, result] {
 impl_instruction!(JMP => execute_jmp [mode, params, _reg, memory, result] {
     match mode {
         OpAddressMode::Absolute => result.reg.pc = params.as_u16(),
-        OpAddressMode::Indirect => result.reg.pc = memory.read().word(params.as_u16()),
+        OpAddressMode::Indirect => result.reg.pc = memory.read_word(params.as_u16()),
         _ => unreachable!()
     }
 });
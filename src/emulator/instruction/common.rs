@@ -7,7 +7,7 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use emulator::memory::MemoryMap;
+use emulator::bus::Bus;
 use emulator::opcode::{CpuAddressMode, OpAddressMode};
 use emulator::registers::Registers;
 use emulator::instruction::executor::InstructionResult;
@@ -30,7 +30,7 @@ macro_rules! impl_instruction {
                 $mode: OpAddressMode,
                 $params: &OpParam,
                 $reg: &Registers,
-                $memory: &mut ::emulator::memory::MemoryMap,
+                $memory: &mut ::emulator::bus::Bus,
                 mut $result: InstructionResult)
         -> InstructionResult {
             $block
@@ -69,7 +69,7 @@ pub fn execute(
     mode: OpAddressMode,
     param: &OpParam,
     reg: &Registers,
-    memory: &mut MemoryMap,
+    memory: &mut Bus,
     mut result: InstructionResult,
 ) -> InstructionResult {
     result.writes.clear();
@@ -88,9 +88,9 @@ pub fn push(mut result: InstructionResult, val: u8) -> InstructionResult {
 }
 
 #[inline]
-pub fn pop(result: &mut InstructionResult, memory: &mut MemoryMap) -> u8 {
+pub fn pop(result: &mut InstructionResult, memory: &mut Bus) -> u8 {
     result.reg.sp = result.reg.sp.wrapping_add(1);
-    memory.read().byte(STACK_ADDR + result.reg.sp as u16)
+    memory.read(STACK_ADDR + result.reg.sp as u16)
 }
 
 // TODO: unit test
@@ -7,7 +7,7 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use emulator::memory::MemoryMap;
+use emulator::bus::Bus;
 use emulator::opcode::{self, Op, OpAddressMode, OpClass, OpParam};
 use emulator::registers::Registers;
 
@@ -42,7 +42,7 @@ impl InstructionResult {
     }
 }
 
-pub type InstructionFn = &'static Fn(OpAddressMode, &OpParam, &Registers, &mut MemoryMap, InstructionResult)
+pub type InstructionFn = &'static Fn(OpAddressMode, &OpParam, &Registers, &mut Bus, InstructionResult)
     -> InstructionResult;
 
 struct Instruction {
@@ -60,7 +60,7 @@ impl Executor {
     pub fn execute_instruction(
         &mut self,
         reg: &Registers,
-        memory: &mut MemoryMap,
+        memory: &mut Bus,
         mut result: InstructionResult,
     ) -> InstructionResult {
         let op = opcode::decode_op(memory, reg.pc);
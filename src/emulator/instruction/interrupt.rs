@@ -25,5 +25,5 @@ impl_instruction!(BRK => execute_brk [_mode, _params, reg, memory, result] {
     result = push(result, reg_status);
 
     result.reg.status.set_brk(true);
-    result.reg.pc = memory.read().word(BRK_VECTOR);
+    result.reg.pc = memory.read_word(BRK_VECTOR);
 });
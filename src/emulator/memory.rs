@@ -7,11 +7,19 @@
 // copied, modified, or distributed except according to those terms.
 //
 
+use std::any::Any;
 use std::rc::Rc;
 use std::cell::{Ref, RefCell, RefMut};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
 use std::mem;
+use std::path::Path;
 
-use emulator::cpu::InterruptType;
+use emulator::banking::{BankRegister, BankSwitch, BankedDevice, OverlayDevice};
+use emulator::bus::Bus;
+use emulator::cpu::{InterruptType, ResetKind};
 
 macro_rules! read_word {
     ($memory:ident, $addr:expr) => {
@@ -112,6 +120,28 @@ impl<'a> WriteMemory for NormalMemoryView<'a> {
     }
 }
 
+/// The kinds of bus access reported to a `BusObserver`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccessKind {
+    /// The CPU read the first byte of an instruction
+    OpCodeFetch,
+    /// The CPU read one of the operand bytes that follow the op-code
+    OperandFetch,
+    /// Any other read, such as by a load instruction or when popping the stack
+    Read,
+    /// A write
+    Write,
+}
+
+/// Receives every access made through `MemoryMap::read`, `MemoryMap::write` and the
+/// CPU's instruction fetches. Reads through `MemoryMap::debug_read` aren't reported,
+/// and neither are accesses that devices make to the memory map while being stepped.
+pub trait BusObserver {
+    /// Called after each access with the address, the value that was read or written,
+    /// and the CPU cycle count at the start of the instruction that made the access
+    fn access(&mut self, address: u16, value: u8, cycle: usize, kind: AccessKind);
+}
+
 /// Any device that is attached to the system bus, including (but not limited to):
 ///
 /// * RAM
@@ -137,6 +167,13 @@ pub trait MemoryMappedDevice {
     /// Writes a byte to this device
     fn write_byte(&mut self, addr: u16, val: u8);
 
+    /// Writes a byte the way a device programmer would, ignoring any write
+    /// protection. Only devices that ignore normal writes, such as ROM, need
+    /// to override this.
+    fn program_byte(&mut self, addr: u16, val: u8) {
+        self.write_byte(addr, val);
+    }
+
     /// Tells the MemoryMap whether or not this device actually
     /// does anything in its step method. This value is cached,
     /// so it should be constant. This is merely an optimization.
@@ -146,19 +183,74 @@ pub trait MemoryMappedDevice {
     /// that is called on every instruction execution. This should probably
     /// take a cycle count to support peripheral timings, but it doesn't currently.
     fn step(&mut self, memory: &mut MemoryMap) -> Option<InterruptType>;
+
+    /// Called when the system is reset. Devices should return to their power-on
+    /// state on a cold reset, and to whatever state the RES line puts them in on a
+    /// warm reset. Does nothing by default.
+    fn reset(&mut self, _kind: ResetKind) {}
+}
+
+/// How RAM is filled when it's created. Real RAM powers on with unpredictable
+/// contents, so filling it with something other than zeros can bring out code
+/// that reads memory before writing it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RamFill {
+    Zero,
+    Value(u8),
+    /// Pseudo-random bytes generated from the given seed, so that runs can be reproduced
+    Random(u64),
 }
 
 /// Random access memory device
 pub struct RAMDevice {
     start: u16,
     memory: Vec<u8>,
+    /// How the memory is filled again on a cold reset
+    fill: RamFill,
+    /// Contents that a cold reset restores instead of using the fill
+    initial: Option<Vec<u8>>,
 }
 
 impl RAMDevice {
     pub fn new(start: u16, size: usize) -> RAMDevice {
+        RAMDevice::filled(start, size, RamFill::Zero)
+    }
+
+    /// Creates a RAM device with contents set by the given fill. Random fills
+    /// also depend on `start`, so that separate devices get different bytes.
+    pub fn filled(start: u16, size: usize, fill: RamFill) -> RAMDevice {
+        let memory = match fill {
+            RamFill::Zero => vec![0u8; size],
+            RamFill::Value(val) => vec![val; size],
+            RamFill::Random(seed) => {
+                // xorshift64, which must not be seeded with zero
+                let mut state = (seed ^ (start as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1;
+                (0..size)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state >> 32) as u8
+                    })
+                    .collect()
+            }
+        };
+        RAMDevice {
+            start: start,
+            memory: memory,
+            fill: fill,
+            initial: None,
+        }
+    }
+
+    /// Creates a RAM device that is preloaded with the given contents. A cold
+    /// reset puts these contents back.
+    pub fn with_data(start: u16, memory: Vec<u8>) -> RAMDevice {
         RAMDevice {
             start: start,
-            memory: vec![0u8; size],
+            initial: Some(memory.clone()),
+            memory: memory,
+            fill: RamFill::Zero,
         }
     }
 }
@@ -185,6 +277,15 @@ impl MemoryMappedDevice for RAMDevice {
     fn step(&mut self, _memory: &mut MemoryMap) -> Option<InterruptType> {
         None
     }
+
+    fn reset(&mut self, kind: ResetKind) {
+        if kind == ResetKind::Cold {
+            self.memory = match self.initial {
+                Some(ref initial) => initial.clone(),
+                None => RAMDevice::filled(self.start, self.memory.len(), self.fill).memory,
+            };
+        }
+    }
 }
 
 /// Readonly memory device
@@ -214,6 +315,11 @@ impl MemoryMappedDevice for ROMDevice {
 
     fn write_byte(&mut self, _addr: u16, _val: u8) {}
 
+    fn program_byte(&mut self, addr: u16, val: u8) {
+        let offset = addr.wrapping_sub(self.start);
+        self.memory[offset as usize] = val;
+    }
+
     fn requires_step(&self) -> bool {
         false
     }
@@ -251,25 +357,97 @@ impl MemoryMappedDevice for NullDevice {
     }
 }
 
+/// What happens when the CPU accesses an address that no device responds to
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Unmapped {
+    /// Reads return the last value seen on the data bus, which is what most
+    /// 6502 systems do since nothing drives the bus
+    OpenBus,
+    /// Reads return the given value, as with pull-up or pull-down resistors
+    Value(u8),
+    /// Reads behave like open bus, but the access is recorded so the run can be
+    /// stopped. See `MemoryMap::take_trap`.
+    Trap,
+}
+
+/// An access to an unmapped range configured with `Unmapped::Trap`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BusTrap {
+    pub address: u16,
+    pub kind: AccessKind,
+}
+
+/// How an address in a segment's range is turned into the address its device sees
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Decoder {
+    relative: bool,
+    mask: u16,
+    period: usize,
+}
+
+impl Decoder {
+    fn new() -> Decoder {
+        Decoder {
+            relative: false,
+            mask: 0xFFFF,
+            period: 0x10000,
+        }
+    }
+
+    fn decode(&self, start: u16, addr: u16) -> u16 {
+        let offset = ((addr.wrapping_sub(start) & self.mask) as usize % self.period) as u16;
+        if self.relative {
+            offset
+        } else {
+            start.wrapping_add(offset)
+        }
+    }
+}
+
+/// Extra cycles that each access to a segment takes
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+struct WaitStates {
+    read: usize,
+    write: usize,
+}
+
 #[derive(Clone)]
 struct MemorySegment {
+    name: Rc<String>,
     start: u16,
     end_inclusive: u16,
     device: Rc<RefCell<MemoryMappedDevice>>,
     requires_step: bool,
+    unmapped: Option<Unmapped>,
+    decoder: Decoder,
+    switch: Option<BankSwitch>,
+    wait_states: WaitStates,
+    /// The device with its concrete type, for devices added with `MemoryMapBuilder::device`
+    typed_device: Option<Rc<Any>>,
 }
 
 impl MemorySegment {
-    fn new(start: u16, end_inclusive: u16, device: Rc<RefCell<MemoryMappedDevice>>) -> MemorySegment {
+    fn new(name: Rc<String>, start: u16, end_inclusive: u16, device: Rc<RefCell<MemoryMappedDevice>>) -> MemorySegment {
         let requires_step = device.borrow().requires_step();
         MemorySegment {
+            name: name,
             start: start,
             end_inclusive: end_inclusive,
             device: device,
             requires_step: requires_step,
+            unmapped: None,
+            decoder: Decoder::new(),
+            switch: None,
+            wait_states: WaitStates::default(),
+            typed_device: None,
         }
     }
 
+    /// Returns the address that the device sees for an address in the segment
+    fn device_address(&self, addr: u16) -> u16 {
+        self.decoder.decode(self.start, addr)
+    }
+
     pub fn debug<'a>(&'a self) -> DebugMemoryView<'a> {
         DebugMemoryView {
             device: self.device.borrow(),
@@ -291,13 +469,191 @@ impl MemorySegment {
     }
 
     fn with_device(&self, device: Rc<RefCell<MemoryMappedDevice>>) -> MemorySegment {
-        MemorySegment::new(self.start, self.end_inclusive, device)
+        let mut segment = MemorySegment::new(Rc::clone(&self.name), self.start, self.end_inclusive, device);
+        segment.unmapped = self.unmapped;
+        segment.decoder = self.decoder;
+        segment.switch = self.switch.clone();
+        segment.wait_states = self.wait_states;
+        segment.typed_device = self.typed_device.clone();
+        segment
+    }
+}
+
+/// A single problem found while building a memory map
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MemoryMapProblem {
+    /// No device covers the given range
+    Gap { start: u16, end_inclusive: u16 },
+    /// Two devices were both mapped to the given range
+    Overlap {
+        first: String,
+        second: String,
+        start: u16,
+        end_inclusive: u16,
+    },
+    /// A device's range ends before it starts
+    InvalidRange {
+        name: String,
+        start: u16,
+        end_inclusive: u16,
+    },
+    /// A banked window was given no banks
+    NoBanks { name: String },
+    /// A bank register was added for a window that doesn't exist
+    UnknownBank { name: String },
+    /// A device was mirrored with a period of zero
+    InvalidMirror { name: String },
+    /// A ROM was given a different number of bytes than its range holds
+    SizeMismatch {
+        name: String,
+        start: u16,
+        end_inclusive: u16,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for MemoryMapProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryMapProblem::Gap {
+                start,
+                end_inclusive,
+            } => write!(f, "nothing is mapped at ${:04X}-${:04X}", start, end_inclusive),
+            MemoryMapProblem::Overlap {
+                ref first,
+                ref second,
+                start,
+                end_inclusive,
+            } => write!(
+                f,
+                "{} and {} overlap at ${:04X}-${:04X}",
+                first, second, start, end_inclusive
+            ),
+            MemoryMapProblem::InvalidRange {
+                ref name,
+                start,
+                end_inclusive,
+            } => write!(
+                f,
+                "{} ends at ${:04X} before it starts at ${:04X}",
+                name, end_inclusive, start
+            ),
+            MemoryMapProblem::NoBanks { ref name } => write!(f, "{} has no banks", name),
+            MemoryMapProblem::UnknownBank { ref name } => write!(f, "no banked window is named {}", name),
+            MemoryMapProblem::InvalidMirror { ref name } => write!(f, "{} is mirrored every 0 bytes", name),
+            MemoryMapProblem::SizeMismatch {
+                ref name,
+                start,
+                end_inclusive,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} at ${:04X}-${:04X} needs {} bytes but was given {}",
+                name, start, end_inclusive, expected, actual
+            ),
+        }
+    }
+}
+
+/// Returned by `MemoryMapBuilder::try_build` with every problem in the configuration
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryMapError {
+    pub problems: Vec<MemoryMapProblem>,
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid memory map: ")?;
+        for (index, problem) in self.problems.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for MemoryMapError {
+    fn description(&self) -> &str {
+        "invalid memory map"
+    }
+}
+
+/// A device that has been added to a builder but not checked yet
+struct PendingSegment {
+    name: String,
+    start: u16,
+    end_inclusive: u16,
+    device: Option<Rc<RefCell<MemoryMappedDevice>>>,
+    /// Number of bytes given for a ROM, which must match the range
+    data_size: Option<usize>,
+    unmapped: Option<Unmapped>,
+    decoder: Decoder,
+    /// Selects the bank of a banked window or overlay
+    switch: Option<BankSwitch>,
+    /// Name of the window that a bank register selects for, which is
+    /// looked up once every segment has been added
+    bank_register: Option<String>,
+    wait_states: WaitStates,
+    typed_device: Option<Rc<Any>>,
+}
+
+impl PendingSegment {
+    fn new(
+        name: &str,
+        start: u16,
+        end_inclusive: u16,
+        device: Option<Rc<RefCell<MemoryMappedDevice>>>,
+    ) -> PendingSegment {
+        PendingSegment {
+            name: name.into(),
+            start: start,
+            end_inclusive: end_inclusive,
+            device: device,
+            data_size: None,
+            unmapped: None,
+            decoder: Decoder::new(),
+            switch: None,
+            bank_register: None,
+            wait_states: WaitStates::default(),
+            typed_device: None,
+        }
+    }
+
+    fn unmapped(start: u16, end_inclusive: u16, behavior: Unmapped) -> PendingSegment {
+        let name = match behavior {
+            Unmapped::OpenBus => "open bus".to_string(),
+            Unmapped::Value(val) => format!("unmapped (${:02X})", val),
+            Unmapped::Trap => "unmapped (trap)".to_string(),
+        };
+        let mut segment = PendingSegment::new(
+            &name,
+            start,
+            end_inclusive,
+            Some(Rc::new(RefCell::new(NullDevice::new()))),
+        );
+        segment.unmapped = Some(behavior);
+        segment
+    }
+
+    /// Returns the number of addresses in the range, if it's valid
+    fn length(&self) -> Option<usize> {
+        if self.end_inclusive >= self.start {
+            Some((self.end_inclusive as usize + 1) - self.start as usize)
+        } else {
+            None
+        }
     }
 }
 
 /// Builder interface for constructing a memory map
 pub struct MemoryMapBuilder {
-    segments: Vec<MemorySegment>,
+    segments: Vec<PendingSegment>,
+    ram_fill: RamFill,
+    gaps: Option<Unmapped>,
 }
 
 impl MemoryMapBuilder {
@@ -305,100 +661,462 @@ impl MemoryMapBuilder {
     pub fn new() -> MemoryMapBuilder {
         MemoryMapBuilder {
             segments: Vec::new(),
+            ram_fill: RamFill::Zero,
+            gaps: None,
         }
     }
 
+    /// Sets how RAM added by later calls to `ram` is filled. The default is zeros.
+    pub fn ram_fill(mut self, fill: RamFill) -> Self {
+        self.ram_fill = fill;
+        self
+    }
+
     /// Adds RAM to the memory map
-    pub fn ram(self, start: u16, end_inclusive: u16) -> Self {
-        assert!(end_inclusive >= start);
-        let length = (end_inclusive as usize + 1) - start as usize;
-        self.peripheral(
-            start,
-            end_inclusive,
-            Rc::new(RefCell::new(RAMDevice::new(start, length))),
-        )
+    pub fn ram(mut self, start: u16, end_inclusive: u16) -> Self {
+        let mut segment = PendingSegment::new("RAM", start, end_inclusive, None);
+        if let Some(length) = segment.length() {
+            let device = RAMDevice::filled(start, length, self.ram_fill);
+            segment.device = Some(Rc::new(RefCell::new(device)));
+        }
+        self.segments.push(segment);
+        self
     }
 
-    /// Adds ROM to the memory map
-    pub fn rom(self, start: u16, end_inclusive: u16, data: Vec<u8>) -> Self {
-        assert!(end_inclusive >= start);
-        let length = (end_inclusive as usize + 1) - start as usize;
-        assert_eq!(
-            length,
-            data.len(),
-            "given rom is not the right size for the built memory map"
-        );
-        self.peripheral(
-            start,
-            end_inclusive,
-            Rc::new(RefCell::new(ROMDevice::new(start, data))),
-        )
+    /// Adds ROM to the memory map. The data must be exactly the size of the range.
+    pub fn rom(mut self, start: u16, end_inclusive: u16, data: Vec<u8>) -> Self {
+        let mut segment = PendingSegment::new("ROM", start, end_inclusive, None);
+        segment.data_size = Some(data.len());
+        segment.device = Some(Rc::new(RefCell::new(ROMDevice::new(start, data))));
+        self.segments.push(segment);
+        self
     }
 
-    /// Adds a peripheral to the memory map
+    /// Adds a peripheral to the memory map. The device's concrete type is gone by
+    /// now, so it can't be found with `MemoryMap::device`; add it with `device` for that.
     pub fn peripheral(mut self, start: u16, end_inclusive: u16, device: Rc<RefCell<MemoryMappedDevice>>) -> Self {
         self.segments
-            .push(MemorySegment::new(start, end_inclusive, device));
+            .push(PendingSegment::new("peripheral", start, end_inclusive, Some(device)));
+        self
+    }
+
+    /// Adds a device under the given name, so that it can be found again with
+    /// `MemoryMap::device` without keeping a clone of it around
+    pub fn device<T: MemoryMappedDevice + 'static>(
+        mut self,
+        name: &str,
+        start: u16,
+        end_inclusive: u16,
+        device: Rc<RefCell<T>>,
+    ) -> Self {
+        let mut segment = PendingSegment::new(name, start, end_inclusive, Some(device.clone()));
+        segment.typed_device = Some(device);
+        self.segments.push(segment);
+        self
+    }
+
+    /// Adds a window that shows one of the given devices at a time. Bank 0 is
+    /// selected to begin with. Use `bank_register` to let the program switch
+    /// banks, or `MemoryMap::bank_switch` to switch them from outside.
+    pub fn banked(
+        mut self,
+        name: &str,
+        start: u16,
+        end_inclusive: u16,
+        banks: Vec<Rc<RefCell<MemoryMappedDevice>>>,
+    ) -> Self {
+        let mut segment = PendingSegment::new(name, start, end_inclusive, None);
+        if !banks.is_empty() {
+            let device = Rc::new(RefCell::new(BankedDevice::new(banks)));
+            segment.switch = Some(device.borrow().switch());
+            segment.device = Some(device.clone());
+            segment.typed_device = Some(device);
+        }
+        self.segments.push(segment);
+        self
+    }
+
+    /// Adds a window onto RAM that's split into the given number of banks, each
+    /// the size of the window. This can hold more than 64K of RAM in total.
+    pub fn banked_ram(self, name: &str, start: u16, end_inclusive: u16, banks: usize) -> Self {
+        let length = if end_inclusive >= start {
+            (end_inclusive as usize + 1) - start as usize
+        } else {
+            0
+        };
+        let fill = self.ram_fill;
+        let banks = (0..banks)
+            .map(|bank| {
+                // Give each bank different contents when they're random
+                let fill = match fill {
+                    RamFill::Random(seed) => RamFill::Random(seed.wrapping_add(bank as u64)),
+                    fill => fill,
+                };
+                Rc::new(RefCell::new(RAMDevice::filled(start, length, fill))) as Rc<RefCell<MemoryMappedDevice>>
+            })
+            .collect();
+        self.banked(name, start, end_inclusive, banks)
+    }
+
+    /// Adds ROM that shadows RAM in the same range until it's disabled through
+    /// a `bank_register` (by writing 0) or `MemoryMap::bank_switch`. Writes always
+    /// go to the RAM, so the ROM can copy itself there before it's disabled.
+    pub fn overlay(mut self, name: &str, start: u16, end_inclusive: u16, data: Vec<u8>) -> Self {
+        let mut segment = PendingSegment::new(name, start, end_inclusive, None);
+        segment.data_size = Some(data.len());
+        if let Some(length) = segment.length() {
+            let device = Rc::new(RefCell::new(OverlayDevice::new(
+                ROMDevice::new(start, data),
+                RAMDevice::filled(start, length, self.ram_fill),
+            )));
+            segment.switch = Some(device.borrow().switch());
+            segment.device = Some(device.clone());
+            segment.typed_device = Some(device);
+        }
+        self.segments.push(segment);
+        self
+    }
+
+    /// Adds a one-byte register at `address` that selects the bank of the named
+    /// `banked` window or `overlay` when written
+    pub fn bank_register(mut self, address: u16, window: &str) -> Self {
+        let mut segment = PendingSegment::new("bank register", address, address, None);
+        segment.bank_register = Some(window.into());
+        self.segments.push(segment);
+        self
+    }
+
+    /// Leaves a range without a device. Writes to it are ignored.
+    pub fn unmapped(mut self, start: u16, end_inclusive: u16, behavior: Unmapped) -> Self {
+        self.segments
+            .push(PendingSegment::unmapped(start, end_inclusive, behavior));
+        self
+    }
+
+    /// Treats every range that isn't covered by a device as unmapped, instead of
+    /// reporting gaps as errors
+    pub fn unmapped_gaps(mut self, behavior: Unmapped) -> Self {
+        self.gaps = Some(behavior);
+        self
+    }
+
+    /// Passes the device added last offsets from the start of its range instead
+    /// of absolute addresses, so it doesn't need to know where it's mapped
+    pub fn relative(mut self) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.decoder.relative = true;
+        }
+        self
+    }
+
+    /// Repeats the device added last every `period` bytes through its range, the
+    /// way a small device appears on a decoder that ignores the low address lines
+    pub fn mirrored(mut self, period: usize) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.decoder.period = period;
+        }
+        self
+    }
+
+    /// Only decodes the offset bits in `mask` for the device added last. Offsets
+    /// are counted from the start of its range, so a mask of `$0007` on a device
+    /// at `$D000-$D0FF` repeats its first 8 addresses through the page.
+    pub fn address_mask(mut self, mask: u16) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.decoder.mask = mask;
+        }
+        self
+    }
+
+    /// Makes every access to the device added last take extra cycles, such as
+    /// for slow ROM or peripherals that stretch the clock. They're added to the
+    /// cycles that `Cpu::step` returns.
+    pub fn wait_states(mut self, cycles: usize) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.wait_states = WaitStates {
+                read: cycles,
+                write: cycles,
+            };
+        }
         self
     }
 
-    /// Constructs and validates the memory map. Checks for
-    /// gaps and overlaps in the configured memory segments.
-    /// Also verifies the entire address space is covered by
-    /// devices.
-    pub fn build(mut self) -> MemoryMap {
+    /// Sets different wait states for writes to the device added last, such as
+    /// for EEPROM that's slower to write than to read. Call it after `wait_states`.
+    pub fn write_wait_states(mut self, cycles: usize) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.wait_states.write = cycles;
+        }
+        self
+    }
+
+    /// Names the device added last, for errors and the map's `Display` output.
+    /// Devices are called RAM, ROM or peripheral otherwise.
+    pub fn named(mut self, name: &str) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.name = name.into();
+        }
+        self
+    }
+
+    /// Constructs and validates the memory map, panicking if `try_build` finds any problems
+    pub fn build(self) -> MemoryMap {
+        match self.try_build() {
+            Ok(memory_map) => memory_map,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Constructs and validates the memory map. Checks for gaps and overlaps
+    /// in the configured memory segments, that ROMs are the size of their
+    /// ranges, and that the entire address space is covered by devices or
+    /// unmapped ranges.
+    pub fn try_build(mut self) -> Result<MemoryMap, MemoryMapError> {
+        let mut problems = Vec::new();
+        for segment in &self.segments {
+            if segment.end_inclusive < segment.start {
+                problems.push(MemoryMapProblem::InvalidRange {
+                    name: segment.name.clone(),
+                    start: segment.start,
+                    end_inclusive: segment.end_inclusive,
+                });
+                continue;
+            }
+            if segment.device.is_none() && segment.bank_register.is_none() {
+                problems.push(MemoryMapProblem::NoBanks {
+                    name: segment.name.clone(),
+                });
+            }
+            if segment.decoder.period == 0 {
+                problems.push(MemoryMapProblem::InvalidMirror {
+                    name: segment.name.clone(),
+                });
+            }
+            let expected = (segment.end_inclusive as usize + 1) - segment.start as usize;
+            match segment.data_size {
+                Some(actual) if actual != expected => problems.push(MemoryMapProblem::SizeMismatch {
+                    name: segment.name.clone(),
+                    start: segment.start,
+                    end_inclusive: segment.end_inclusive,
+                    expected: expected,
+                    actual: actual,
+                }),
+                _ => {}
+            }
+        }
+
+        self.segments
+            .retain(|segment| segment.end_inclusive >= segment.start);
+
+        let switches: Vec<(String, BankSwitch)> = self.segments
+            .iter()
+            .filter_map(|segment| {
+                segment
+                    .switch
+                    .as_ref()
+                    .map(|switch| (segment.name.clone(), switch.clone()))
+            })
+            .collect();
+        for segment in &mut self.segments {
+            if let Some(ref window) = segment.bank_register {
+                match switches.iter().find(|&&(ref name, _)| name == window) {
+                    Some(&(_, ref switch)) => {
+                        segment.device = Some(Rc::new(RefCell::new(BankRegister::new(switch.clone()))));
+                    }
+                    None => problems.push(MemoryMapProblem::UnknownBank {
+                        name: window.clone(),
+                    }),
+                }
+            }
+        }
         self.segments.sort_by(|lhs, rhs| lhs.start.cmp(&rhs.start));
 
+        // The next address that needs covering, and the segment that covered the one before it
         let mut address: usize = 0;
+        let mut previous: Option<&PendingSegment> = None;
+        let mut gaps = Vec::new();
         for segment in &self.segments {
-            assert!(
-                address == segment.start as usize,
-                "found a gap in built memory map"
-            );
-            address = segment.end_inclusive as usize + 1;
-        }
-        assert!(
-            address == 0x10000,
-            "built memory map doesn't cover the full address range"
-        );
+            let start = segment.start as usize;
+            if start > address {
+                gaps.push((address as u16, segment.start - 1));
+            } else if start < address {
+                if let Some(previous) = previous {
+                    problems.push(MemoryMapProblem::Overlap {
+                        first: previous.name.clone(),
+                        second: segment.name.clone(),
+                        start: segment.start,
+                        end_inclusive: segment.end_inclusive.min((address - 1) as u16),
+                    });
+                }
+            }
+            if segment.end_inclusive as usize + 1 > address {
+                address = segment.end_inclusive as usize + 1;
+                previous = Some(segment);
+            }
+        }
+        if address < 0x10000 {
+            gaps.push((address as u16, 0xFFFF));
+        }
+        match self.gaps {
+            Some(behavior) => {
+                self.segments.extend(
+                    gaps.into_iter()
+                        .map(|(start, end_inclusive)| PendingSegment::unmapped(start, end_inclusive, behavior)),
+                );
+                self.segments.sort_by(|lhs, rhs| lhs.start.cmp(&rhs.start));
+            }
+            None => problems.extend(gaps.into_iter().map(|(start, end_inclusive)| {
+                MemoryMapProblem::Gap {
+                    start: start,
+                    end_inclusive: end_inclusive,
+                }
+            })),
+        }
 
-        MemoryMap::new(self.segments)
+        if !problems.is_empty() {
+            return Err(MemoryMapError { problems: problems });
+        }
+        let segments = self.segments
+            .into_iter()
+            .map(|segment| {
+                let mut built = MemorySegment::new(
+                    Rc::new(segment.name),
+                    segment.start,
+                    segment.end_inclusive,
+                    segment.device.unwrap(),
+                );
+                built.unmapped = segment.unmapped;
+                built.decoder = segment.decoder;
+                built.switch = segment.switch;
+                built.wait_states = segment.wait_states;
+                built.typed_device = segment.typed_device;
+                built
+            })
+            .collect();
+        Ok(MemoryMap::new(segments))
     }
 }
 
 struct MemoryMapInner {
     segments: Vec<MemorySegment>,
+    observers: Vec<Rc<RefCell<BusObserver>>>,
+    cycle: usize,
+    /// The last value read or written, which is what open bus reads return
+    bus_value: u8,
+    /// Wait state cycles from accesses since they were last taken
+    wait_cycles: usize,
+    trap: Option<BusTrap>,
 }
 
 impl MemoryMapInner {
-    fn segment(&self, addr: u16) -> &MemorySegment {
-        for segment in &self.segments {
-            if segment.start <= addr && segment.end_inclusive >= addr {
-                return segment;
+    fn notify(&self, addr: u16, val: u8, kind: AccessKind) {
+        for observer in &self.observers {
+            observer.borrow_mut().access(addr, val, self.cycle, kind);
+        }
+    }
+
+    fn read_byte(&mut self, addr: u16, kind: AccessKind) -> u8 {
+        let segment = find_segment(&self.segments, addr);
+        self.wait_cycles += segment.wait_states.read;
+        let val = match segment.unmapped {
+            None => ReadMemoryMut::byte(&mut segment.normal(), segment.device_address(addr)),
+            Some(behavior) => self.unmapped_access(addr, kind, behavior),
+        };
+        self.bus_value = val;
+        if !self.observers.is_empty() {
+            self.notify(addr, val, kind);
+        }
+        val
+    }
+
+    fn unmapped_access(&mut self, addr: u16, kind: AccessKind, behavior: Unmapped) -> u8 {
+        match behavior {
+            Unmapped::OpenBus => self.bus_value,
+            Unmapped::Value(val) => val,
+            Unmapped::Trap => {
+                if self.trap.is_none() {
+                    self.trap = Some(BusTrap {
+                        address: addr,
+                        kind: kind,
+                    });
+                }
+                self.bus_value
             }
         }
-        unreachable!()
     }
+
+    fn segment(&self, addr: u16) -> &MemorySegment {
+        find_segment(&self.segments, addr)
+    }
+}
+
+/// Finds the segment for an address. This is separate from `MemoryMapInner` so that
+/// a bus access can look its segment up once and still update the rest of the map.
+fn find_segment(segments: &[MemorySegment], addr: u16) -> &MemorySegment {
+    for segment in segments {
+        if segment.start <= addr && segment.end_inclusive >= addr {
+            return segment;
+        }
+    }
+    unreachable!()
 }
 
 impl ReadMemory for MemoryMapInner {
     fn byte(&self, addr: u16) -> u8 {
-        self.segment(addr).debug().byte(addr)
+        let segment = self.segment(addr);
+        match segment.unmapped {
+            None => segment.debug().byte(segment.device_address(addr)),
+            Some(Unmapped::Value(val)) => val,
+            Some(_) => self.bus_value,
+        }
     }
 }
 
 impl ReadMemoryMut for MemoryMapInner {
     fn byte(&mut self, addr: u16) -> u8 {
-        ReadMemoryMut::byte(&mut self.segment(addr).normal(), addr)
+        self.read_byte(addr, AccessKind::Read)
     }
 }
 
 impl WriteMemory for MemoryMapInner {
     fn byte(&mut self, addr: u16, val: u8) {
-        WriteMemory::byte(&mut self.segment(addr).normal(), addr, val);
+        let segment = find_segment(&self.segments, addr);
+        self.wait_cycles += segment.wait_states.write;
+        match segment.unmapped {
+            None => WriteMemory::byte(&mut segment.normal(), segment.device_address(addr), val),
+            Some(behavior) => {
+                self.unmapped_access(addr, AccessKind::Write, behavior);
+            }
+        }
+        self.bus_value = val;
+        if !self.observers.is_empty() {
+            self.notify(addr, val, AccessKind::Write);
+        }
     }
 }
 
+/// How the bulk operations on `MemoryMap` write memory
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WriteMode {
+    /// Writes go through the normal write path, the same as `MemoryMap::write`,
+    /// so ROM ignores them and bus observers see them
+    Bus,
+    /// Writes go straight to the devices as from a device programmer, so ROM is
+    /// written too. Bus observers aren't told, no wait states are counted, and
+    /// unmapped ranges are skipped.
+    Programmer,
+}
+
+/// A segment of a built memory map, as returned by `MemoryMap::segments`
+pub struct SegmentInfo<'a> {
+    pub name: &'a str,
+    pub start: u16,
+    pub end_inclusive: u16,
+    pub device: &'a Rc<RefCell<MemoryMappedDevice>>,
+    /// Set if nothing is mapped to the range
+    pub unmapped: Option<Unmapped>,
+}
+
 /// A memory mapping representing a system architecture
 /// for the MOS 6502 processor.
 pub struct MemoryMap {
@@ -410,7 +1128,14 @@ pub struct MemoryMap {
 impl MemoryMap {
     fn new(segments: Vec<MemorySegment>) -> MemoryMap {
         MemoryMap {
-            inner: MemoryMapInner { segments: segments },
+            inner: MemoryMapInner {
+                segments: segments,
+                observers: Vec::new(),
+                cycle: 0,
+                bus_value: 0,
+                wait_cycles: 0,
+                trap: None,
+            },
             working_segment_cache: None,
             null_device_cache: None,
         }
@@ -436,6 +1161,187 @@ impl MemoryMap {
         &mut self.inner
     }
 
+    /// Reads the op-code byte of an instruction. This should get called by the Cpu.
+    pub fn fetch_op_code(&mut self, addr: u16) -> u8 {
+        self.inner.read_byte(addr, AccessKind::OpCodeFetch)
+    }
+
+    /// Reads an operand byte of an instruction. This should get called by the Cpu.
+    pub fn fetch_operand(&mut self, addr: u16) -> u8 {
+        self.inner.read_byte(addr, AccessKind::OperandFetch)
+    }
+
+    /// Sets the cycle count that is reported to bus observers. This should get
+    /// called by the Cpu before each instruction.
+    pub fn set_cycle(&mut self, cycle: usize) {
+        self.inner.cycle = cycle;
+    }
+
+    /// Writes `data` starting at `address`, wrapping around at the end of memory
+    pub fn load(&mut self, address: u16, data: &[u8], mode: WriteMode) {
+        self.write_range(address, data.len(), mode, |offset| data[offset]);
+    }
+
+    /// Sets every byte in an inclusive range to `val`. Like every range taken by the
+    /// bulk operations, a range that ends below its start wraps around past $FFFF.
+    pub fn fill(&mut self, start: u16, end_inclusive: u16, val: u8, mode: WriteMode) {
+        self.write_range(start, range_length(start, end_inclusive), mode, |_| val);
+    }
+
+    /// Copies an inclusive range to `destination`. The ranges may overlap. The source
+    /// is read through the debug path, so reading it doesn't disturb any peripherals.
+    pub fn copy(&mut self, start: u16, end_inclusive: u16, destination: u16, mode: WriteMode) {
+        let data = self.peek_range(start, end_inclusive);
+        self.load(destination, &data, mode);
+    }
+
+    /// Compares an inclusive range with the range of the same length at `other`,
+    /// and returns the addresses in the first range whose bytes differ
+    pub fn compare(&self, start: u16, end_inclusive: u16, other: u16) -> Vec<u16> {
+        let first = self.peek_range(start, end_inclusive);
+        let second = self.peek_range(other, other.wrapping_add(end_inclusive.wrapping_sub(start)));
+        first
+            .iter()
+            .zip(second.iter())
+            .enumerate()
+            .filter(|&(_, (a, b))| a != b)
+            .map(|(offset, _)| start.wrapping_add(offset as u16))
+            .collect()
+    }
+
+    /// Writes an inclusive range to a file as raw bytes, through the debug path
+    pub fn dump<P: AsRef<Path>>(&self, start: u16, end_inclusive: u16, path: P) -> io::Result<()> {
+        File::create(path)?.write_all(&self.peek_range(start, end_inclusive))
+    }
+
+    /// Returns the address of every match of `pattern` that lies entirely within an
+    /// inclusive range. `None` in the pattern matches any byte.
+    pub fn search(&self, start: u16, end_inclusive: u16, pattern: &[Option<u8>]) -> Vec<u16> {
+        let data = self.peek_range(start, end_inclusive);
+        if pattern.is_empty() || pattern.len() > data.len() {
+            return Vec::new();
+        }
+        data.windows(pattern.len())
+            .enumerate()
+            .filter(|&(_, window)| {
+                window
+                    .iter()
+                    .zip(pattern.iter())
+                    .all(|(byte, wanted)| wanted.map_or(true, |wanted| wanted == *byte))
+            })
+            .map(|(offset, _)| start.wrapping_add(offset as u16))
+            .collect()
+    }
+
+    /// Reads an inclusive range through the debug path
+    fn peek_range(&self, start: u16, end_inclusive: u16) -> Vec<u8> {
+        let mut data = vec![0; range_length(start, end_inclusive)];
+        self.inner.dma_slice(&mut data, start);
+        data
+    }
+
+    fn write_range<F: Fn(usize) -> u8>(&mut self, address: u16, length: usize, mode: WriteMode, value: F) {
+        if mode == WriteMode::Bus {
+            for offset in 0..length {
+                WriteMemory::byte(&mut self.inner, address.wrapping_add(offset as u16), value(offset));
+            }
+            return;
+        }
+
+        // Borrow each device once for the whole run of bytes that falls inside it
+        let mut offset = 0;
+        while offset < length {
+            let addr = address.wrapping_add(offset as u16);
+            let segment = self.inner.segment(addr);
+            let run = (length - offset).min(range_length(addr, segment.end_inclusive));
+            if segment.unmapped.is_none() {
+                let mut device = segment.device.borrow_mut();
+                for i in 0..run {
+                    let addr = addr + i as u16;
+                    device.program_byte(segment.device_address(addr), value(offset + i));
+                }
+            }
+            offset += run;
+        }
+    }
+
+    /// Resets every device. A cold reset also forgets the last value on the bus and
+    /// any trap or wait state cycles that haven't been taken yet. This should get
+    /// called by the Cpu.
+    pub fn reset(&mut self, kind: ResetKind) {
+        for segment in &self.inner.segments {
+            segment.device.borrow_mut().reset(kind);
+        }
+        if kind == ResetKind::Cold {
+            self.inner.bus_value = 0;
+            self.inner.wait_cycles = 0;
+            self.inner.trap = None;
+        }
+    }
+
+    /// Returns the device added with `MemoryMapBuilder::device` under the given name,
+    /// if there is one and it's a `T`. Banked windows and overlays can also be found
+    /// as `BankedDevice` and `OverlayDevice`.
+    pub fn device<T: MemoryMappedDevice + 'static>(&self, name: &str) -> Option<Rc<RefCell<T>>> {
+        self.inner
+            .segments
+            .iter()
+            .filter(|segment| *segment.name == name)
+            .filter_map(|segment| segment.typed_device.clone())
+            .filter_map(|device| device.downcast::<RefCell<T>>().ok())
+            .next()
+    }
+
+    /// Returns every segment in address order, including unmapped ranges
+    pub fn segments<'a>(&'a self) -> Box<Iterator<Item = SegmentInfo<'a>> + 'a> {
+        Box::new(self.inner.segments.iter().map(|segment| {
+            SegmentInfo {
+                name: &segment.name,
+                start: segment.start,
+                end_inclusive: segment.end_inclusive,
+                device: &segment.device,
+                unmapped: segment.unmapped,
+            }
+        }))
+    }
+
+    /// Returns the switch for the `banked` window or `overlay` with the given name
+    pub fn bank_switch(&self, name: &str) -> Option<BankSwitch> {
+        self.inner
+            .segments
+            .iter()
+            .find(|segment| segment.switch.is_some() && *segment.name == name)
+            .and_then(|segment| segment.switch.clone())
+    }
+
+    /// Returns and resets the wait state cycles from accesses made since the last
+    /// call. This should get called by the Cpu after each instruction; accesses made
+    /// by interrupts are counted with the instruction after them.
+    pub fn take_wait_cycles(&mut self) -> usize {
+        ::std::mem::replace(&mut self.inner.wait_cycles, 0)
+    }
+
+    /// Returns the last value that was read or written
+    pub fn bus_value(&self) -> u8 {
+        self.inner.bus_value
+    }
+
+    /// Returns and clears the first access to an `Unmapped::Trap` range since the
+    /// last call. Run loops should check this after each step to stop on a trap.
+    pub fn take_trap(&mut self) -> Option<BusTrap> {
+        self.inner.trap.take()
+    }
+
+    /// Registers an observer to be told about every read, write and instruction fetch
+    pub fn add_bus_observer(&mut self, observer: Rc<RefCell<BusObserver>>) {
+        self.inner.observers.push(observer);
+    }
+
+    /// Removes all of the bus observers
+    pub fn clear_bus_observers(&mut self) {
+        self.inner.observers.clear();
+    }
+
     /// Updates all attached peripherals. This should get called by the Cpu.
     pub fn step(&mut self) -> Option<InterruptType> {
         if self.working_segment_cache.is_none() {
@@ -478,6 +1384,63 @@ impl MemoryMap {
     }
 }
 
+impl Bus for MemoryMap {
+    fn read(&mut self, addr: u16) -> u8 {
+        ReadMemoryMut::byte(&mut self.inner, addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        WriteMemory::byte(&mut self.inner, addr, val)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        ReadMemory::byte(&self.inner, addr)
+    }
+
+    fn fetch_op_code(&mut self, addr: u16) -> u8 {
+        MemoryMap::fetch_op_code(self, addr)
+    }
+
+    fn fetch_operand(&mut self, addr: u16) -> u8 {
+        MemoryMap::fetch_operand(self, addr)
+    }
+
+    fn set_cycle(&mut self, cycle: usize) {
+        MemoryMap::set_cycle(self, cycle)
+    }
+
+    fn take_wait_cycles(&mut self) -> usize {
+        MemoryMap::take_wait_cycles(self)
+    }
+
+    fn step(&mut self) -> Option<InterruptType> {
+        MemoryMap::step(self)
+    }
+
+    fn reset(&mut self, kind: ResetKind) {
+        MemoryMap::reset(self, kind)
+    }
+}
+
+/// Returns the length of an inclusive range, which wraps around past $FFFF if it ends below its start
+fn range_length(start: u16, end_inclusive: u16) -> usize {
+    end_inclusive.wrapping_sub(start) as usize + 1
+}
+
+/// Lists each device's range and name, one per line
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for segment in self.segments() {
+            writeln!(
+                f,
+                "${:04X}-${:04X}  {}",
+                segment.start, segment.end_inclusive, segment.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -499,4 +1462,235 @@ mod tests {
 
         assert_eq!(None, memory.step());
     }
+
+    #[test]
+    fn test_try_build() {
+        let memory = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .peripheral(0xD000, 0xD0FF, Rc::new(RefCell::new(NullDevice::new())))
+            .named("video")
+            .ram(0xE100, 0xE000)
+            .rom(0xF000, 0xFFFF, vec![0xEA; 0x100])
+            .try_build();
+        let problems = memory.err().unwrap().problems;
+        assert_eq!(
+            vec![
+                MemoryMapProblem::InvalidRange {
+                    name: "RAM".into(),
+                    start: 0xE100,
+                    end_inclusive: 0xE000,
+                },
+                MemoryMapProblem::SizeMismatch {
+                    name: "ROM".into(),
+                    start: 0xF000,
+                    end_inclusive: 0xFFFF,
+                    expected: 0x1000,
+                    actual: 0x100,
+                },
+                MemoryMapProblem::Overlap {
+                    first: "RAM".into(),
+                    second: "video".into(),
+                    start: 0xD000,
+                    end_inclusive: 0xD0FF,
+                },
+                MemoryMapProblem::Gap {
+                    start: 0xE000,
+                    end_inclusive: 0xEFFF,
+                },
+            ],
+            problems
+        );
+
+        let memory = MemoryMap::builder()
+            .ram(0x0000, 0xBFFF)
+            .rom(0xC000, 0xFFFF, vec![0; 0x4000])
+            .named("BASIC")
+            .try_build()
+            .unwrap();
+        assert_eq!("$0000-$BFFF  RAM\n$C000-$FFFF  BASIC\n", memory.to_string());
+    }
+
+    #[test]
+    fn test_unmapped() {
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0x7FFF)
+            .unmapped(0x8000, 0x8FFF, Unmapped::OpenBus)
+            .unmapped(0x9000, 0x9FFF, Unmapped::Value(0xFF))
+            .rom(0xF000, 0xFFFF, vec![0xEA; 0x1000])
+            .unmapped_gaps(Unmapped::Trap)
+            .build();
+        assert!(memory.to_string().contains("$A000-$EFFF  unmapped (trap)"));
+
+        memory.write().byte(0x0010, 0x42);
+        assert_eq!(0x42, memory.read().byte(0x8000));
+        assert_eq!(0xEA, memory.read().byte(0xF000));
+        assert_eq!(0xEA, memory.read().byte(0x8123));
+        assert_eq!(0xFF, memory.read().byte(0x9000));
+        assert_eq!(0xFF, memory.bus_value());
+        assert_eq!(None, memory.take_trap());
+
+        memory.write().byte(0xB000, 0x01);
+        memory.read().byte(0xC000);
+        assert_eq!(
+            Some(BusTrap {
+                address: 0xB000,
+                kind: AccessKind::Write,
+            }),
+            memory.take_trap()
+        );
+        assert_eq!(None, memory.take_trap());
+    }
+
+    #[test]
+    fn test_wait_states() {
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .wait_states(0)
+            .rom(0xE000, 0xFFFF, vec![0xEA; 0x2000])
+            .wait_states(2)
+            .write_wait_states(5)
+            .build();
+        memory.write().byte(0x0010, 1);
+        memory.read().byte(0x0010);
+        assert_eq!(0, memory.take_wait_cycles());
+        memory.read().byte(0xE000);
+        memory.write().byte(0xE000, 0);
+        assert_eq!(7, memory.take_wait_cycles());
+        assert_eq!(0, memory.take_wait_cycles());
+    }
+
+    struct Registers {
+        accesses: Vec<u16>,
+    }
+
+    impl MemoryMappedDevice for Registers {
+        fn read_byte(&self, addr: u16) -> u8 {
+            addr as u8
+        }
+
+        fn read_byte_mut(&mut self, addr: u16) -> u8 {
+            self.accesses.push(addr);
+            addr as u8
+        }
+
+        fn write_byte(&mut self, addr: u16, _val: u8) {
+            self.accesses.push(addr);
+        }
+
+        fn requires_step(&self) -> bool {
+            false
+        }
+
+        fn step(&mut self, _memory: &mut MemoryMap) -> Option<InterruptType> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_bulk_operations() {
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0xDFFF)
+            .unmapped(0xE000, 0xEFFF, Unmapped::Value(0xFF))
+            .rom(0xF000, 0xFFFF, vec![0; 0x1000])
+            .build();
+
+        memory.load(0x0200, &[0xA9, 0x01, 0x8D, 0x00, 0x10], WriteMode::Bus);
+        memory.load(0xFFFC, &[0x00, 0x02], WriteMode::Bus);
+        assert_eq!(0x0000, memory.debug_read().word(0xFFFC));
+        memory.load(0xFFFC, &[0x00, 0x02, 0xEA, 0xEA, 0x60], WriteMode::Programmer);
+        assert_eq!(0x0200, memory.debug_read().word(0xFFFC));
+        assert_eq!(0x60, memory.debug_read().byte(0x0000));
+
+        memory.fill(0x1000, 0x10FF, 0x55, WriteMode::Bus);
+        memory.copy(0x0200, 0x0204, 0x1080, WriteMode::Bus);
+        memory.copy(0x1080, 0x1084, 0x1081, WriteMode::Bus);
+        assert!(memory.compare(0x1081, 0x1085, 0x0200).is_empty());
+        assert_eq!(vec![0x1086], memory.compare(0x1081, 0x1086, 0x0200));
+        memory.fill(0xDFF0, 0xF00F, 0x42, WriteMode::Programmer);
+        assert_eq!(0xFF, memory.debug_read().byte(0xE000));
+        assert_eq!(0x42, memory.debug_read().byte(0xF00F));
+
+        // STA absolute with any operand
+        let pattern = [Some(0x8D), None, Some(0x10)];
+        assert_eq!(vec![0x0202], memory.search(0x0000, 0x0FFF, &pattern));
+        assert_eq!(vec![0x0202, 0x1083], memory.search(0x0000, 0xFFFF, &pattern));
+        assert!(memory.search(0x0202, 0x0203, &pattern).is_empty());
+
+        // Ranges that run past $FFFF wrap around to $0000
+        memory.fill(0x0000, 0x00FF, 0x00, WriteMode::Bus);
+        memory.load(0x0010, &[0x8D, 0x34, 0x10], WriteMode::Bus);
+        assert_eq!(vec![0x0000, 0x0001], memory.compare(0x0000, 0x0003, 0xFFFE));
+        assert_eq!(vec![0xFFFE, 0xFFFF, 0x0000], memory.compare(0xFFFE, 0x0001, 0x0200));
+        assert_eq!(
+            vec![0x0010, 0x0011, 0x0012, 0x007D, 0x007E, 0x007F, 0x0090, 0x0091, 0x0092],
+            memory.compare(0x0000, 0x00FF, 0xFF80)
+        );
+        memory.fill(0xFFFE, 0x0001, 0x8D, WriteMode::Programmer);
+        assert_eq!(
+            vec![0xFFFE, 0xFFFF, 0x0000, 0x0001, 0x0010],
+            memory.search(0xFFF0, 0x0020, &pattern[..1])
+        );
+    }
+
+    #[test]
+    fn test_device_lookup() {
+        let registers = Rc::new(RefCell::new(Registers { accesses: Vec::new() }));
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0xCFFF)
+            .device("regs", 0xD000, 0xD0FF, registers)
+            .unmapped(0xD100, 0xFFFF, Unmapped::OpenBus)
+            .build();
+        memory.write().byte(0xD010, 1);
+
+        assert_eq!(vec![0xD010], memory.device::<Registers>("regs").unwrap().borrow().accesses);
+        assert!(memory.device::<RAMDevice>("regs").is_none());
+        assert!(memory.device::<Registers>("RAM").is_none());
+
+        let segments: Vec<(&str, u16, u16, bool)> = memory
+            .segments()
+            .map(|segment| (segment.name, segment.start, segment.end_inclusive, segment.unmapped.is_some()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("RAM", 0x0000, 0xCFFF, false),
+                ("regs", 0xD000, 0xD0FF, false),
+                ("open bus", 0xD100, 0xFFFF, true),
+            ],
+            segments
+        );
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let registers = Rc::new(RefCell::new(Registers { accesses: Vec::new() }));
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000, 0xCFFF)
+            .peripheral(0xD000, 0xD0FF, registers.clone())
+            .relative()
+            .mirrored(3)
+            .ram(0xD100, 0xDFFF)
+            .address_mask(0x00F0)
+            .ram(0xE000, 0xFFFF)
+            .build();
+
+        assert_eq!(0x00, memory.read().byte(0xD000));
+        assert_eq!(0x02, memory.read().byte(0xD0FE));
+        memory.write().byte(0xD004, 0x01);
+        assert_eq!(vec![0, 2, 1], registers.borrow().accesses);
+
+        memory.write().byte(0xD1FF, 0x42);
+        assert_eq!(0x42, memory.debug_read().byte(0xD1FF));
+        assert_eq!(0x42, memory.debug_read().byte(0xD2F0));
+        assert_eq!(0x42, memory.debug_read().byte(0xDFF3));
+        assert_eq!(0x00, memory.debug_read().byte(0xD1EF));
+
+        let problems = MemoryMap::builder()
+            .ram(0x0000, 0xFFFF)
+            .mirrored(0)
+            .try_build()
+            .err()
+            .unwrap()
+            .problems;
+        assert_eq!(vec![MemoryMapProblem::InvalidMirror { name: "RAM".into() }], problems);
+    }
 }
@@ -7,14 +7,26 @@
 // copied, modified, or distributed except according to those terms.
 //
 
+mod banking;
+mod bus;
+mod call_stack;
 mod cpu;
+mod image;
 mod instruction;
 mod memory;
 mod opcode;
+mod pins;
+mod provenance;
 mod register_status;
 mod registers;
 
-pub use self::cpu::{Cpu, InterruptType};
+pub use self::banking::{BankRegister, BankSwitch, BankedDevice, OverlayDevice};
+pub use self::bus::{Bus, BusPeek};
+pub use self::call_stack::{CallFrame, FrameKind, ReturnMismatch};
+pub use self::cpu::{Cpu, InstructionObserver, InterruptType, ResetKind, StepTarget};
+pub use self::image::{Image, ImageError, ImageFormat, ImageSegment};
+pub use self::pins::Pin;
+pub use self::provenance::{ProvenanceMap, WriteRecord};
 pub use self::registers::Registers;
 pub use self::register_status::RegisterStatus;
 pub use self::memory::*;
@@ -7,7 +7,7 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use emulator::memory::MemoryMap;
+use emulator::bus::Bus;
 use emulator::registers::Registers;
 
 pub use hassel_lib6502::{Op, OpAddressMode, OpClass, OpCode, OpParam};
@@ -17,8 +17,8 @@ const ADDR_PAGE_MASK: u16 = 0xFF00;
 pub trait CpuAddressMode {
     fn same_page(addr1: u16, addr2: u16) -> bool;
     fn offset(addr: u16, offset: u8) -> (bool, u16);
-    fn address(&self, param: &OpParam, reg: &Registers, memory: &mut MemoryMap) -> (bool, u16);
-    fn address_and_read_byte(&self, param: &OpParam, reg: &Registers, memory: &mut MemoryMap) -> (bool, u8);
+    fn address(&self, param: &OpParam, reg: &Registers, memory: &mut Bus) -> (bool, u16);
+    fn address_and_read_byte(&self, param: &OpParam, reg: &Registers, memory: &mut Bus) -> (bool, u8);
 }
 
 impl CpuAddressMode for OpAddressMode {
@@ -34,7 +34,7 @@ impl CpuAddressMode for OpAddressMode {
         (different_page, result)
     }
 
-    fn address(&self, param: &OpParam, reg: &Registers, memory: &mut MemoryMap) -> (bool, u16) {
+    fn address(&self, param: &OpParam, reg: &Registers, memory: &mut Bus) -> (bool, u16) {
         use emulator::opcode::OpAddressMode::*;
         let addr = match *self {
             Implied => 0,
@@ -46,12 +46,10 @@ impl CpuAddressMode for OpAddressMode {
             ZeroPageOffsetX => param.as_u8().wrapping_add(reg.x) as u16,
             ZeroPageOffsetY => param.as_u8().wrapping_add(reg.y) as u16,
             PCOffset => unreachable!(),
-            Indirect => memory.read().word(param.as_u16()),
-            PreIndirectX => memory
-                .read()
-                .word_zero_page(param.as_u8().wrapping_add(reg.x)),
+            Indirect => memory.read_word(param.as_u16()),
+            PreIndirectX => memory.read_word_zero_page(param.as_u8().wrapping_add(reg.x)),
             PostIndirectY => {
-                let addr = memory.read().word_zero_page(param.as_u8());
+                let addr = memory.read_word_zero_page(param.as_u8());
                 return OpAddressMode::offset(addr, reg.y);
             }
         };
@@ -59,28 +57,28 @@ impl CpuAddressMode for OpAddressMode {
         (false, addr)
     }
 
-    fn address_and_read_byte(&self, param: &OpParam, reg: &Registers, memory: &mut MemoryMap) -> (bool, u8) {
+    fn address_and_read_byte(&self, param: &OpParam, reg: &Registers, memory: &mut Bus) -> (bool, u8) {
         use emulator::opcode::OpAddressMode::*;
         let (different_page, addr) = self.address(param, reg, memory);
         match *self {
             Implied => (false, reg.a),
             PCOffset => unreachable!(),
             Immediate => (false, param.as_u8()),
-            AbsoluteOffsetX | AbsoluteOffsetY | PostIndirectY => (different_page, memory.read().byte(addr)),
-            _ => (false, memory.read().byte(addr)),
+            AbsoluteOffsetX | AbsoluteOffsetY | PostIndirectY => (different_page, memory.read(addr)),
+            _ => (false, memory.read(addr)),
         }
     }
 }
 
-pub fn decode_op(memory: &mut MemoryMap, reg_pc: u16) -> Op {
-    let op_code_value = memory.read().byte(reg_pc);
+pub fn decode_op(memory: &mut Bus, reg_pc: u16) -> Op {
+    let op_code_value = memory.fetch_op_code(reg_pc);
     let op_code = OpCode::from_value(op_code_value).expect("invalid opcode");
     let op_param = match op_code.len {
         1 => OpParam::None,
-        2 => OpParam::Byte(memory.read().byte(reg_pc.wrapping_add(1))),
+        2 => OpParam::Byte(memory.fetch_operand(reg_pc.wrapping_add(1))),
         3 => {
-            let lo = memory.read().byte(reg_pc.wrapping_add(1));
-            let hi = memory.read().byte(reg_pc.wrapping_add(2));
+            let lo = memory.fetch_operand(reg_pc.wrapping_add(1));
+            let hi = memory.fetch_operand(reg_pc.wrapping_add(2));
             OpParam::Word(((hi as u16) << 8) | (lo as u16))
         }
         _ => panic!("unexpected op-code length"),
@@ -0,0 +1,98 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// One of the CPU's input pins, which is high until something pulls it low.
+/// Clones share the pin, so a device can hold one and drive it while the
+/// CPU runs, the same as the host can.
+#[derive(Clone, Debug)]
+pub struct Pin {
+    low: Rc<Cell<bool>>,
+    /// Set when the pin goes from high to low, until the CPU sees it
+    fell: Rc<Cell<bool>>,
+}
+
+impl Pin {
+    pub fn new() -> Pin {
+        Pin {
+            low: Rc::new(Cell::new(false)),
+            fell: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Pulls the pin low
+    pub fn pull_low(&self) {
+        if !self.low.get() {
+            self.fell.set(true);
+        }
+        self.low.set(true);
+    }
+
+    /// Lets the pin go high again
+    pub fn release(&self) {
+        self.low.set(false);
+    }
+
+    /// Returns true if the pin is being pulled low
+    pub fn is_low(&self) -> bool {
+        self.low.get()
+    }
+
+    /// Returns true if the pin went low since the last call. This is for the Cpu,
+    /// so that a pulse between two instructions isn't missed.
+    pub fn take_falling_edge(&self) -> bool {
+        self.fell.replace(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_support::load_program;
+
+    #[test]
+    fn test_pins() {
+        let (mut cpu, _) = load_program(
+            "start: INX
+                    BVC start
+                    INY
+             halt:  JMP halt
+             .reset start",
+        );
+
+        // RDY holds the CPU between instructions, one cycle per step
+        cpu.rdy().pull_low();
+        for _ in 0..3 {
+            assert_eq!(1, cpu.step());
+        }
+        assert_eq!((0x0200, 0, 3), (cpu.registers().pc, cpu.registers().x, cpu.cycles()));
+        cpu.rdy().release();
+        cpu.step();
+        assert_eq!((0x0201, 1), (cpu.registers().pc, cpu.registers().x));
+
+        // SO sets overflow once per falling edge, however short the pulse
+        cpu.so().pull_low();
+        cpu.so().release();
+        cpu.step();
+        cpu.step();
+        assert_eq!((0x0204, 1), (cpu.registers().pc, cpu.registers().y));
+        assert!(cpu.registers().status.overflow());
+
+        // RES holds the CPU until it's released, then runs the reset sequence
+        let sp = cpu.registers().sp;
+        cpu.res().pull_low();
+        cpu.step();
+        cpu.step();
+        assert_eq!(0x0204, cpu.registers().pc);
+        cpu.res().release();
+        assert_eq!(7, cpu.step());
+        assert_eq!((0x0200, sp.wrapping_sub(3)), (cpu.registers().pc, cpu.registers().sp));
+    }
+}
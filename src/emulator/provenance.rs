@@ -0,0 +1,110 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use emulator::cpu::InterruptType;
+
+/// The most recent write the CPU made to an address
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WriteRecord {
+    /// Address of the instruction that made the write, or the address that was
+    /// about to execute when an interrupt pushed the return address and status
+    pub pc: u16,
+    /// Cycle count at the start of the instruction or interrupt
+    pub cycle: usize,
+    /// Value of the byte before the write
+    pub previous: u8,
+    /// Value that was written
+    pub value: u8,
+    /// Set if the write was a hardware interrupt pushing onto the stack
+    pub interrupt: Option<InterruptType>,
+}
+
+/// Remembers which instruction last wrote every address, for tracking down
+/// corrupted variables. Only writes made by the CPU are recorded, so loading
+/// memory from outside (such as from a monitor) leaves the records alone.
+#[derive(Clone, Debug)]
+pub struct ProvenanceMap {
+    records: Vec<Option<WriteRecord>>,
+}
+
+impl ProvenanceMap {
+    pub fn new() -> ProvenanceMap {
+        ProvenanceMap {
+            records: vec![None; 0x10000],
+        }
+    }
+
+    /// Returns the last write to the given address, if it was ever written
+    pub fn last_write(&self, address: u16) -> Option<WriteRecord> {
+        self.records[address as usize]
+    }
+
+    /// Returns the last writes to an inclusive range, skipping addresses that were never written
+    pub fn writes_in(&self, start: u16, end_inclusive: u16) -> Vec<(u16, WriteRecord)> {
+        (start as usize..end_inclusive as usize + 1)
+            .filter_map(|address| self.records[address].map(|record| (address as u16, record)))
+            .collect()
+    }
+
+    /// Forgets every recorded write
+    pub fn clear(&mut self) {
+        for record in self.records.iter_mut() {
+            *record = None;
+        }
+    }
+
+    pub fn record(&mut self, address: u16, record: WriteRecord) {
+        self.records[address as usize] = Some(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::load_program;
+
+    #[test]
+    fn test_last_writes() {
+        let (mut cpu, _) = load_program(
+            "start: LDA #$11
+                    STA $10
+                    INC $10
+                    CLI
+             halt:  JMP halt
+             irq:   RTI
+             .reset start
+             .irq irq",
+        );
+        cpu.enable_provenance();
+        cpu.registers_mut().sp = 0xFF;
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert!(cpu.request_interrupt());
+
+        let provenance = cpu.provenance().unwrap();
+        assert_eq!(
+            Some(WriteRecord {
+                pc: 0x0204,
+                cycle: 5,
+                previous: 0x11,
+                value: 0x12,
+                interrupt: None,
+            }),
+            provenance.last_write(0x0010)
+        );
+        assert_eq!(None, provenance.last_write(0x0011));
+
+        let pushes = provenance.writes_in(0x01FD, 0x01FF);
+        assert_eq!(3, pushes.len());
+        assert_eq!((0x01FF, 0x02), (pushes[2].0, pushes[2].1.value));
+        assert_eq!(0x0207, pushes[2].1.pc);
+        assert_eq!(Some(InterruptType::Maskable), pushes[2].1.interrupt);
+    }
+}
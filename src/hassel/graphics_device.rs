@@ -7,7 +7,7 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use emulator::{InterruptType, MemoryMap, MemoryMappedDevice};
+use emulator::{InterruptType, MemoryMap, MemoryMappedDevice, ResetKind};
 
 const CHAR_WIDTH: usize = 9;
 const CHAR_HEIGHT: usize = 16;
@@ -56,6 +56,7 @@ enum IOState {
 
 pub struct GraphicsDevice {
     frame_buffer: Vec<u32>,
+    text: Vec<u8>,
     next_command: IOState,
     cursor_x: u8,
     cursor_y: u8,
@@ -67,6 +68,7 @@ impl GraphicsDevice {
 
         let bus = GraphicsDevice {
             frame_buffer: frame_buffer,
+            text: vec![0; SCREEN_WIDTH_CHARS * SCREEN_HEIGHT_CHARS],
             next_command: IOState::Listening,
             cursor_x: 0,
             cursor_y: 0,
@@ -78,6 +80,27 @@ impl GraphicsDevice {
         &self.frame_buffer
     }
 
+    /// Returns the characters currently on screen as text, one line per row.
+    /// Trailing blanks are trimmed and non-printable characters are shown as `.`
+    pub fn screen_text(&self) -> String {
+        let mut result = String::new();
+        for row in self.text.chunks(SCREEN_WIDTH_CHARS) {
+            let mut line: String = row.iter()
+                .map(|&chr| match chr {
+                    0 => ' ',
+                    _ if chr >= 0x20 && chr < 0x7F => chr as char,
+                    _ => '.',
+                })
+                .collect();
+            while line.ends_with(' ') {
+                line.pop();
+            }
+            result.push_str(&line);
+            result.push('\n');
+        }
+        result
+    }
+
     fn put_chr(&mut self, code_point: u8) {
         if code_point == '\n' as u8 {
             self.cursor_x = 0;
@@ -99,6 +122,10 @@ impl GraphicsDevice {
     }
 
     fn blit_chr(&mut self, x_chr: u8, y_chr: u8, code_point: u8) {
+        if (x_chr as usize) < SCREEN_WIDTH_CHARS && (y_chr as usize) < SCREEN_HEIGHT_CHARS {
+            self.text[y_chr as usize * SCREEN_WIDTH_CHARS + x_chr as usize] = code_point;
+        }
+
         let src_start_x = (code_point as usize % FONT_CHARS_PER_ROW) * CHAR_WIDTH;
         let src_start_y = (code_point as usize / FONT_CHARS_PER_ROW) * CHAR_HEIGHT;
 
@@ -188,6 +215,9 @@ impl MemoryMappedDevice for GraphicsDevice {
                 for i in 0..self.frame_buffer.len() {
                     self.frame_buffer[i] = DEFAULT_BG_COLOR;
                 }
+                for chr in self.text.iter_mut() {
+                    *chr = 0;
+                }
                 self.next_command = IOState::Listening;
             }
             IOState::SetMode { mode } => {
@@ -230,6 +260,14 @@ impl MemoryMappedDevice for GraphicsDevice {
         }
         None
     }
+
+    /// A warm reset abandons any half-received command, and a cold reset also clears the screen
+    fn reset(&mut self, kind: ResetKind) {
+        match kind {
+            ResetKind::Cold => *self = GraphicsDevice::new(),
+            ResetKind::Warm => self.next_command = IOState::Listening,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -260,4 +298,22 @@ mod tests {
             bus.write_byte(address_doesnt_matter, 'h' as u8);
         }
     }
+
+    #[test]
+    fn test_reset() {
+        let mut bus = GraphicsDevice::new();
+        bus.text[0] = 'h' as u8;
+        bus.cursor_x = 3;
+        bus.write_byte(0, CMD_SET_POSITION);
+
+        bus.reset(ResetKind::Warm);
+        match bus.next_command {
+            IOState::Listening => {}
+            _ => panic!("a warm reset should abandon the command"),
+        }
+        assert_eq!(('h' as u8, 3), (bus.text[0], bus.cursor_x));
+
+        bus.reset(ResetKind::Cold);
+        assert_eq!((0, 0), (bus.text[0], bus.cursor_x));
+    }
 }
@@ -7,7 +7,7 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use emulator::{InterruptType, MemoryMap, MemoryMappedDevice};
+use emulator::{InterruptType, MemoryMap, MemoryMappedDevice, ResetKind};
 use hassel::key::Key;
 
 const KEY_DOWN_INTERRUPT: u8 = 0x01;
@@ -75,4 +75,9 @@ impl MemoryMappedDevice for IODevice {
             None
         }
     }
+
+    fn reset(&mut self, _kind: ResetKind) {
+        self.response_queue.clear();
+        self.last_interrupt_size = 0;
+    }
 }
@@ -10,7 +10,7 @@
 //! Holds all of the Hasseldorf Computer specific code. This can be turned off by
 //! removing the "hassel_arch" feature when including this crate.
 
-use emulator::{MemoryMap, MemoryMappedDevice};
+use emulator::{MemoryMap, RamFill};
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -18,22 +18,24 @@ use std::rc::Rc;
 mod graphics_device;
 mod io_device;
 mod key;
-mod peripherals;
 
 pub use self::key::Key;
 pub use self::graphics_device::{GraphicsDevice, SCREEN_HEIGHT_PIXELS, SCREEN_WIDTH_PIXELS};
 pub use self::io_device::IODevice;
-use self::peripherals::Peripherals;
 
 pub const REQUIRED_ROM_SIZE: usize = 0x2000;
 
 pub struct HasselSystemBuilder {
     rom: Option<Vec<u8>>,
+    ram_fill: RamFill,
 }
 
 impl HasselSystemBuilder {
     pub fn new() -> HasselSystemBuilder {
-        HasselSystemBuilder { rom: None }
+        HasselSystemBuilder {
+            rom: None,
+            ram_fill: RamFill::Zero,
+        }
     }
 
     pub fn rom(mut self, rom: Vec<u8>) -> Self {
@@ -41,6 +43,12 @@ impl HasselSystemBuilder {
         self
     }
 
+    /// Sets how the system RAM is filled at power on. The default is zeros.
+    pub fn ram_fill(mut self, fill: RamFill) -> Self {
+        self.ram_fill = fill;
+        self
+    }
+
     pub fn build(
         self,
     ) -> (
@@ -53,17 +61,29 @@ impl HasselSystemBuilder {
         let graphics = Rc::new(RefCell::new(graphics_device::GraphicsDevice::new()));
         let io = Rc::new(RefCell::new(io_device::IODevice::new()));
 
-        let peripherals: Rc<RefCell<MemoryMappedDevice>> = Rc::new(RefCell::new(Peripherals::new(
-            Rc::clone(&graphics),
-            Rc::clone(&io),
-        )));
-
         let memory_map = MemoryMap::builder()
+            .ram_fill(self.ram_fill)
             .ram(0x0000, 0xDFFD)
-            .peripheral(0xDFFE, 0xDFFF, peripherals)
+            .device("gfx", 0xDFFE, 0xDFFE, Rc::clone(&graphics))
+            .device("io", 0xDFFF, 0xDFFF, Rc::clone(&io))
             .rom(0xE000, 0xFFFF, self.rom.unwrap())
             .build();
 
         (memory_map, graphics, io)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_devices() {
+        let (memory_map, graphics, io) = HasselSystemBuilder::new().rom(vec![0; REQUIRED_ROM_SIZE]).build();
+        let found_graphics = memory_map.device::<GraphicsDevice>("gfx").unwrap();
+        assert!(Rc::ptr_eq(&graphics, &found_graphics));
+        let found_io = memory_map.device::<IODevice>("io").unwrap();
+        assert!(Rc::ptr_eq(&io, &found_io));
+        assert!(memory_map.device::<IODevice>("gfx").is_none());
+    }
+}
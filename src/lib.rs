@@ -44,10 +44,13 @@
 //!
 //! To create your own memory-mapped hardware peripheral, you just need to
 //! implement the MemoryMappedDevice trait on a struct, and then add it to
-//! the memory map using the MemoryMapBuilder.
+//! the memory map using the MemoryMapBuilder. Systems that don't fit a memory
+//! map can implement the Bus trait instead, which the Cpu is generic over.
 
 extern crate hassel_lib6502;
 
+pub mod assembler;
+pub mod debug;
 pub mod emulator;
 
 #[cfg(feature = "hassel_arch")]
@@ -57,5 +60,12 @@ extern crate enum_primitive;
 #[cfg(feature = "hassel_arch")]
 pub mod hassel;
 
+#[cfg(feature = "dap")]
+#[macro_use]
+extern crate serde_json;
+
+#[cfg(test)]
+mod test_support;
+
 pub use emulator::Cpu;
-pub use emulator::{MemoryMap, MemoryMappedDevice};
+pub use emulator::{Bus, MemoryMap, MemoryMappedDevice};
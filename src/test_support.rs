@@ -0,0 +1,34 @@
+//
+// Copyright 2017 hassel_emu Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! Fixtures shared by the tests that run small assembled programs
+
+use assembler::{Assembler, Program};
+use emulator::{Bus, Cpu, MemoryMap};
+
+/// Assembles `source` at $0200
+pub fn assemble(source: &str) -> Program {
+    Assembler::new(0x0200).source(source).assemble().unwrap()
+}
+
+/// Assembles `source` at $0200 and loads it into a Cpu with 64K of RAM
+pub fn load_program(source: &str) -> (Cpu, Program) {
+    let program = assemble(source);
+    let mut memory = MemoryMap::builder().ram(0x0000, 0xFFFF).build();
+    program.image().write_to(&mut memory);
+    (Cpu::new(memory), program)
+}
+
+/// Steps the Cpu until it reaches the given label
+pub fn run_to_label<B: Bus>(cpu: &mut Cpu<B>, program: &Program, label: &str) {
+    let address = program.symbols().address_of(label).unwrap();
+    while cpu.registers().pc != address {
+        cpu.step();
+    }
+}